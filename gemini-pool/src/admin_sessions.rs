@@ -0,0 +1,120 @@
+//! Admin sessions: token lifetimes, refresh tokens and session cookies.
+//!
+//! A login creates a row in `admin_sessions` and hands out a short-lived JWT
+//! access token plus an opaque refresh token. Only a hash of the refresh token
+//! is stored. Access tokens name their session, so revoking the session ends
+//! them too. Both tokens can be delivered as HttpOnly cookies instead of in
+//! the response body, which keeps them out of reach of page scripts.
+
+use axum::http::{HeaderMap, HeaderValue};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::time::Duration;
+
+/// Cookie holding the access token. Sent with every admin API request.
+pub const ACCESS_COOKIE: &str = "gp_admin_access";
+/// Cookie holding the refresh token. Only sent to the auth endpoints.
+pub const REFRESH_COOKIE: &str = "gp_admin_refresh";
+
+const ACCESS_COOKIE_PATH: &str = "/admin";
+const REFRESH_COOKIE_PATH: &str = "/admin/api/auth";
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// Lifetime of access tokens.
+    pub access_ttl: Duration,
+    /// Lifetime of a session; refreshing does not extend it.
+    pub refresh_ttl: Duration,
+    /// Adds `Secure` to session cookies. Turn off only for plain-HTTP setups.
+    pub secure_cookies: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            access_ttl: Duration::from_secs(15 * 60),
+            refresh_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            secure_cookies: true,
+        }
+    }
+}
+
+impl SessionConfig {
+    /// Reads `ADMIN_ACCESS_TOKEN_TTL_SECS`, `ADMIN_REFRESH_TOKEN_TTL_SECS` and
+    /// `ADMIN_COOKIE_SECURE`, keeping the defaults for anything unset.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let read_secs = |name: &str| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+        };
+
+        Self {
+            access_ttl: read_secs("ADMIN_ACCESS_TOKEN_TTL_SECS").unwrap_or(default.access_ttl),
+            refresh_ttl: read_secs("ADMIN_REFRESH_TOKEN_TTL_SECS").unwrap_or(default.refresh_ttl),
+            secure_cookies: env::var("ADMIN_COOKIE_SECURE")
+                .map(|v| !matches!(v.trim(), "false" | "0"))
+                .unwrap_or(default.secure_cookies),
+        }
+    }
+
+    /// `Set-Cookie` values that store both tokens.
+    pub fn session_cookies(&self, access_token: &str, refresh_token: &str) -> [HeaderValue; 2] {
+        [
+            self.cookie(ACCESS_COOKIE, access_token, ACCESS_COOKIE_PATH, self.access_ttl.as_secs()),
+            self.cookie(REFRESH_COOKIE, refresh_token, REFRESH_COOKIE_PATH, self.refresh_ttl.as_secs()),
+        ]
+    }
+
+    /// `Set-Cookie` values that delete both tokens.
+    pub fn clear_cookies(&self) -> [HeaderValue; 2] {
+        [
+            self.cookie(ACCESS_COOKIE, "", ACCESS_COOKIE_PATH, 0),
+            self.cookie(REFRESH_COOKIE, "", REFRESH_COOKIE_PATH, 0),
+        ]
+    }
+
+    fn cookie(&self, name: &str, value: &str, path: &str, max_age: u64) -> HeaderValue {
+        let secure = if self.secure_cookies { "; Secure" } else { "" };
+        let cookie = format!(
+            "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Strict{}",
+            name, value, path, max_age, secure
+        );
+        // Tokens are base64url or JWTs, which are always valid header values
+        HeaderValue::from_str(&cookie).expect("session cookie is a valid header value")
+    }
+}
+
+/// A new random refresh token.
+pub fn generate_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The stored form of a refresh token. Tokens are random, so no salt is needed.
+pub fn hash_refresh_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Reads a cookie from the request headers.
+pub fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
@@ -0,0 +1,102 @@
+//! Admin accounts: roles, password hashing and the role each admin route requires.
+//!
+//! Roles are ordered: a viewer can read everything in the admin API, an
+//! operator can also manage client keys and model aliases, and an owner can
+//! additionally manage upstream keys and admin users.
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use http::Method;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
+    Viewer,
+    Operator,
+    Owner,
+}
+
+impl AdminRole {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "viewer" => Some(Self::Viewer),
+            "operator" => Some(Self::Operator),
+            "owner" => Some(Self::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Operator => "operator",
+            Self::Owner => "owner",
+        }
+    }
+}
+
+/// The role needed for an admin API route, given its method and route pattern
+/// (e.g. `/admin/api/api-keys/{id}`). Routes not listed here need an owner.
+pub fn required_role(method: &Method, route: &str) -> AdminRole {
+    match (method.as_str(), route) {
+        ("GET", "/admin/api/dashboard")
+        | ("GET", "/admin/api/usage/timeseries")
+        | ("GET", "/admin/api/usage/top")
+        | ("GET", "/admin/api/usage/spend")
+        | ("GET", "/admin/api/usage/export")
+        | ("GET", "/admin/api/model-prices")
+        | ("GET", "/admin/api/api-keys")
+        | ("GET", "/admin/api/api-keys/{id}")
+        | ("GET", "/admin/api/model-aliases")
+        | ("GET", "/admin/api/upstream-keys")
+        | ("GET", "/admin/api/upstream-keys/{id}")
+        | ("POST", "/admin/api/auth/change-password")
+        | ("POST", "/admin/api/auth/logout-all")
+        | ("POST", "/admin/api/auth/totp/setup")
+        | ("POST", "/admin/api/auth/totp/enable")
+        | ("POST", "/admin/api/auth/totp/disable")
+        | ("POST", "/admin/api/auth/totp/recovery-codes") => AdminRole::Viewer,
+        ("POST", "/admin/api/api-keys")
+        | ("PUT", "/admin/api/api-keys/{id}")
+        | ("DELETE", "/admin/api/api-keys/{id}")
+        | ("PUT", "/admin/api/model-aliases/{alias}")
+        | ("DELETE", "/admin/api/model-aliases/{alias}")
+        | ("PUT", "/admin/api/model-prices/{model}")
+        | ("DELETE", "/admin/api/model-prices/{model}") => AdminRole::Operator,
+        _ => AdminRole::Owner,
+    }
+}
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("failed to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+/// A hash to verify against when the username does not exist, so that unknown
+/// and known usernames take the same time to reject.
+pub fn dummy_hash() -> &'static str {
+    static HASH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    HASH.get_or_init(|| hash_password("not-a-real-password").expect("hashing a constant password"))
+}
+
+/// A random password handed out when a user is invited or their password is reset.
+pub fn generate_temporary_password() -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz23456789";
+    let mut rng = rand::thread_rng();
+    (0..16)
+        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+        .collect()
+}
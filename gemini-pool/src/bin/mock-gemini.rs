@@ -0,0 +1,28 @@
+//! Runs the mock Gemini server for local development without network access.
+//!
+//! Usage: `mock-gemini [ADDR] [SCRIPT]`. `ADDR` defaults to `127.0.0.1:8090`.
+//! `SCRIPT` is an optional JSON file with an array of responses served in
+//! order, e.g. `[{"type": "text", "text": "Hi"}, {"type": "error", "status": 429, "message": "slow down"}]`.
+//! Point the proxy at it with `GEMINI_BASE_URL=http://127.0.0.1:8090/v1beta`.
+
+use gemini_pool::mock_gemini::{MockGemini, MockResponse};
+use std::env;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:8090".to_string());
+
+    let mock = MockGemini::bind(&addr).await?;
+    if let Some(path) = args.next() {
+        let script: Vec<MockResponse> = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        println!("Loaded {} scripted responses from {}", script.len(), path);
+        for response in script {
+            mock.push(response);
+        }
+    }
+
+    println!("Mock Gemini API listening on {}", mock.base_url());
+    mock.wait().await;
+    Ok(())
+}
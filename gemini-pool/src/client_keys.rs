@@ -0,0 +1,42 @@
+//! Client API key secrets.
+//!
+//! Keys are stored as an HMAC-SHA256 hash keyed with a per-installation salt,
+//! plus a short prefix for display. The hash is deterministic so a bearer
+//! token can be looked up directly, and the salt keeps hashes from being
+//! matched against precomputed tables.
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+/// Length of the prefix kept in clear text to tell keys apart in the UI.
+const DISPLAY_PREFIX_LEN: usize = 8;
+
+/// Generates a new random client key.
+pub fn generate_api_key() -> String {
+    format!("gp_{}", uuid::Uuid::new_v4().simple())
+}
+
+/// Generates a new random salt, hex encoded.
+pub fn generate_salt() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// Hashes a client key with the installation salt.
+pub fn hash_api_key(salt: &str, api_key: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(api_key.as_bytes());
+    to_hex(&mac.finalize().into_bytes())
+}
+
+/// The part of a key shown in the admin UI. Short keys reveal at most half of themselves.
+pub fn display_prefix(api_key: &str) -> String {
+    let len = DISPLAY_PREFIX_LEN.min(api_key.chars().count() / 2);
+    api_key.chars().take(len).collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,77 @@
+//! HTTP client for the Gemini API.
+//!
+//! Every upstream URL is built here. Keys are sent in the `x-goog-api-key`
+//! header rather than a `?key=` query parameter, so they never show up in
+//! URLs, error messages or proxy logs. The base URL is configurable to point
+//! the pool at a local mock or a regional endpoint.
+
+use reqwest::header::HeaderValue;
+use reqwest::RequestBuilder;
+use serde::Serialize;
+use std::env;
+
+pub const DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+pub struct GeminiClient {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl GeminiClient {
+    /// Creates a client for `base_url`, e.g. `https://generativelanguage.googleapis.com/v1beta`.
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Reads the base URL from `GEMINI_BASE_URL`, defaulting to Google's endpoint.
+    pub fn from_env() -> Self {
+        Self::new(&env::var("GEMINI_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string()))
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// `GET /models`
+    pub fn list_models(&self, api_key: &str) -> RequestBuilder {
+        self.authorize(self.http.get(format!("{}/models", self.base_url)), api_key)
+    }
+
+    /// `POST /models/{model}:generateContent`
+    pub fn generate_content<T: Serialize>(&self, api_key: &str, model: &str, body: &T) -> RequestBuilder {
+        let url = format!("{}/models/{}:generateContent", self.base_url, model);
+        self.authorize(self.http.post(url), api_key).json(body)
+    }
+
+    /// `POST /models/{model}:streamGenerateContent?alt=sse`
+    pub fn stream_generate_content<T: Serialize>(&self, api_key: &str, model: &str, body: &T) -> RequestBuilder {
+        let url = format!("{}/models/{}:streamGenerateContent?alt=sse", self.base_url, model);
+        self.authorize(self.http.post(url), api_key).json(body)
+    }
+
+    /// URI of a Files API file: a `files/{id}` name resolved against the base
+    /// URL, or a full URI under it. `None` for anything else.
+    pub fn file_uri(&self, file: &str) -> Option<String> {
+        if file.starts_with("files/") {
+            return Some(format!("{}/{}", self.base_url, file));
+        }
+        file.strip_prefix(&self.base_url)
+            .filter(|rest| rest.starts_with("/files/"))
+            .map(|_| file.to_string())
+    }
+
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        match HeaderValue::from_str(api_key) {
+            Ok(mut value) => {
+                // Keeps the key out of `Debug` output of the request
+                value.set_sensitive(true);
+                request.header("x-goog-api-key", value)
+            }
+            // Not a valid header value, so no Gemini key either; Gemini rejects the request
+            Err(_) => request,
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Upstream Gemini key pool with per-key health tracking.
+//!
+//! Every key is in one of three states: healthy, cooling down until a
+//! deadline (after a 429), or disabled (after the key was rejected as
+//! invalid or revoked). Selection is round-robin over the healthy keys.
+//!
+//! Keys are identified by their `upstream_keys` row id. The set of keys can be
+//! replaced at runtime with `set_keys`, keeping the health of keys that stay.
+
+use chrono::{DateTime, Utc};
+use http::{HeaderMap, StatusCode};
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Cooldown applied to a rate-limited key when Gemini gives no retry hint.
+const DEFAULT_COOLDOWN_SECS: i64 = 60;
+
+/// Longest cooldown taken from a retry hint.
+const MAX_COOLDOWN_SECS: i64 = 24 * 60 * 60;
+
+/// `ErrorInfo` reasons of a 403 that mean the key itself is unusable. Other
+/// 403s, such as no access to a model, a file or a region, leave it enabled.
+const KEY_REJECTED_REASONS: &[&str] = &[
+    "API_KEY_INVALID",
+    "API_KEY_SERVICE_BLOCKED",
+    "API_KEY_HTTP_REFERRER_BLOCKED",
+    "API_KEY_IP_ADDRESS_BLOCKED",
+    "API_KEY_ANDROID_APP_BLOCKED",
+    "API_KEY_IOS_APP_BLOCKED",
+    "SERVICE_DISABLED",
+    "CONSUMER_SUSPENDED",
+];
+
+/// Health of a single upstream key.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum KeyHealth {
+    Healthy,
+    CoolingDown { until: DateTime<Utc>, reason: String },
+    Disabled { reason: String },
+}
+
+struct UpstreamKey {
+    id: String,
+    key: String,
+    health: Mutex<KeyHealth>,
+}
+
+/// A key handed out by the pool. `id` identifies it when reporting back.
+pub struct SelectedKey {
+    pub id: String,
+    pub key: String,
+}
+
+/// Health snapshot of one key, as shown in the admin dashboard.
+#[derive(Debug, Serialize)]
+pub struct KeyStatus {
+    pub id: String,
+    pub key: String,
+    #[serde(flatten)]
+    pub health: KeyHealth,
+}
+
+pub struct KeyPool {
+    keys: RwLock<Vec<Arc<UpstreamKey>>>,
+    counter: AtomicUsize,
+}
+
+impl KeyPool {
+    /// Creates a pool from `(id, key)` pairs.
+    pub fn new(keys: Vec<(String, String)>) -> Self {
+        let pool = Self {
+            keys: RwLock::new(Vec::new()),
+            counter: AtomicUsize::new(0),
+        };
+        pool.set_keys(keys);
+        pool
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.read().unwrap().len()
+    }
+
+    /// Replaces the keys in the pool. Keys that were already present with the
+    /// same value keep their health; new keys start out healthy.
+    pub fn set_keys(&self, keys: Vec<(String, String)>) {
+        let mut current = self.keys.write().unwrap();
+        let updated = keys
+            .into_iter()
+            .map(|(id, key)| {
+                current
+                    .iter()
+                    .find(|existing| existing.id == id && existing.key == key)
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        Arc::new(UpstreamKey {
+                            id,
+                            key,
+                            health: Mutex::new(KeyHealth::Healthy),
+                        })
+                    })
+            })
+            .collect();
+        *current = updated;
+    }
+
+    /// Clears a `Disabled` state, e.g. after an admin re-enabled the key.
+    /// Cooldowns are left alone since they expire on their own.
+    pub fn clear_disabled(&self, id: &str) {
+        let keys = self.keys.read().unwrap();
+        if let Some(key) = keys.iter().find(|key| key.id == id) {
+            let mut health = key.health.lock().unwrap();
+            if matches!(*health, KeyHealth::Disabled { .. }) {
+                *health = KeyHealth::Healthy;
+            }
+        }
+    }
+
+    /// Selects the next healthy key in round-robin order, skipping the keys in
+    /// `exclude` (those already tried for the current request). Keys whose
+    /// cooldown has expired become healthy again. Returns `None` if no key is usable.
+    pub fn next_key(&self, exclude: &[String]) -> Option<SelectedKey> {
+        let keys = self.keys.read().unwrap();
+        if keys.is_empty() {
+            return None;
+        }
+        let start = self.counter.fetch_add(1, Ordering::SeqCst);
+        let now = Utc::now();
+
+        (0..keys.len())
+            .map(|offset| &keys[(start + offset) % keys.len()])
+            .filter(|key| !exclude.contains(&key.id))
+            .find(|key| {
+                let mut health = key.health.lock().unwrap();
+                match &*health {
+                    KeyHealth::Healthy => true,
+                    KeyHealth::CoolingDown { until, .. } if *until <= now => {
+                        *health = KeyHealth::Healthy;
+                        true
+                    }
+                    _ => false,
+                }
+            })
+            .map(|key| SelectedKey {
+                id: key.id.clone(),
+                key: key.key.clone(),
+            })
+    }
+
+    /// Updates a key's health from a failed upstream response and returns
+    /// whether the key was disabled, i.e. the failure was the key's fault.
+    /// Errors that say nothing about the key itself (e.g. 5xx) are ignored.
+    pub fn report_failure(&self, id: &str, status: StatusCode, headers: &HeaderMap, body: &str) -> bool {
+        let keys = self.keys.read().unwrap();
+        let Some(key) = keys.iter().find(|key| key.id == id) else {
+            return false;
+        };
+        let new_health = match status {
+            StatusCode::TOO_MANY_REQUESTS => {
+                let delay = retry_delay(headers, body).unwrap_or(chrono::Duration::seconds(DEFAULT_COOLDOWN_SECS));
+                KeyHealth::CoolingDown {
+                    until: Utc::now() + delay,
+                    reason: "rate limited (429)".to_string(),
+                }
+            }
+            StatusCode::UNAUTHORIZED => KeyHealth::Disabled {
+                reason: "rejected by Gemini (401)".to_string(),
+            },
+            StatusCode::FORBIDDEN => match error_reasons(body)
+                .into_iter()
+                .find(|reason| KEY_REJECTED_REASONS.contains(&reason.as_str()))
+            {
+                Some(reason) => KeyHealth::Disabled {
+                    reason: format!("rejected by Gemini (403 {})", reason),
+                },
+                None => return false,
+            },
+            StatusCode::BAD_REQUEST if body.contains("API_KEY_INVALID") => KeyHealth::Disabled {
+                reason: "invalid API key (API_KEY_INVALID)".to_string(),
+            },
+            _ => return false,
+        };
+
+        tracing::warn!("Upstream key {} is now {:?}", key.id, new_health);
+        let disabled = matches!(new_health, KeyHealth::Disabled { .. });
+        *key.health.lock().unwrap() = new_health;
+        disabled
+    }
+
+    /// Returns the health of every key, with the keys masked.
+    pub fn snapshot(&self) -> Vec<KeyStatus> {
+        let now = Utc::now();
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .map(|key| {
+                let health = match &*key.health.lock().unwrap() {
+                    KeyHealth::CoolingDown { until, .. } if *until <= now => KeyHealth::Healthy,
+                    health => health.clone(),
+                };
+                KeyStatus {
+                    id: key.id.clone(),
+                    key: mask_key(&key.key),
+                    health,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Masks a key for display, keeping only its first and last four characters.
+pub fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}...{}", head, tail)
+}
+
+/// Reads how long to back off from `Retry-After` or Gemini's `RetryInfo.retryDelay`,
+/// limited to [`MAX_COOLDOWN_SECS`].
+fn retry_delay(headers: &HeaderMap, body: &str) -> Option<chrono::Duration> {
+    let clamp = |millis: f64| chrono::Duration::milliseconds(millis.clamp(0.0, MAX_COOLDOWN_SECS as f64 * 1000.0) as i64);
+
+    if let Some(value) = headers.get(http::header::RETRY_AFTER).and_then(|v| v.to_str().ok()) {
+        if let Ok(seconds) = value.trim().parse::<f64>() {
+            return Some(clamp(seconds * 1000.0));
+        }
+        if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+            return Some(clamp((date.timestamp_millis() - Utc::now().timestamp_millis()) as f64));
+        }
+    }
+
+    // {"error": {"details": [{"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "13s"}]}}
+    let body: serde_json::Value = serde_json::from_str(body).ok()?;
+    body.pointer("/error/details")?
+        .as_array()?
+        .iter()
+        .find_map(|detail| detail.get("retryDelay")?.as_str())
+        .and_then(|delay| delay.strip_suffix('s')?.parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite())
+        .map(|seconds| clamp((seconds * 1000.0).ceil()))
+}
+
+/// The `ErrorInfo` reasons in a Gemini error body.
+fn error_reasons(body: &str) -> Vec<String> {
+    // {"error": {"details": [{"@type": "type.googleapis.com/google.rpc.ErrorInfo", "reason": "API_KEY_INVALID"}]}}
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|body| body.pointer("/error/details")?.as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|detail| detail.get("reason")?.as_str().map(str::to_string))
+        .collect()
+}
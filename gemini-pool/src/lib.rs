@@ -0,0 +1,5 @@
+//! Library side of gemini-pool. The proxy itself is the `gemini-pool` binary
+//! (`main.rs`); this crate holds code shared with the `mock-gemini` binary and
+//! the integration tests.
+
+pub mod mock_gemini;
@@ -0,0 +1,325 @@
+//! Brute-force protection for admin logins and client key authentication.
+//!
+//! Failed attempts are counted per client IP and, for admin logins, per
+//! username. A subject that fails `max_failures` times in a row is locked out,
+//! and each further lockout lasts twice as long as the one before, up to
+//! `max_lockout`. Counters live in memory and are forgotten after a quiet
+//! `failure_window`, or when the server restarts.
+//!
+//! Behind a reverse proxy every connection comes from the proxy, so the client
+//! IP is taken from `X-Forwarded-For` instead, but only when the connection
+//! comes from one of the trusted proxies. Anyone else could forge the header
+//! to get a fresh counter for every guess.
+
+use axum::http::HeaderMap;
+use chrono::Utc;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct LockoutPolicy {
+    /// Failures in a row that lock a subject out.
+    pub max_failures: u32,
+    /// Length of the first lockout; it doubles with every further one.
+    pub lockout: Duration,
+    /// Upper bound for a single lockout.
+    pub max_lockout: Duration,
+    /// Quiet time after which failures and past lockouts are forgotten.
+    pub failure_window: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            lockout: Duration::from_secs(60),
+            max_lockout: Duration::from_secs(60 * 60),
+            failure_window: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// Reads `LOGIN_MAX_FAILURES`, `LOGIN_LOCKOUT_SECS`, `LOGIN_MAX_LOCKOUT_SECS`
+    /// and `LOGIN_FAILURE_WINDOW_SECS`, keeping the defaults for anything unset.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let read = |name: &str| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .filter(|value| *value > 0)
+        };
+
+        Self {
+            max_failures: read("LOGIN_MAX_FAILURES")
+                .map(|v| v.min(u32::MAX as u64) as u32)
+                .unwrap_or(default.max_failures),
+            lockout: read("LOGIN_LOCKOUT_SECS").map(Duration::from_secs).unwrap_or(default.lockout),
+            max_lockout: read("LOGIN_MAX_LOCKOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.max_lockout),
+            failure_window: read("LOGIN_FAILURE_WINDOW_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.failure_window),
+        }
+    }
+
+    /// Length of the `nth` lockout in a row, starting at 1.
+    fn lockout_for(&self, nth: u32) -> Duration {
+        let factor = 2u32.saturating_pow(nth.saturating_sub(1));
+        self.lockout.saturating_mul(factor).min(self.max_lockout)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubjectKind {
+    Ip,
+    Username,
+}
+
+impl SubjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ip => "ip",
+            Self::Username => "username",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ip" => Some(Self::Ip),
+            "username" => Some(Self::Username),
+            _ => None,
+        }
+    }
+}
+
+/// Who failures are counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Subject {
+    pub kind: SubjectKind,
+    pub value: String,
+}
+
+impl Subject {
+    pub fn ip(ip: IpAddr) -> Self {
+        Self {
+            kind: SubjectKind::Ip,
+            value: ip.to_string(),
+        }
+    }
+
+    /// Usernames are case-insensitive, like the `admin_users` table.
+    pub fn username(username: &str) -> Self {
+        Self {
+            kind: SubjectKind::Username,
+            value: username.trim().to_lowercase(),
+        }
+    }
+
+    /// A subject named by the admin API, e.g. `("ip", "203.0.113.7")`.
+    pub fn parse(kind: &str, value: &str) -> Option<Self> {
+        match SubjectKind::parse(kind)? {
+            SubjectKind::Ip => value.parse().ok().map(Self::ip),
+            SubjectKind::Username => Some(Self::username(value)),
+        }
+    }
+}
+
+struct Entry {
+    failures: u32,
+    lockouts: u32,
+    locked_until: Option<Instant>,
+    last_failure: Instant,
+}
+
+impl Entry {
+    fn locked_for(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Whether the entry has been quiet long enough to be forgotten.
+    fn is_stale(&self, now: Instant, window: Duration) -> bool {
+        let last_activity = self.locked_until.map_or(self.last_failure, |until| until.max(self.last_failure));
+        now.saturating_duration_since(last_activity) >= window
+    }
+}
+
+/// Lock state of one subject, as shown in the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct LockoutInfo {
+    pub kind: SubjectKind,
+    pub value: String,
+    /// Failures since the last lockout.
+    pub failures: u32,
+    /// Lockouts in a row; the next one lasts twice as long.
+    pub lockouts: u32,
+    pub locked_until: Option<String>,
+}
+
+pub struct LoginGuard {
+    policy: LockoutPolicy,
+    trusted_proxies: Vec<IpNet>,
+    entries: Mutex<HashMap<Subject, Entry>>,
+}
+
+impl LoginGuard {
+    pub fn new(policy: LockoutPolicy, trusted_proxies: Vec<IpNet>) -> Self {
+        Self {
+            policy,
+            trusted_proxies,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads the policy and `TRUSTED_PROXIES`, a comma-separated list of
+    /// addresses or CIDR ranges.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let trusted_proxies = env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<IpNet>()
+                    .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| anyhow::anyhow!("Invalid address in TRUSTED_PROXIES: {}", s))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self::new(LockoutPolicy::from_env(), trusted_proxies))
+    }
+
+    pub fn policy(&self) -> &LockoutPolicy {
+        &self.policy
+    }
+
+    pub fn trusted_proxies(&self) -> &[IpNet] {
+        &self.trusted_proxies
+    }
+
+    /// The client's address. For connections from a trusted proxy this is the
+    /// last address in `X-Forwarded-For` that is not a trusted proxy itself.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|addr| addr.trim().parse().ok())
+            .collect();
+        forwarded
+            .iter()
+            .rev()
+            .find(|ip| !self.is_trusted(**ip))
+            .or_else(|| forwarded.first())
+            .copied()
+            .unwrap_or(peer)
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip,
+        };
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// How long the longest lockout among `subjects` still lasts, if any.
+    pub fn locked_for(&self, subjects: &[Subject]) -> Option<Duration> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        subjects
+            .iter()
+            .filter_map(|subject| entries.get(subject)?.locked_for(now))
+            .max()
+    }
+
+    /// Counts a failed attempt against every subject and locks out those that
+    /// reached the limit.
+    pub fn record_failure(&self, subjects: &[Subject]) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| !entry.is_stale(now, self.policy.failure_window));
+
+        for subject in subjects {
+            let entry = entries.entry(subject.clone()).or_insert(Entry {
+                failures: 0,
+                lockouts: 0,
+                locked_until: None,
+                last_failure: now,
+            });
+            entry.last_failure = now;
+            if entry.locked_for(now).is_some() {
+                continue;
+            }
+
+            entry.failures += 1;
+            if entry.failures >= self.policy.max_failures {
+                entry.lockouts += 1;
+                let lockout = self.policy.lockout_for(entry.lockouts);
+                entry.locked_until = Some(now + lockout);
+                tracing::warn!(
+                    target: "security",
+                    "Locked out {} {} for {}s after {} failed attempts (lockout #{})",
+                    subject.kind.as_str(),
+                    subject.value,
+                    lockout.as_secs(),
+                    entry.failures,
+                    entry.lockouts
+                );
+                entry.failures = 0;
+            }
+        }
+    }
+
+    /// Forgets the failures of a subject after a successful attempt.
+    pub fn record_success(&self, subject: &Subject) {
+        self.entries.lock().unwrap().remove(subject);
+    }
+
+    /// Subjects with failures or an active lockout, locked ones first.
+    pub fn entries(&self) -> Vec<LockoutInfo> {
+        let now = Instant::now();
+        let wall_now = Utc::now();
+        let entries = self.entries.lock().unwrap();
+        let mut infos: Vec<LockoutInfo> = entries
+            .iter()
+            .filter(|(_, entry)| !entry.is_stale(now, self.policy.failure_window))
+            .map(|(subject, entry)| LockoutInfo {
+                kind: subject.kind,
+                value: subject.value.clone(),
+                failures: entry.failures,
+                lockouts: entry.lockouts,
+                locked_until: entry.locked_for(now).map(|remaining| {
+                    let until = wall_now + chrono::Duration::from_std(remaining).unwrap_or_default();
+                    until.format("%Y-%m-%d %H:%M:%S").to_string()
+                }),
+            })
+            .collect();
+        infos.sort_by(|a, b| {
+            b.locked_until
+                .is_some()
+                .cmp(&a.locked_until.is_some())
+                .then_with(|| a.value.cmp(&b.value))
+        });
+        infos
+    }
+
+    /// Lifts the lockout of a subject and forgets its failures.
+    pub fn clear(&self, subject: &Subject) -> bool {
+        self.entries.lock().unwrap().remove(subject).is_some()
+    }
+}
@@ -1,22 +1,62 @@
+mod admin_sessions;
+mod admin_users;
+mod client_keys;
+mod gemini_client;
+mod key_pool;
+mod login_guard;
+mod master_key;
+mod metrics;
+mod model_access;
+mod pricing;
+mod quota;
+mod rate_limit;
+mod retry;
+mod totp;
+mod usage;
+
 use axum::{
-    extract::{Path, State},
+    body::Body,
+    extract::{rejection::JsonRejection, ConnectInfo, MatchedPath, Path, Query, State},
     http::{HeaderMap, StatusCode},
     middleware,
-    response::{IntoResponse, Response},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::{delete, get, post, put},
-    Json, Router,
+    Extension, Json, Router,
 };
+use anyhow::Context;
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::{SqlitePool, Row};
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::env;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tower_http::services::ServeDir;
 use tower_http::cors::CorsLayer;
 use tracing::info;
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use admin_sessions::SessionConfig;
+use admin_users::AdminRole;
+use gemini_client::GeminiClient;
+use key_pool::{mask_key, KeyHealth, KeyPool, KeyStatus, SelectedKey};
+use login_guard::{LockoutInfo, LoginGuard, Subject, SubjectKind};
+use master_key::MasterKey;
+use metrics::{Metrics, RequestLabels};
+use model_access::{is_allowed, is_valid_model_name, is_valid_pattern, ModelAlias, ModelAliases};
+use pricing::{ModelPrice, PriceTable, DEFAULT_PRICES};
+use quota::{Quota, QuotaPeriod, QuotaUsage};
+use rate_limit::{RateLimiter, RateLimits};
+use retry::RetryPolicy;
+use usage::{UsageAmounts, UsageDimension, UsageGrouping, UsageOrder};
 
 //================================================================================
 // Database Models
@@ -26,12 +66,28 @@ use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation}
 struct ApiKey {
     id: Uuid,
     key_name: String,
-    api_key: String,
+    /// First characters of the key; the full key is only shown when it is created.
+    key_prefix: String,
     is_active: bool,
     created_at: DateTime<Utc>,
     total_requests: i64,
     total_input_tokens: i64,
     total_output_tokens: i64,
+    #[serde(flatten)]
+    limits: RateLimits,
+    #[serde(flatten)]
+    quota: Quota,
+    /// Usage in the current quota period, if the key has a quota.
+    quota_usage: Option<QuotaUsage>,
+    /// Model patterns the key may use; empty means every model.
+    allowed_models: Vec<String>,
+}
+
+/// The authenticated client key, stored in request extensions by `auth_middleware`.
+#[derive(Debug, Clone)]
+struct ClientKey {
+    id: String,
+    allowed_models: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -42,13 +98,37 @@ struct UsageLog {
     endpoint: String,
     model: String,
     input_tokens: i32,
+    /// Cached prompt tokens, included in `input_tokens`.
+    cached_tokens: i32,
     output_tokens: i32,
+    cost_usd: f64,
+    success: bool,
+}
+
+/// One upstream request attempt. A client request that failed over to another
+/// key has several attempts sharing the same `request_id`.
+#[derive(Debug, Serialize)]
+struct UpstreamAttempt {
+    id: Uuid,
+    request_id: Uuid,
+    attempt: u32,
+    upstream_key_id: String,
+    timestamp: DateTime<Utc>,
+    endpoint: String,
+    model: String,
+    status_code: Option<u16>,
+    error: Option<String>,
+    latency_ms: i64,
     success: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
     sub: String,
+    /// Id of the admin user in `admin_users`.
+    uid: String,
+    /// Id of the session in `admin_sessions`; revoking it ends this token.
+    sid: String,
     exp: usize,
 }
 
@@ -56,11 +136,139 @@ struct Claims {
 struct LoginRequest {
     username: String,
     password: String,
+    /// Deliver the tokens as HttpOnly cookies instead of in the response body.
+    #[serde(default)]
+    use_cookies: bool,
 }
 
 #[derive(Debug, Serialize)]
 struct LoginResponse {
-    token: String,
+    /// Access token; omitted when the tokens are sent as cookies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    /// Seconds until the access token expires.
+    expires_in: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RefreshRequest {
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+/// The signed-in admin, stored in request extensions by `admin_auth_middleware`.
+#[derive(Debug, Clone, Serialize)]
+struct AdminUser {
+    id: String,
+    username: String,
+    role: AdminRole,
+    /// The `admin_sessions` row of the current session.
+    session_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AdminUserInfo {
+    id: Uuid,
+    username: String,
+    role: AdminRole,
+    is_active: bool,
+    created_at: DateTime<Utc>,
+    last_login_at: Option<DateTime<Utc>>,
+    totp_enabled: bool,
+    /// Sessions that are neither revoked nor expired.
+    active_sessions: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct AdminUsersResponse {
+    users: Vec<AdminUserInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InviteAdminUserRequest {
+    username: String,
+    role: AdminRole,
+}
+
+/// The temporary password is only returned here; the user should change it after signing in.
+#[derive(Debug, Serialize)]
+struct InviteAdminUserResponse {
+    id: String,
+    username: String,
+    temporary_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateAdminUserRequest {
+    role: AdminRole,
+    is_active: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ResetPasswordResponse {
+    temporary_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+/// Claims of the intermediate token handed out by `admin_login` when the
+/// user has two-factor login enabled. It only unlocks `login/totp`.
+#[derive(Debug, Serialize, Deserialize)]
+struct TotpChallengeClaims {
+    /// Id of the admin user in `admin_users`.
+    uid: String,
+    /// Always `totp_challenge`, so the token is never taken for an access token.
+    purpose: String,
+    use_cookies: bool,
+    exp: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct TotpChallengeResponse {
+    totp_required: bool,
+    totp_token: String,
+    expires_in: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TotpLoginRequest {
+    totp_token: String,
+    /// A code from the authenticator app, or a recovery code.
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TotpSetupResponse {
+    secret: String,
+    otpauth_uri: String,
+    /// The provisioning URI as an SVG QR code.
+    qr_svg: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TotpCodeRequest {
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PasswordConfirmationRequest {
+    password: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct LockoutsResponse {
+    lockouts: Vec<LockoutInfo>,
 }
 
 #[derive(Debug, Serialize)]
@@ -69,6 +277,150 @@ struct DashboardStats {
     total_requests: i64,
     total_tokens: i64,
     active_keys: i64,
+    /// Client requests that needed more than one upstream attempt.
+    retried_requests: i64,
+    /// Retried requests that eventually succeeded on another key.
+    failover_saves: i64,
+    total_cost_usd: f64,
+    /// Spend since the start of the current UTC month.
+    month_cost_usd: f64,
+    upstream_keys: Vec<KeyStatus>,
+}
+
+/// Range and filters shared by the usage analytics endpoints.
+#[derive(Debug, Deserialize)]
+struct UsageQuery {
+    from: Option<String>,
+    to: Option<String>,
+    group_by: Option<String>,
+    api_key_id: Option<String>,
+    model: Option<String>,
+    endpoint: Option<String>,
+    success: Option<bool>,
+    /// Breakdown dimension: `api_key`, `model` or `endpoint`.
+    by: Option<String>,
+    order_by: Option<String>,
+    limit: Option<u32>,
+    /// `json` (default) or `csv` for spend reports, `csv` (default) or
+    /// `ndjson` for exports.
+    format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct UsagePoint {
+    period: String,
+    #[serde(flatten)]
+    usage: UsageAmounts,
+}
+
+#[derive(Debug, Serialize)]
+struct UsageSeriesResponse {
+    from: String,
+    to: String,
+    group_by: UsageGrouping,
+    points: Vec<UsagePoint>,
+    totals: UsageAmounts,
+}
+
+#[derive(Debug, Serialize)]
+struct UsageBreakdownItem {
+    key: String,
+    /// Client key name, when breaking down by `api_key`.
+    name: Option<String>,
+    #[serde(flatten)]
+    usage: UsageAmounts,
+}
+
+#[derive(Debug, Serialize)]
+struct UsageBreakdownResponse {
+    from: String,
+    to: String,
+    by: UsageDimension,
+    order_by: UsageOrder,
+    items: Vec<UsageBreakdownItem>,
+    /// Usage outside the listed items.
+    others: UsageAmounts,
+    totals: UsageAmounts,
+}
+
+#[derive(Debug, Serialize)]
+struct SpendRow {
+    period: String,
+    api_key_id: String,
+    key_name: Option<String>,
+    model: String,
+    #[serde(flatten)]
+    usage: UsageAmounts,
+}
+
+#[derive(Debug, Serialize)]
+struct SpendReportResponse {
+    from: String,
+    to: String,
+    group_by: UsageGrouping,
+    rows: Vec<SpendRow>,
+    totals: UsageAmounts,
+}
+
+/// One `usage_logs` row as exported.
+#[derive(Debug, Serialize)]
+struct UsageExportRow {
+    id: String,
+    timestamp: String,
+    api_key_id: String,
+    key_name: Option<String>,
+    endpoint: String,
+    model: String,
+    input_tokens: i64,
+    cached_tokens: i64,
+    output_tokens: i64,
+    cost_usd: f64,
+    success: bool,
+}
+
+impl UsageExportRow {
+    const CSV_HEADER: &'static [&'static str] = &[
+        "id",
+        "timestamp",
+        "api_key_id",
+        "key_name",
+        "endpoint",
+        "model",
+        "input_tokens",
+        "cached_tokens",
+        "output_tokens",
+        "cost_usd",
+        "success",
+    ];
+
+    fn csv_line(&self) -> String {
+        usage::csv_line(&[
+            &self.id,
+            &self.timestamp,
+            &self.api_key_id,
+            self.key_name.as_deref().unwrap_or(""),
+            &self.endpoint,
+            &self.model,
+            &self.input_tokens.to_string(),
+            &self.cached_tokens.to_string(),
+            &self.output_tokens.to_string(),
+            &format!("{:.6}", self.cost_usd),
+            if self.success { "true" } else { "false" },
+        ])
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ModelPricesResponse {
+    prices: Vec<ModelPrice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetModelPriceRequest {
+    input_per_million: f64,
+    #[serde(default)]
+    cached_input_per_million: Option<f64>,
+    output_per_million: f64,
 }
 
 #[derive(Debug, Serialize)]
@@ -80,6 +432,12 @@ struct ApiKeysResponse {
 struct CreateApiKeyRequest {
     key_name: String,
     api_key: Option<String>,
+    #[serde(flatten)]
+    limits: RateLimits,
+    #[serde(flatten)]
+    quota: Quota,
+    #[serde(default)]
+    allowed_models: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -92,6 +450,69 @@ struct CreateApiKeyResponse {
 struct UpdateApiKeyRequest {
     key_name: String,
     is_active: bool,
+    /// Omitted limits and quotas are cleared, i.e. become unlimited.
+    #[serde(flatten)]
+    limits: RateLimits,
+    #[serde(flatten)]
+    quota: Quota,
+    #[serde(default)]
+    allowed_models: Vec<String>,
+}
+
+/// An upstream Gemini key as shown in the admin API. The key itself is masked.
+#[derive(Debug, Serialize)]
+struct UpstreamKeyInfo {
+    id: Uuid,
+    label: String,
+    api_key: String,
+    notes: String,
+    is_active: bool,
+    created_at: DateTime<Utc>,
+    success_count: i64,
+    error_count: i64,
+    last_error: Option<String>,
+    last_error_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    /// Health in the running pool; absent for keys that are not active.
+    #[serde(flatten)]
+    health: Option<KeyHealth>,
+}
+
+#[derive(Debug, Serialize)]
+struct ModelAliasesResponse {
+    aliases: Vec<ModelAlias>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetModelAliasRequest {
+    model: String,
+}
+
+#[derive(Debug, Serialize)]
+struct UpstreamKeysResponse {
+    upstream_keys: Vec<UpstreamKeyInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateUpstreamKeyRequest {
+    api_key: String,
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    notes: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateUpstreamKeyResponse {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateUpstreamKeyRequest {
+    label: String,
+    #[serde(default)]
+    notes: String,
+    is_active: bool,
 }
 
 //================================================================================
@@ -100,19 +521,44 @@ struct UpdateApiKeyRequest {
 
 /// Shared state for the application, including the API key pool.
 struct AppState {
-    api_keys: Vec<String>,
-    counter: AtomicUsize,
+    key_pool: KeyPool,
     db_pool: SqlitePool,
     jwt_secret: String,
-    admin_username: String,
-    admin_password: String,
+    sessions: SessionConfig,
+    /// Reject unknown chat completion parameters instead of logging them.
+    strict_params: bool,
+    retry_policy: RetryPolicy,
+    rate_limiter: RateLimiter,
+    /// Failed login and client key attempts per IP and username.
+    login_guard: LoginGuard,
+    metrics: Arc<Metrics>,
+    /// Bearer token required by `/metrics`, if set.
+    metrics_token: Option<String>,
+    prices: PriceTable,
+    model_aliases: ModelAliases,
+    /// Salt client API keys are hashed with.
+    api_key_salt: String,
+    /// Encrypts upstream keys stored in the database.
+    master_key: MasterKey,
+    gemini: GeminiClient,
 }
 
 impl AppState {
-    /// Selects an API key from the pool in a round-robin fashion.
-    fn get_next_api_key(&self) -> &str {
-        let index = self.counter.fetch_add(1, Ordering::SeqCst);
-        &self.api_keys[index % self.api_keys.len()]
+    /// Selects a healthy API key from the pool in a round-robin fashion,
+    /// skipping the keys already tried for the current request.
+    fn get_next_api_key(&self, exclude: &[String]) -> Result<SelectedKey, AppError> {
+        self.key_pool
+            .next_key(exclude)
+            .ok_or_else(|| AppError::ServiceUnavailable("No healthy upstream API keys available".to_string()))
+    }
+
+    /// Reloads the active upstream keys from the database into the pool.
+    async fn reload_key_pool(&self) -> Result<(), AppError> {
+        let keys = load_active_upstream_keys(&self.db_pool, &self.master_key)
+            .await
+            .map_err(AppError::Internal)?;
+        self.key_pool.set_keys(keys);
+        Ok(())
     }
 }
 
@@ -124,6 +570,16 @@ impl AppState {
 enum AppError {
     Internal(anyhow::Error),
     BadRequest(String),
+    /// The upstream answered, but with something we cannot hand to the client.
+    BadGateway(String),
+    NotFound(String),
+    ServiceUnavailable(String),
+    /// Missing, invalid or expired admin session.
+    Unauthorized(String),
+    /// Authenticated, but the admin's role does not allow the action.
+    Forbidden(String),
+    /// Too many failed attempts; try again after `retry_after` seconds.
+    TooManyRequests { message: String, retry_after: u64 },
 }
 
 impl IntoResponse for AppError {
@@ -138,6 +594,17 @@ impl IntoResponse for AppError {
                     .into_response()
             }
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": msg}))).into_response(),
+            AppError::BadGateway(msg) => (StatusCode::BAD_GATEWAY, Json(serde_json::json!({"error": msg}))).into_response(),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": msg}))).into_response(),
+            AppError::ServiceUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, Json(serde_json::json!({"error": msg}))).into_response(),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": msg}))).into_response(),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, Json(serde_json::json!({"error": msg}))).into_response(),
+            AppError::TooManyRequests { message, retry_after } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(http::header::RETRY_AFTER, retry_after.to_string())],
+                Json(serde_json::json!({"error": message})),
+            )
+                .into_response(),
         }
     }
 }
@@ -160,97 +627,476 @@ where
 struct OpenAIChatRequest {
     model: String,
     messages: Vec<OpenAIMessage>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(default)]
+    stream_options: Option<StreamOptions>,
+    #[serde(default)]
+    tools: Option<Vec<OpenAITool>>,
+    #[serde(default)]
+    tool_choice: Option<OpenAIToolChoice>,
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[serde(default)]
+    top_p: Option<f32>,
+    #[serde(default)]
+    max_tokens: Option<u32>,
+    #[serde(default)]
+    max_completion_tokens: Option<u32>,
+    #[serde(default)]
+    stop: Option<OpenAIStop>,
+    #[serde(default)]
+    presence_penalty: Option<f32>,
+    #[serde(default)]
+    frequency_penalty: Option<f32>,
+    #[serde(default)]
+    seed: Option<i64>,
+    #[serde(default)]
+    n: Option<u32>,
+    #[serde(default)]
+    response_format: Option<OpenAIResponseFormat>,
+    /// Any parameter not listed above, checked against `AppState::strict_params`.
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
+
+/// `stop`: a single sequence or a list of sequences.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum OpenAIStop {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAIResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: OpenAIJsonSchema },
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIJsonSchema {
+    #[serde(default)]
+    schema: Option<serde_json::Value>,
+    #[serde(default)]
+    strict: bool,
+}
+
+/// OpenAI parameters that have no Gemini equivalent but are safe to drop.
+const IGNORED_OPENAI_PARAMS: &[&str] = &["user", "metadata", "store", "parallel_tool_calls", "service_tier"];
+
+#[derive(Deserialize, Debug, Default)]
+struct StreamOptions {
+    #[serde(default)]
+    include_usage: bool,
 }
 
 #[derive(Deserialize, Debug, Serialize, Clone)]
 struct OpenAIMessage {
     role: String,
-    content: String,
+    #[serde(default)]
+    content: Option<OpenAIContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OpenAIToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
 }
 
-#[derive(Serialize, Debug)]
-struct OpenAIChatResponse {
-    id: String,
-    object: String,
-    created: u64,
-    model: String,
-    choices: Vec<OpenAIChoice>,
+/// Message content: either a plain string or an array of typed parts.
+#[derive(Deserialize, Debug, Serialize, Clone)]
+#[serde(untagged)]
+enum OpenAIContent {
+    Text(String),
+    Parts(Vec<OpenAIContentPart>),
 }
 
-#[derive(Serialize, Debug)]
-struct OpenAIChoice {
-    index: u32,
-    message: OpenAIMessage,
-    finish_reason: String,
+impl OpenAIContent {
+    /// Returns the concatenated text of the content, ignoring media parts.
+    fn text(&self) -> String {
+        match self {
+            OpenAIContent::Text(text) => text.clone(),
+            OpenAIContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    OpenAIContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
 }
 
-#[derive(Serialize, Debug)]
-struct ModelList {
-    object: String,
-    data: Vec<ModelObject>,
+#[derive(Deserialize, Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAIContentPart {
+    Text { text: String },
+    ImageUrl { image_url: OpenAIImageUrl },
+    InputAudio { input_audio: OpenAIInputAudio },
+    File { file: OpenAIFile },
+    #[serde(other)]
+    Unsupported,
 }
 
-#[derive(Serialize, Debug)]
-struct ModelObject {
-    id: String,
-    object: String,
-    created: u64,
-    owned_by: String,
+#[derive(Deserialize, Debug, Serialize, Clone)]
+struct OpenAIImageUrl {
+    url: String,
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone)]
+struct OpenAIInputAudio {
+    data: String,
+    format: String,
 }
 
 //--------------------------------------------------------------------------------
-// Gemini-Specific Model List Structures
+// OpenAI Tool Calling Structures
 //--------------------------------------------------------------------------------
 
 #[derive(Deserialize, Debug)]
-struct GeminiModelList {
-    models: Vec<GeminiModelInfo>,
+struct OpenAITool {
+    #[serde(rename = "type")]
+    tool_type: String,
+    function: OpenAIFunctionDefinition,
 }
 
 #[derive(Deserialize, Debug)]
-struct GeminiModelInfo {
+struct OpenAIFunctionDefinition {
     name: String,
-    #[serde(rename = "supportedGenerationMethods")]
-    supported_generation_methods: Vec<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    parameters: Option<serde_json::Value>,
 }
 
-
-//================================================================================
-// Gemini API Data Structures
-//================================================================================
-
-#[derive(Serialize, Debug)]
-struct GeminiRequest {
-    contents: Vec<GeminiContent>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    system_instruction: Option<SystemInstruction>,
+/// `tool_choice`: either `"none"`, `"auto"`, `"required"` or a named function.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum OpenAIToolChoice {
+    Mode(String),
+    Function { function: OpenAIFunctionName },
 }
 
-#[derive(Serialize, Debug, Clone)]
-struct SystemInstruction {
-    parts: Vec<GeminiPart>,
+#[derive(Deserialize, Debug)]
+struct OpenAIFunctionName {
+    name: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct GeminiContent {
-    #[serde(default)]
-    role: String,
-    parts: Vec<GeminiPart>,
+#[derive(Deserialize, Debug, Serialize, Clone)]
+struct OpenAIToolCall {
+    /// Position of the call within a streamed delta; absent in full messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    index: Option<u32>,
+    id: String,
+    #[serde(rename = "type")]
+    call_type: String,
+    function: OpenAIFunctionCall,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
-struct GeminiPart {
-    text: String,
+#[derive(Deserialize, Debug, Serialize, Clone)]
+struct OpenAIFunctionCall {
+    name: String,
+    /// JSON-encoded arguments, as OpenAI sends them.
+    arguments: String,
 }
 
-#[derive(Deserialize, Debug)]
-struct GeminiResponse {
-    candidates: Vec<GeminiCandidate>,
+#[derive(Deserialize, Debug, Serialize, Clone)]
+struct OpenAIFile {
+    #[serde(default)]
+    file_data: Option<String>,
+    #[serde(default)]
+    file_id: Option<String>,
+    #[serde(default)]
+    filename: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAIChatResponse {
+    id: String,
+    object: String,
+    created: u64,
+    model: String,
+    choices: Vec<OpenAIChoice>,
+    usage: OpenAIUsage,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAIChoice {
+    index: u32,
+    message: OpenAIMessage,
+    finish_reason: String,
+}
+
+//--------------------------------------------------------------------------------
+// OpenAI Streaming Structures
+//--------------------------------------------------------------------------------
+
+#[derive(Serialize, Debug)]
+struct OpenAIChatChunk {
+    id: String,
+    object: String,
+    created: u64,
+    model: String,
+    choices: Vec<OpenAIChunkChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAIChunkChoice {
+    index: u32,
+    delta: OpenAIDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct OpenAIDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OpenAIToolCall>>,
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+struct OpenAIUsage {
+    prompt_tokens: i32,
+    completion_tokens: i32,
+    total_tokens: i32,
+    prompt_tokens_details: PromptTokensDetails,
+    completion_tokens_details: CompletionTokensDetails,
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+struct PromptTokensDetails {
+    cached_tokens: i32,
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+struct CompletionTokensDetails {
+    reasoning_tokens: i32,
+}
+
+#[derive(Serialize, Debug)]
+struct ModelList {
+    object: String,
+    data: Vec<ModelObject>,
+}
+
+#[derive(Serialize, Debug)]
+struct ModelObject {
+    id: String,
+    object: String,
+    created: u64,
+    owned_by: String,
+}
+
+//--------------------------------------------------------------------------------
+// Gemini-Specific Model List Structures
+//--------------------------------------------------------------------------------
+
+#[derive(Deserialize, Debug)]
+struct GeminiModelList {
+    models: Vec<GeminiModelInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GeminiModelInfo {
+    name: String,
+    #[serde(rename = "supportedGenerationMethods")]
+    supported_generation_methods: Vec<String>,
+}
+
+
+//================================================================================
+// Gemini API Data Structures
+//================================================================================
+
+#[derive(Serialize, Debug)]
+struct GeminiRequest {
+    contents: Vec<GeminiContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<SystemInstruction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<GeminiTool>>,
+    #[serde(rename = "toolConfig", skip_serializing_if = "Option::is_none")]
+    tool_config: Option<GeminiToolConfig>,
+    #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GeminiGenerationConfig>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct GeminiGenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(rename = "topP", skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(rename = "maxOutputTokens", skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+    #[serde(rename = "stopSequences", skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+    #[serde(rename = "presencePenalty", skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(rename = "frequencyPenalty", skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(rename = "candidateCount", skip_serializing_if = "Option::is_none")]
+    candidate_count: Option<u32>,
+    #[serde(rename = "responseMimeType", skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<String>,
+    #[serde(rename = "responseSchema", skip_serializing_if = "Option::is_none")]
+    response_schema: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct GeminiTool {
+    #[serde(rename = "functionDeclarations")]
+    function_declarations: Vec<GeminiFunctionDeclaration>,
+}
+
+#[derive(Serialize, Debug)]
+struct GeminiFunctionDeclaration {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameters: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct GeminiToolConfig {
+    #[serde(rename = "functionCallingConfig")]
+    function_calling_config: GeminiFunctionCallingConfig,
+}
+
+#[derive(Serialize, Debug)]
+struct GeminiFunctionCallingConfig {
+    mode: String,
+    #[serde(rename = "allowedFunctionNames", skip_serializing_if = "Option::is_none")]
+    allowed_function_names: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct SystemInstruction {
+    parts: Vec<GeminiPart>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct GeminiContent {
+    #[serde(default)]
+    role: String,
+    #[serde(default)]
+    parts: Vec<GeminiPart>,
+}
+
+/// A single Gemini content part. Each variant maps onto one of the part
+/// shapes of the Gemini API; anything else is passed through untouched.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum GeminiPart {
+    Text {
+        text: String,
+    },
+    InlineData {
+        #[serde(rename = "inlineData")]
+        inline_data: GeminiBlob,
+    },
+    FileData {
+        #[serde(rename = "fileData")]
+        file_data: GeminiFileData,
+    },
+    FunctionCall {
+        #[serde(rename = "functionCall")]
+        function_call: GeminiFunctionCall,
+        /// Opaque signature of the model's reasoning, which Gemini expects back
+        /// with the call on the next turn.
+        #[serde(rename = "thoughtSignature", default, skip_serializing_if = "Option::is_none")]
+        thought_signature: Option<String>,
+    },
+    FunctionResponse {
+        #[serde(rename = "functionResponse")]
+        function_response: GeminiFunctionResponse,
+    },
+    Other(serde_json::Value),
+}
+
+impl GeminiPart {
+    fn text(text: impl Into<String>) -> Self {
+        GeminiPart::Text { text: text.into() }
+    }
+
+    fn as_text(&self) -> Option<&str> {
+        match self {
+            GeminiPart::Text { text } => Some(text),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct GeminiBlob {
+    #[serde(rename = "mimeType")]
+    mime_type: String,
+    data: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct GeminiFunctionCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    name: String,
+    #[serde(default)]
+    args: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct GeminiFunctionResponse {
+    name: String,
+    response: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct GeminiFileData {
+    #[serde(rename = "mimeType", skip_serializing_if = "Option::is_none")]
+    mime_type: Option<String>,
+    #[serde(rename = "fileUri")]
+    file_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GeminiResponse {
+    #[serde(default)]
+    candidates: Vec<GeminiCandidate>,
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<GeminiUsageMetadata>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+struct GeminiUsageMetadata {
+    #[serde(rename = "promptTokenCount", default)]
+    prompt_token_count: i32,
+    #[serde(rename = "candidatesTokenCount", default)]
+    candidates_token_count: i32,
+    #[serde(rename = "totalTokenCount")]
+    total_token_count: Option<i32>,
+    #[serde(rename = "cachedContentTokenCount", default)]
+    cached_content_token_count: i32,
+    #[serde(rename = "thoughtsTokenCount", default)]
+    thoughts_token_count: i32,
 }
 
 #[derive(Deserialize, Debug)]
 struct GeminiCandidate {
+    #[serde(default)]
+    index: Option<u32>,
+    #[serde(default)]
     content: GeminiContent,
+    #[serde(rename = "finishReason")]
+    finish_reason: Option<String>,
 }
 
 
@@ -258,25 +1104,34 @@ struct GeminiCandidate {
 // Database Functions
 //================================================================================
 
-async fn initialize_database(pool: &SqlitePool) -> anyhow::Result<()> {
-    // Create api_keys table
+async fn initialize_database(pool: &SqlitePool, master_key: &MasterKey) -> anyhow::Result<()> {
+    // Create app_settings table
     sqlx::query(
         r#"
-        CREATE TABLE IF NOT EXISTS api_keys (
-            id TEXT PRIMARY KEY,
-            key_name TEXT NOT NULL,
-            api_key TEXT NOT NULL UNIQUE,
-            is_active BOOLEAN NOT NULL DEFAULT TRUE,
-            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
-            total_requests INTEGER NOT NULL DEFAULT 0,
-            total_input_tokens INTEGER NOT NULL DEFAULT 0,
-            total_output_tokens INTEGER NOT NULL DEFAULT 0
+        CREATE TABLE IF NOT EXISTS app_settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
         )
         "#,
     )
     .execute(pool)
     .await?;
 
+    // Create api_keys table
+    sqlx::query(&api_keys_table_sql("api_keys"))
+        .execute(pool)
+        .await?;
+    add_column_if_missing(pool, "api_keys", "rpm_limit", "INTEGER").await?;
+    add_column_if_missing(pool, "api_keys", "tpm_limit", "INTEGER").await?;
+    add_column_if_missing(pool, "api_keys", "max_concurrent", "INTEGER").await?;
+    add_column_if_missing(pool, "api_keys", "quota_period", "TEXT").await?;
+    add_column_if_missing(pool, "api_keys", "quota_tokens", "INTEGER").await?;
+    add_column_if_missing(pool, "api_keys", "quota_requests", "INTEGER").await?;
+    add_column_if_missing(pool, "api_keys", "budget_usd", "REAL").await?;
+    add_column_if_missing(pool, "api_keys", "allowed_models", "TEXT").await?;
+    let salt = load_api_key_salt(pool).await?;
+    let migrated_api_keys = migrate_plaintext_api_keys(pool, &salt).await?;
+
     // Create usage_logs table
     sqlx::query(
         r#"
@@ -295,366 +1150,2868 @@ async fn initialize_database(pool: &SqlitePool) -> anyhow::Result<()> {
     )
     .execute(pool)
     .await?;
+    add_column_if_missing(pool, "usage_logs", "cost_usd", "REAL NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "usage_logs", "cached_tokens", "INTEGER NOT NULL DEFAULT 0").await?;
 
-    info!("Database initialized successfully");
-    Ok(())
-}
+    // Quota checks sum a key's usage since the start of the current period
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_usage_logs_api_key_timestamp ON usage_logs (api_key_id, timestamp)"
+    )
+    .execute(pool)
+    .await?;
 
-async fn get_or_create_api_key(pool: &SqlitePool, api_key: &str) -> anyhow::Result<Uuid> {
-    // Try to find existing API key
-    if let Some(row) = sqlx::query("SELECT id FROM api_keys WHERE api_key = ?")
-        .bind(api_key)
-        .fetch_optional(pool)
-        .await?
-    {
-        let id_str: String = row.get("id");
-        return Ok(Uuid::parse_str(&id_str)?);
+    // Usage analytics scan a time range across all keys
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_usage_logs_timestamp_api_key ON usage_logs (timestamp, api_key_id)"
+    )
+    .execute(pool)
+    .await?;
+
+    // Create model_aliases table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS model_aliases (
+            alias TEXT PRIMARY KEY,
+            model TEXT NOT NULL,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create model_prices table, seeded with the default prices
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS model_prices (
+            model TEXT PRIMARY KEY,
+            input_per_million REAL NOT NULL,
+            output_per_million REAL NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    let added_cached_prices =
+        add_column_if_missing(pool, "model_prices", "cached_input_per_million", "REAL").await?;
+    for (model, input, cached, output) in DEFAULT_PRICES {
+        sqlx::query(
+            "INSERT OR IGNORE INTO model_prices (model, input_per_million, cached_input_per_million, output_per_million)
+             VALUES (?, ?, ?, ?)"
+        )
+        .bind(model)
+        .bind(input)
+        .bind(cached)
+        .bind(output)
+        .execute(pool)
+        .await?;
+        if added_cached_prices {
+            // Seeded before cached prices existed
+            sqlx::query("UPDATE model_prices SET cached_input_per_million = ? WHERE model = ?")
+                .bind(cached)
+                .bind(model)
+                .execute(pool)
+                .await?;
+        }
     }
 
-    // Create new API key entry
-    let id = Uuid::new_v4();
-    let key_name = format!("API Key {}", &api_key[..8.min(api_key.len())]);
-    
+    // Create upstream_keys table
+    sqlx::query(&upstream_keys_table_sql("upstream_keys"))
+        .execute(pool)
+        .await?;
+    let migrated_upstream_keys = migrate_plaintext_upstream_keys(pool, master_key, &salt).await?;
+
+    // Hashes of every upstream key the pool has had, so that keys deleted by
+    // an admin are not imported from GEMINI_API_KEYS again
     sqlx::query(
-        "INSERT INTO api_keys (id, key_name, api_key) VALUES (?, ?, ?)"
+        r#"
+        CREATE TABLE IF NOT EXISTS upstream_key_imports (
+            key_hash TEXT PRIMARY KEY,
+            imported_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
     )
-    .bind(id.to_string())
-    .bind(&key_name)
-    .bind(api_key)
     .execute(pool)
     .await?;
 
-    Ok(id)
-}
+    // Create admin_users table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_users (
+            id TEXT PRIMARY KEY,
+            username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            password_hash TEXT NOT NULL,
+            role TEXT NOT NULL,
+            is_active BOOLEAN NOT NULL DEFAULT TRUE,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            last_login_at DATETIME
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
 
-async fn log_usage(
-    pool: &SqlitePool,
-    api_key_id: Uuid,
-    endpoint: &str,
-    model: &str,
-    input_tokens: i32,
-    output_tokens: i32,
-    success: bool,
-) -> anyhow::Result<()> {
-    let log_id = Uuid::new_v4();
-    
-    // Insert usage log
+    // Two-factor login: the TOTP secret is sealed with the master key
+    add_column_if_missing(pool, "admin_users", "totp_secret", "TEXT").await?;
+    add_column_if_missing(pool, "admin_users", "totp_wrapped_key", "TEXT").await?;
+    add_column_if_missing(pool, "admin_users", "totp_enabled", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+    add_column_if_missing(pool, "admin_users", "totp_last_step", "INTEGER").await?;
+    add_column_if_missing(pool, "admin_users", "totp_failures", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "admin_users", "totp_locked_until", "DATETIME").await?;
+
+    // Create admin_recovery_codes table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_recovery_codes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id TEXT NOT NULL,
+            code_hash TEXT NOT NULL,
+            used_at DATETIME,
+            FOREIGN KEY (user_id) REFERENCES admin_users (id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create admin_sessions table; only refresh token hashes are stored
     sqlx::query(
-        "INSERT INTO usage_logs (id, api_key_id, endpoint, model, input_tokens, output_tokens, success) 
-         VALUES (?, ?, ?, ?, ?, ?, ?)"
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_sessions (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            refresh_token_hash TEXT NOT NULL UNIQUE,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            expires_at DATETIME NOT NULL,
+            last_used_at DATETIME,
+            revoked_at DATETIME,
+            FOREIGN KEY (user_id) REFERENCES admin_users (id)
+        )
+        "#,
     )
-    .bind(log_id.to_string())
-    .bind(api_key_id.to_string())
-    .bind(endpoint)
-    .bind(model)
-    .bind(input_tokens)
-    .bind(output_tokens)
-    .bind(success)
     .execute(pool)
     .await?;
 
-    // Update totals in api_keys table
+    // Create upstream_attempts table
     sqlx::query(
-        "UPDATE api_keys 
-         SET total_requests = total_requests + 1,
-             total_input_tokens = total_input_tokens + ?,
-             total_output_tokens = total_output_tokens + ?
-         WHERE id = ?"
+        r#"
+        CREATE TABLE IF NOT EXISTS upstream_attempts (
+            id TEXT PRIMARY KEY,
+            request_id TEXT NOT NULL,
+            attempt INTEGER NOT NULL,
+            upstream_key_id TEXT NOT NULL DEFAULT '',
+            timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            endpoint TEXT NOT NULL,
+            model TEXT NOT NULL,
+            status_code INTEGER,
+            error TEXT,
+            latency_ms INTEGER NOT NULL DEFAULT 0,
+            success BOOLEAN NOT NULL DEFAULT FALSE
+        )
+        "#,
     )
-    .bind(input_tokens)
-    .bind(output_tokens)
-    .bind(api_key_id.to_string())
     .execute(pool)
     .await?;
+    add_column_if_missing(pool, "upstream_attempts", "upstream_key_id", "TEXT NOT NULL DEFAULT ''").await?;
+    // Earlier versions kept the masked upstream key next to its id
+    let dropped_masked_keys = drop_column_if_exists(pool, "upstream_attempts", "upstream_key").await?;
+
+    if migrated_api_keys || migrated_upstream_keys || dropped_masked_keys {
+        // Free pages still hold the old plaintext keys until the file is rewritten
+        sqlx::query("VACUUM").execute(pool).await?;
+    }
 
+    info!("Database initialized successfully");
     Ok(())
 }
 
-//================================================================================
-// Authentication Middleware
-//================================================================================
-
-/// Middleware to verify API key in Authorization header
-async fn auth_middleware(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-    mut request: axum::extract::Request,
-    next: axum::middleware::Next,
-) -> Result<axum::response::Response, AppError> {
-    // Check Authorization header
-    let auth_header = headers
-        .get("authorization")
-        .and_then(|value| value.to_str().ok())
-        .ok_or_else(|| AppError::BadRequest("Missing Authorization header".to_string()))?;
-
-    // Expect "Bearer <api_key>" format
-    if !auth_header.starts_with("Bearer ") {
-        return Err(AppError::BadRequest("Invalid Authorization header format. Expected: Bearer <api_key>".to_string()));
-    }
+async fn log_usage(pool: &SqlitePool, log: &UsageLog) -> anyhow::Result<()> {
+    // Insert usage log, keeping the same timestamp format as CURRENT_TIMESTAMP
+    sqlx::query(
+        "INSERT INTO usage_logs (id, api_key_id, timestamp, endpoint, model, input_tokens, cached_tokens, output_tokens, cost_usd, success) 
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(log.id.to_string())
+    .bind(log.api_key_id.to_string())
+    .bind(log.timestamp.format("%Y-%m-%d %H:%M:%S").to_string())
+    .bind(&log.endpoint)
+    .bind(&log.model)
+    .bind(log.input_tokens)
+    .bind(log.cached_tokens)
+    .bind(log.output_tokens)
+    .bind(log.cost_usd)
+    .bind(log.success)
+    .execute(pool)
+    .await?;
 
-    let provided_key = auth_header.strip_prefix("Bearer ").unwrap();
-    
-    // Verify the API key exists in database and is active
-    let api_key_result = sqlx::query("SELECT id FROM api_keys WHERE api_key = ? AND is_active = TRUE")
-        .bind(provided_key)
-        .fetch_optional(&state.db_pool)
-        .await
-        .map_err(|e| AppError::Internal(e.into()))?;
+    // Update totals in api_keys table
+    sqlx::query(
+        "UPDATE api_keys 
+         SET total_requests = total_requests + 1,
+             total_input_tokens = total_input_tokens + ?,
+             total_output_tokens = total_output_tokens + ?
+         WHERE id = ?"
+    )
+    .bind(log.input_tokens)
+    .bind(log.output_tokens)
+    .bind(log.api_key_id.to_string())
+    .execute(pool)
+    .await?;
 
-    let api_key_id = api_key_result
-        .ok_or_else(|| AppError::BadRequest("Invalid or inactive API key".to_string()))?
-        .get::<String, _>("id");
+    Ok(())
+}
 
-    // Store the API key ID in request extensions for later use
-    request.extensions_mut().insert(api_key_id);
+async fn load_model_prices(pool: &SqlitePool) -> anyhow::Result<Vec<ModelPrice>> {
+    let rows = sqlx::query("SELECT model, input_per_million, cached_input_per_million, output_per_million FROM model_prices")
+        .fetch_all(pool)
+        .await?;
 
-    // If authentication successful, continue to the handler
-    Ok(next.run(request).await)
+    Ok(rows
+        .into_iter()
+        .map(|row| ModelPrice {
+            model: row.get("model"),
+            input_per_million: row.get("input_per_million"),
+            cached_input_per_million: row.get("cached_input_per_million"),
+            output_per_million: row.get("output_per_million"),
+        })
+        .collect())
 }
 
-//================================================================================
-// Admin API Handlers
-//================================================================================
-
-async fn admin_login(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, AppError> {
-    // Verify credentials
-    if payload.username != state.admin_username || payload.password != state.admin_password {
-        return Err(AppError::BadRequest("Invalid credentials".to_string()));
-    }
+async fn load_model_aliases(pool: &SqlitePool) -> anyhow::Result<Vec<ModelAlias>> {
+    let rows = sqlx::query("SELECT alias, model FROM model_aliases")
+        .fetch_all(pool)
+        .await?;
 
-    // Create JWT token
-    let claims = Claims {
-        sub: payload.username,
-        exp: (chrono::Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
-    };
+    Ok(rows
+        .into_iter()
+        .map(|row| ModelAlias {
+            alias: row.get("alias"),
+            model: row.get("model"),
+        })
+        .collect())
+}
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(state.jwt_secret.as_ref()),
+/// Sums a key's usage in the current quota period. Failed requests still
+/// spend their upstream tokens, so only the request count skips them.
+async fn quota_usage(pool: &SqlitePool, api_key_id: &str, period: QuotaPeriod) -> anyhow::Result<QuotaUsage> {
+    let now = Utc::now();
+    let row = sqlx::query(
+        "SELECT COALESCE(SUM(success = TRUE), 0) AS requests,
+                COALESCE(SUM(input_tokens + output_tokens), 0) AS tokens,
+                COALESCE(SUM(cost_usd), 0.0) AS cost_usd
+         FROM usage_logs
+         WHERE api_key_id = ? AND timestamp >= ?"
     )
-    .map_err(|e| AppError::Internal(e.into()))?;
+    .bind(api_key_id)
+    .bind(period.start(now).format("%Y-%m-%d %H:%M:%S").to_string())
+    .fetch_one(pool)
+    .await?;
 
-    Ok(Json(LoginResponse { token }))
+    Ok(QuotaUsage {
+        requests: row.get("requests"),
+        tokens: row.get("tokens"),
+        cost_usd: row.get("cost_usd"),
+        resets_at: period.next_reset(now),
+    })
 }
 
-async fn admin_verify_token(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let auth_header = headers
-        .get("authorization")
-        .and_then(|value| value.to_str().ok())
-        .ok_or_else(|| AppError::BadRequest("Missing Authorization header".to_string()))?;
-
-    if !auth_header.starts_with("Bearer ") {
-        return Err(AppError::BadRequest("Invalid Authorization header format".to_string()));
-    }
-
-    let token = auth_header.strip_prefix("Bearer ").unwrap();
-    
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(state.jwt_secret.as_ref()),
-        &Validation::default(),
+/// Sums the usage of every key with a quota period in that key's current
+/// period, in a single query, counted like `quota_usage`. Keys without usage
+/// in their period are left out.
+async fn quota_usage_by_key(pool: &SqlitePool) -> anyhow::Result<HashMap<String, QuotaUsage>> {
+    let now = Utc::now();
+    let start = |period: QuotaPeriod| period.start(now).format("%Y-%m-%d %H:%M:%S").to_string();
+    let rows = sqlx::query(
+        "SELECT u.api_key_id, k.quota_period,
+                COALESCE(SUM(u.success = TRUE), 0) AS requests,
+                COALESCE(SUM(u.input_tokens + u.output_tokens), 0) AS tokens,
+                COALESCE(SUM(u.cost_usd), 0.0) AS cost_usd
+         FROM usage_logs u
+         JOIN api_keys k ON k.id = u.api_key_id
+         WHERE u.timestamp >= CASE k.quota_period WHEN 'day' THEN ? WHEN 'month' THEN ? END
+         GROUP BY u.api_key_id, k.quota_period"
     )
-    .map_err(|_| AppError::BadRequest("Invalid token".to_string()))?;
+    .bind(start(QuotaPeriod::Day))
+    .bind(start(QuotaPeriod::Month))
+    .fetch_all(pool)
+    .await?;
 
-    Ok(Json(serde_json::json!({"status": "valid"})))
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let period = QuotaPeriod::parse(&row.get::<String, _>("quota_period"))?;
+            let usage = QuotaUsage {
+                requests: row.get("requests"),
+                tokens: row.get("tokens"),
+                cost_usd: row.get("cost_usd"),
+                resets_at: period.next_reset(now),
+            };
+            Some((row.get("api_key_id"), usage))
+        })
+        .collect())
 }
 
-async fn admin_dashboard(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<DashboardStats>, AppError> {
-    let total_api_keys = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM api_keys")
-        .fetch_one(&state.db_pool)
-        .await
-        .unwrap_or(0);
+/// Adds a column to a table created by an earlier version, if it is not there yet.
+/// Returns whether the column was added.
+async fn add_column_if_missing(pool: &SqlitePool, table: &str, column: &str, definition: &str) -> anyhow::Result<bool> {
+    let exists = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?"
+    )
+    .bind(table)
+    .bind(column)
+    .fetch_one(pool)
+    .await?;
 
-    let total_requests = sqlx::query_scalar::<_, i64>("SELECT SUM(total_requests) FROM api_keys")
-        .fetch_one(&state.db_pool)
-        .await
-        .unwrap_or(0);
+    if exists == 0 {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await?;
+    }
 
-    let total_input_tokens = sqlx::query_scalar::<_, i64>("SELECT SUM(total_input_tokens) FROM api_keys")
-        .fetch_one(&state.db_pool)
-        .await
-        .unwrap_or(0);
+    Ok(exists == 0)
+}
 
-    let total_output_tokens = sqlx::query_scalar::<_, i64>("SELECT SUM(total_output_tokens) FROM api_keys")
-        .fetch_one(&state.db_pool)
-        .await
-        .unwrap_or(0);
+/// Drops a column that an earlier version created, if it is still there.
+/// Returns whether the column was dropped.
+async fn drop_column_if_exists(pool: &SqlitePool, table: &str, column: &str) -> anyhow::Result<bool> {
+    let exists = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?"
+    )
+    .bind(table)
+    .bind(column)
+    .fetch_one(pool)
+    .await?;
 
-    let active_keys = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM api_keys WHERE is_active = TRUE")
-        .fetch_one(&state.db_pool)
-        .await
-        .unwrap_or(0);
+    if exists > 0 {
+        sqlx::query(&format!("ALTER TABLE {} DROP COLUMN {}", table, column))
+            .execute(pool)
+            .await?;
+    }
 
-    Ok(Json(DashboardStats {
-        total_api_keys,
-        total_requests,
-        total_tokens: total_input_tokens + total_output_tokens,
-        active_keys,
-    }))
+    Ok(exists > 0)
 }
 
-async fn admin_list_api_keys(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<ApiKeysResponse>, AppError> {
-    let rows = sqlx::query(
-        "SELECT id, key_name, api_key, is_active, created_at, total_requests, total_input_tokens, total_output_tokens 
-         FROM api_keys ORDER BY created_at DESC"
+fn api_keys_table_sql(table: &str) -> String {
+    format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS {} (
+            id TEXT PRIMARY KEY,
+            key_name TEXT NOT NULL,
+            key_hash TEXT NOT NULL UNIQUE,
+            key_prefix TEXT NOT NULL,
+            is_active BOOLEAN NOT NULL DEFAULT TRUE,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            total_requests INTEGER NOT NULL DEFAULT 0,
+            total_input_tokens INTEGER NOT NULL DEFAULT 0,
+            total_output_tokens INTEGER NOT NULL DEFAULT 0,
+            rpm_limit INTEGER,
+            tpm_limit INTEGER,
+            max_concurrent INTEGER,
+            quota_period TEXT,
+            quota_tokens INTEGER,
+            quota_requests INTEGER,
+            budget_usd REAL,
+            allowed_models TEXT
+        )
+        "#,
+        table
     )
-    .fetch_all(&state.db_pool)
-    .await
-    .map_err(|e| AppError::Internal(e.into()))?;
+}
 
-    let api_keys: Vec<ApiKey> = rows
-        .into_iter()
-        .map(|row| ApiKey {
-            id: Uuid::parse_str(&row.get::<String, _>("id")).unwrap(),
-            key_name: row.get("key_name"),
-            api_key: row.get("api_key"),
-            is_active: row.get("is_active"),
-            created_at: row.get("created_at"),
-            total_requests: row.get("total_requests"),
-            total_input_tokens: row.get("total_input_tokens"),
-            total_output_tokens: row.get("total_output_tokens"),
-        })
-        .collect();
+/// Returns the salt client keys are hashed with, generating it on first start.
+async fn load_api_key_salt(pool: &SqlitePool) -> anyhow::Result<String> {
+    sqlx::query("INSERT OR IGNORE INTO app_settings (key, value) VALUES ('api_key_salt', ?)")
+        .bind(client_keys::generate_salt())
+        .execute(pool)
+        .await?;
 
-    Ok(Json(ApiKeysResponse { api_keys }))
+    let salt = sqlx::query_scalar::<_, String>("SELECT value FROM app_settings WHERE key = 'api_key_salt'")
+        .fetch_one(pool)
+        .await?;
+    Ok(salt)
 }
 
-async fn admin_get_api_key(
-    State(state): State<Arc<AppState>>,
-    Path(key_id): Path<String>,
-) -> Result<Json<ApiKey>, AppError> {
-    let row = sqlx::query(
-        "SELECT id, key_name, api_key, is_active, created_at, total_requests, total_input_tokens, total_output_tokens 
-         FROM api_keys WHERE id = ?"
+/// Rebuilds an `api_keys` table from older versions, which stored client keys
+/// in plain text, with hashed keys instead. Existing keys keep working.
+/// Returns whether anything was migrated.
+async fn migrate_plaintext_api_keys(pool: &SqlitePool, salt: &str) -> anyhow::Result<bool> {
+    let has_plaintext = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM pragma_table_info('api_keys') WHERE name = 'api_key'"
     )
-    .bind(&key_id)
-    .fetch_optional(&state.db_pool)
-    .await
-    .map_err(|e| AppError::Internal(e.into()))?
-    .ok_or_else(|| AppError::BadRequest("API Key not found".to_string()))?;
+    .fetch_one(pool)
+    .await?;
+    if has_plaintext == 0 {
+        return Ok(false);
+    }
 
-    let api_key = ApiKey {
-        id: Uuid::parse_str(&row.get::<String, _>("id")).unwrap(),
-        key_name: row.get("key_name"),
-        api_key: row.get("api_key"),
-        is_active: row.get("is_active"),
-        created_at: row.get("created_at"),
-        total_requests: row.get("total_requests"),
-        total_input_tokens: row.get("total_input_tokens"),
-        total_output_tokens: row.get("total_output_tokens"),
-    };
+    // usage_logs references api_keys, so foreign keys are switched off while
+    // the table is swapped. The pragma only applies to this connection.
+    let mut conn = pool.acquire().await?;
+    sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await?;
 
-    Ok(Json(api_key))
-}
+    let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+    sqlx::query(&api_keys_table_sql("api_keys_hashed"))
+        .execute(&mut *tx)
+        .await?;
+    let rows = sqlx::query("SELECT id, api_key FROM api_keys")
+        .fetch_all(&mut *tx)
+        .await?;
+    for row in &rows {
+        let api_key: String = row.get("api_key");
+        sqlx::query(
+            "INSERT INTO api_keys_hashed (id, key_name, key_hash, key_prefix, is_active, created_at,
+                                          total_requests, total_input_tokens, total_output_tokens,
+                                          rpm_limit, tpm_limit, max_concurrent, quota_period, quota_tokens,
+                                          quota_requests, budget_usd, allowed_models)
+             SELECT id, key_name, ?, ?, is_active, created_at,
+                    total_requests, total_input_tokens, total_output_tokens,
+                    rpm_limit, tpm_limit, max_concurrent, quota_period, quota_tokens,
+                    quota_requests, budget_usd, allowed_models
+             FROM api_keys WHERE id = ?"
+        )
+        .bind(client_keys::hash_api_key(salt, &api_key))
+        .bind(client_keys::display_prefix(&api_key))
+        .bind(row.get::<String, _>("id"))
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query("DROP TABLE api_keys").execute(&mut *tx).await?;
+    sqlx::query("ALTER TABLE api_keys_hashed RENAME TO api_keys")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
 
-async fn admin_create_api_key(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<CreateApiKeyRequest>,
-) -> Result<Json<CreateApiKeyResponse>, AppError> {
-    let api_key = payload.api_key.unwrap_or_else(|| {
-        // Generate a random API key
-        format!("gp_{}", uuid::Uuid::new_v4().to_string().replace("-", ""))
-    });
+    sqlx::query("PRAGMA foreign_keys = ON").execute(&mut *conn).await?;
 
-    let id = Uuid::new_v4();
+    info!("Migrated {} client API keys to hashed storage", rows.len());
+    Ok(true)
+}
 
-    sqlx::query(
-        "INSERT INTO api_keys (id, key_name, api_key) VALUES (?, ?, ?)"
+fn upstream_keys_table_sql(table: &str) -> String {
+    format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS {} (
+            id TEXT PRIMARY KEY,
+            label TEXT NOT NULL DEFAULT '',
+            key_hash TEXT NOT NULL UNIQUE,
+            key_ciphertext TEXT NOT NULL,
+            wrapped_key TEXT NOT NULL,
+            notes TEXT NOT NULL DEFAULT '',
+            is_active BOOLEAN NOT NULL DEFAULT TRUE,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            success_count INTEGER NOT NULL DEFAULT 0,
+            error_count INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT,
+            last_error_at DATETIME,
+            last_used_at DATETIME
+        )
+        "#,
+        table
     )
-    .bind(id.to_string())
-    .bind(&payload.key_name)
-    .bind(&api_key)
-    .execute(&state.db_pool)
-    .await
-    .map_err(|e| AppError::Internal(e.into()))?;
-
-    Ok(Json(CreateApiKeyResponse {
-        id: id.to_string(),
-        api_key,
-    }))
 }
 
-async fn admin_update_api_key(
-    State(state): State<Arc<AppState>>,
-    Path(key_id): Path<String>,
-    Json(payload): Json<UpdateApiKeyRequest>,
-) -> Result<StatusCode, AppError> {
-    let result = sqlx::query(
-        "UPDATE api_keys SET key_name = ?, is_active = ? WHERE id = ?"
+/// Rebuilds an `upstream_keys` table from older versions, which stored keys in
+/// plain text, with encrypted keys instead. Returns whether anything was migrated.
+async fn migrate_plaintext_upstream_keys(pool: &SqlitePool, master_key: &MasterKey, salt: &str) -> anyhow::Result<bool> {
+    let has_plaintext = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM pragma_table_info('upstream_keys') WHERE name = 'api_key'"
     )
-    .bind(&payload.key_name)
-    .bind(payload.is_active)
-    .bind(&key_id)
-    .execute(&state.db_pool)
-    .await
-    .map_err(|e| AppError::Internal(e.into()))?;
+    .fetch_one(pool)
+    .await?;
+    if has_plaintext == 0 {
+        return Ok(false);
+    }
 
-    if result.rows_affected() == 0 {
-        return Err(AppError::BadRequest("API Key not found".to_string()));
+    let mut tx = pool.begin().await?;
+    sqlx::query(&upstream_keys_table_sql("upstream_keys_encrypted"))
+        .execute(&mut *tx)
+        .await?;
+    let rows = sqlx::query("SELECT id, api_key FROM upstream_keys")
+        .fetch_all(&mut *tx)
+        .await?;
+    for row in &rows {
+        let api_key: String = row.get("api_key");
+        let sealed = master_key.seal(&api_key);
+        sqlx::query(
+            "INSERT INTO upstream_keys_encrypted (id, label, key_hash, key_ciphertext, wrapped_key, notes, is_active, created_at,
+                                                 success_count, error_count, last_error, last_error_at, last_used_at)
+             SELECT id, label, ?, ?, ?, notes, is_active, created_at,
+                    success_count, error_count, last_error, last_error_at, last_used_at
+             FROM upstream_keys WHERE id = ?"
+        )
+        .bind(client_keys::hash_api_key(salt, &api_key))
+        .bind(&sealed.ciphertext)
+        .bind(&sealed.wrapped_key)
+        .bind(row.get::<String, _>("id"))
+        .execute(&mut *tx)
+        .await?;
     }
+    sqlx::query("DROP TABLE upstream_keys").execute(&mut *tx).await?;
+    sqlx::query("ALTER TABLE upstream_keys_encrypted RENAME TO upstream_keys")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
 
-    Ok(StatusCode::OK)
+    info!("Encrypted {} upstream keys with the master key", rows.len());
+    Ok(true)
 }
 
-async fn admin_delete_api_key(
-    State(state): State<Arc<AppState>>,
-    Path(key_id): Path<String>,
-) -> Result<StatusCode, AppError> {
-    let result = sqlx::query("DELETE FROM api_keys WHERE id = ?")
-        .bind(&key_id)
-        .execute(&state.db_pool)
-        .await
-        .map_err(|e| AppError::Internal(e.into()))?;
-
-    if result.rows_affected() == 0 {
-        return Err(AppError::BadRequest("API Key not found".to_string()));
+/// Re-encrypts every upstream key and two-factor secret with `new_key`, all
+/// or nothing. Returns the number of upstream keys and of secrets.
+async fn rotate_master_key(pool: &SqlitePool, old_key: &MasterKey, new_key: &MasterKey) -> anyhow::Result<(usize, usize)> {
+    let mut tx = pool.begin().await?;
+    let rows = sqlx::query("SELECT id, key_ciphertext, wrapped_key FROM upstream_keys")
+        .fetch_all(&mut *tx)
+        .await?;
+    for row in &rows {
+        let id: String = row.get("id");
+        let api_key = old_key
+            .open(row.get("key_ciphertext"), row.get("wrapped_key"))
+            .with_context(|| format!("failed to decrypt upstream key {}", id))?;
+        let sealed = new_key.seal(&api_key);
+        sqlx::query("UPDATE upstream_keys SET key_ciphertext = ?, wrapped_key = ? WHERE id = ?")
+            .bind(&sealed.ciphertext)
+            .bind(&sealed.wrapped_key)
+            .bind(&id)
+            .execute(&mut *tx)
+            .await?;
     }
 
-    // Also delete related usage logs
-    sqlx::query("DELETE FROM usage_logs WHERE api_key_id = ?")
-        .bind(&key_id)
-        .execute(&state.db_pool)
-        .await
-        .map_err(|e| AppError::Internal(e.into()))?;
+    let secrets = sqlx::query("SELECT id, totp_secret, totp_wrapped_key FROM admin_users WHERE totp_secret IS NOT NULL")
+        .fetch_all(&mut *tx)
+        .await?;
+    for row in &secrets {
+        let id: String = row.get("id");
+        let secret = old_key
+            .open(row.get("totp_secret"), row.get("totp_wrapped_key"))
+            .with_context(|| format!("failed to decrypt two-factor secret of admin user {}", id))?;
+        let sealed = new_key.seal(&secret);
+        sqlx::query("UPDATE admin_users SET totp_secret = ?, totp_wrapped_key = ? WHERE id = ?")
+            .bind(&sealed.ciphertext)
+            .bind(&sealed.wrapped_key)
+            .bind(&id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    // Drop the pages that still hold secrets encrypted under the old master key
+    sqlx::query("VACUUM").execute(pool).await?;
 
-    Ok(StatusCode::OK)
+    Ok((rows.len(), secrets.len()))
 }
 
-async fn admin_auth_middleware(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-    request: axum::extract::Request,
-    next: axum::middleware::Next,
-) -> Result<axum::response::Response, AppError> {
-    let auth_header = headers
-        .get("authorization")
-        .and_then(|value| value.to_str().ok())
-        .ok_or_else(|| AppError::BadRequest("Missing Authorization header".to_string()))?;
-
-    if !auth_header.starts_with("Bearer ") {
-        return Err(AppError::BadRequest("Invalid Authorization header format".to_string()));
+/// Creates the first owner from `ADMIN_USERNAME`/`ADMIN_PASSWORD` when there
+/// are no admin users yet. Afterwards the variables are ignored.
+async fn bootstrap_owner(pool: &SqlitePool, username: &str, password: Option<&str>) -> anyhow::Result<()> {
+    let users = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM admin_users")
+        .fetch_one(pool)
+        .await?;
+    if users > 0 {
+        return Ok(());
     }
 
-    let token = auth_header.strip_prefix("Bearer ").unwrap();
-    
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(state.jwt_secret.as_ref()),
-        &Validation::default(),
-    )
-    .map_err(|_| AppError::BadRequest("Invalid or expired token".to_string()))?;
+    let password = password.context("ADMIN_PASSWORD must be set to create the first admin user")?;
+    sqlx::query("INSERT INTO admin_users (id, username, password_hash, role) VALUES (?, ?, ?, ?)")
+        .bind(Uuid::new_v4().to_string())
+        .bind(username)
+        .bind(admin_users::hash_password(password)?)
+        .bind(AdminRole::Owner.as_str())
+        .execute(pool)
+        .await?;
 
+    info!("Created admin user '{}' with the owner role", username);
+    Ok(())
+}
+
+/// Imports keys from `GEMINI_API_KEYS` into `upstream_keys`. Each key is
+/// imported once: keys already in the table are left untouched, so their
+/// label, notes and status survive restarts, and deleted keys stay deleted.
+async fn seed_upstream_keys(pool: &SqlitePool, master_key: &MasterKey, salt: &str, api_keys: &[String]) -> anyhow::Result<()> {
+    // Keys in the table count as imported, including those from before the
+    // imports were recorded
+    sqlx::query("INSERT OR IGNORE INTO upstream_key_imports (key_hash) SELECT key_hash FROM upstream_keys")
+        .execute(pool)
+        .await?;
+
+    for api_key in api_keys {
+        let key_hash = client_keys::hash_api_key(salt, api_key);
+        let first_seen = sqlx::query("INSERT OR IGNORE INTO upstream_key_imports (key_hash) VALUES (?)")
+            .bind(&key_hash)
+            .execute(pool)
+            .await?
+            .rows_affected()
+            == 1;
+        if !first_seen {
+            continue;
+        }
+
+        let sealed = master_key.seal(api_key);
+        sqlx::query(
+            "INSERT OR IGNORE INTO upstream_keys (id, label, key_hash, key_ciphertext, wrapped_key) VALUES (?, ?, ?, ?, ?)"
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind("GEMINI_API_KEYS")
+        .bind(&key_hash)
+        .bind(&sealed.ciphertext)
+        .bind(&sealed.wrapped_key)
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Loads and decrypts the active upstream keys as `(id, key)` pairs, oldest first.
+async fn load_active_upstream_keys(pool: &SqlitePool, master_key: &MasterKey) -> anyhow::Result<Vec<(String, String)>> {
+    let rows = sqlx::query(
+        "SELECT id, key_ciphertext, wrapped_key FROM upstream_keys WHERE is_active = TRUE ORDER BY created_at, rowid"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            let id: String = row.get("id");
+            let api_key = master_key
+                .open(row.get("key_ciphertext"), row.get("wrapped_key"))
+                .with_context(|| format!("failed to decrypt upstream key {}", id))?;
+            Ok((id, api_key))
+        })
+        .collect()
+}
+
+async fn log_upstream_attempt(pool: &SqlitePool, attempt: &UpstreamAttempt) -> anyhow::Result<()> {
+    let timestamp = attempt.timestamp.format("%Y-%m-%d %H:%M:%S").to_string();
+
+    sqlx::query(
+        "INSERT INTO upstream_attempts (id, request_id, attempt, upstream_key_id, timestamp, endpoint, model, status_code, error, latency_ms, success)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(attempt.id.to_string())
+    .bind(attempt.request_id.to_string())
+    .bind(attempt.attempt)
+    .bind(&attempt.upstream_key_id)
+    .bind(&timestamp)
+    .bind(&attempt.endpoint)
+    .bind(&attempt.model)
+    .bind(attempt.status_code)
+    .bind(&attempt.error)
+    .bind(attempt.latency_ms)
+    .bind(attempt.success)
+    .execute(pool)
+    .await?;
+
+    // Update counters in upstream_keys table
+    if attempt.success {
+        sqlx::query(
+            "UPDATE upstream_keys
+             SET success_count = success_count + 1,
+                 last_used_at = ?
+             WHERE id = ?"
+        )
+        .bind(&timestamp)
+        .bind(&attempt.upstream_key_id)
+        .execute(pool)
+        .await?;
+    } else {
+        sqlx::query(
+            "UPDATE upstream_keys
+             SET error_count = error_count + 1,
+                 last_error = ?,
+                 last_error_at = ?,
+                 last_used_at = ?
+             WHERE id = ?"
+        )
+        .bind(&attempt.error)
+        .bind(&timestamp)
+        .bind(&timestamp)
+        .bind(&attempt.upstream_key_id)
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Reads the rate limits of a client key row.
+fn rate_limits_from_row(row: &sqlx::sqlite::SqliteRow) -> RateLimits {
+    let limit = |column: &str| row.get::<Option<i64>, _>(column).map(|v| v.clamp(0, u32::MAX as i64) as u32);
+    RateLimits {
+        rpm_limit: limit("rpm_limit"),
+        tpm_limit: limit("tpm_limit"),
+        max_concurrent: limit("max_concurrent"),
+    }
+}
+
+/// Reads the quota of a client key row.
+fn quota_from_row(row: &sqlx::sqlite::SqliteRow) -> Quota {
+    Quota {
+        quota_period: row
+            .get::<Option<String>, _>("quota_period")
+            .and_then(|period| QuotaPeriod::parse(&period)),
+        quota_tokens: row.get("quota_tokens"),
+        quota_requests: row.get("quota_requests"),
+        budget_usd: row.get("budget_usd"),
+    }
+}
+
+fn validate_quota(quota: &Quota) -> Result<(), AppError> {
+    let has_limit = quota.quota_tokens.is_some() || quota.quota_requests.is_some() || quota.budget_usd.is_some();
+    if has_limit && quota.quota_period.is_none() {
+        return Err(AppError::BadRequest(
+            "quota_period (\"day\" or \"month\") is required when a quota or budget is set".to_string(),
+        ));
+    }
+    if quota.quota_tokens.is_some_and(|v| v <= 0)
+        || quota.quota_requests.is_some_and(|v| v <= 0)
+        || quota.budget_usd.is_some_and(|v| v <= 0.0 || !v.is_finite())
+    {
+        return Err(AppError::BadRequest(
+            "Quotas and budgets must be positive; omit them to leave a key unlimited".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the model allowlist of a client key row, stored as a JSON array.
+fn allowed_models_from_row(row: &sqlx::sqlite::SqliteRow) -> Vec<String> {
+    row.get::<Option<String>, _>("allowed_models")
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Trims the patterns, drops empty ones and rejects malformed ones.
+fn normalize_allowed_models(patterns: Vec<String>) -> Result<Vec<String>, AppError> {
+    let patterns: Vec<String> = patterns
+        .into_iter()
+        .map(|pattern| pattern.trim().to_string())
+        .filter(|pattern| !pattern.is_empty())
+        .collect();
+    if let Some(invalid) = patterns.iter().find(|pattern| !is_valid_pattern(pattern)) {
+        return Err(AppError::BadRequest(format!("Invalid model pattern: {}", invalid)));
+    }
+    Ok(patterns)
+}
+
+fn validate_rate_limits(limits: &RateLimits) -> Result<(), AppError> {
+    let values = [limits.rpm_limit, limits.tpm_limit, limits.max_concurrent];
+    if values.contains(&Some(0)) {
+        return Err(AppError::BadRequest(
+            "Rate limits must be positive; omit a limit to leave it unlimited".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Model name used in metric labels: the model itself if it is priced, an
+/// alias target or in Gemini's model list, otherwise [`metrics::OTHER_MODEL`].
+fn metrics_model_label(state: &AppState, model: &str) -> String {
+    let known = model.is_empty()
+        || state.prices.contains(model)
+        || state.model_aliases.is_target(model)
+        || state.metrics.is_upstream_model(model);
+    if known { model.to_string() } else { metrics::OTHER_MODEL.to_string() }
+}
+
+/// Records a usage row for the client key resolved by `auth_middleware`.
+/// Failures are logged but never fail the request itself.
+async fn record_usage(
+    state: &AppState,
+    api_key_id: &str,
+    endpoint: &str,
+    model: &str,
+    usage: OpenAIUsage,
+    success: bool,
+) {
+    let (input_tokens, output_tokens) = (usage.prompt_tokens, usage.completion_tokens);
+    let cached_tokens = usage.prompt_tokens_details.cached_tokens;
+    state
+        .rate_limiter
+        .record_tokens(api_key_id, (input_tokens.max(0) + output_tokens.max(0)) as u64);
+    state
+        .metrics
+        .record_tokens(api_key_id, &metrics_model_label(state, model), input_tokens, output_tokens);
+
+    let api_key_id = match Uuid::parse_str(api_key_id) {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::error!("Invalid API key id in request extensions: {}", e);
+            return;
+        }
+    };
+
+    let log = UsageLog {
+        id: Uuid::new_v4(),
+        api_key_id,
+        timestamp: Utc::now(),
+        endpoint: endpoint.to_string(),
+        model: model.to_string(),
+        input_tokens,
+        cached_tokens,
+        output_tokens,
+        cost_usd: state.prices.cost(model, input_tokens, cached_tokens, output_tokens),
+        success,
+    };
+
+    if let Err(e) = log_usage(&state.db_pool, &log).await {
+        tracing::error!("Failed to log usage: {:?}", e);
+    }
+}
+
+//================================================================================
+// Authentication Middleware
+//================================================================================
+
+/// Middleware to verify API key in Authorization header
+async fn auth_middleware(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<axum::response::Response, AppError> {
+    // Addresses that guessed too many keys are turned away before any lookup
+    let client_ip = Subject::ip(state.login_guard.client_ip(peer.ip(), &headers));
+    check_lockout(&state, std::slice::from_ref(&client_ip))?;
+
+    // Check Authorization header
+    let auth_header = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| AppError::BadRequest("Missing Authorization header".to_string()))?;
+
+    // Expect "Bearer <api_key>" format
+    if !auth_header.starts_with("Bearer ") {
+        return Err(AppError::BadRequest("Invalid Authorization header format. Expected: Bearer <api_key>".to_string()));
+    }
+
+    let provided_key = auth_header.strip_prefix("Bearer ").unwrap();
+    
+    // Verify the API key exists in database and is active
+    let api_key_result = sqlx::query(
+        "SELECT id, rpm_limit, tpm_limit, max_concurrent, quota_period, quota_tokens, quota_requests, budget_usd, allowed_models
+         FROM api_keys WHERE key_hash = ? AND is_active = TRUE"
+    )
+    .bind(client_keys::hash_api_key(&state.api_key_salt, provided_key))
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    let Some(row) = api_key_result else {
+        state.login_guard.record_failure(&[client_ip]);
+        return Err(AppError::BadRequest("Invalid or inactive API key".to_string()));
+    };
+    let api_key_id = row.get::<String, _>("id");
+    if let Some(labels) = request.extensions().get::<RequestLabels>() {
+        labels.set_client_key(&api_key_id);
+    }
+
+    // Reject keys that used up their quota for the current period
+    let quota = quota_from_row(&row);
+    if let Some(period) = quota.active_period() {
+        let usage = quota_usage(&state.db_pool, &api_key_id, period)
+            .await
+            .map_err(AppError::Internal)?;
+        if let Some(exceeded) = usage.exceeded(&quota) {
+            return Ok(exceeded.into_response());
+        }
+    }
+
+    // Enforce the key's rate limits before doing any upstream work
+    let permit = match state.rate_limiter.check(&api_key_id, rate_limits_from_row(&row)) {
+        Ok(permit) => permit,
+        Err(exceeded) => return Ok(exceeded.into_response()),
+    };
+
+    // Store the client key in request extensions for later use
+    request.extensions_mut().insert(ClientKey {
+        id: api_key_id,
+        allowed_models: allowed_models_from_row(&row),
+    });
+
+    // If authentication successful, continue to the handler
+    let response = next.run(request).await;
+    let (mut parts, body) = response.into_parts();
+    permit.status.apply_headers(&mut parts.headers);
+
+    // Hold the concurrency slot until the body, possibly an SSE stream, is done
+    let body = match permit.guard {
+        Some(guard) => Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _ = &guard;
+            chunk
+        })),
+        None => body,
+    };
+    Ok(Response::from_parts(parts, body))
+}
+
+/// Counts and times every API request for `/metrics`.
+async fn metrics_middleware(
+    State(state): State<Arc<AppState>>,
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+    let mut timer = state.metrics.start_request(&route);
+    request.extensions_mut().insert(timer.labels());
+
+    let response = next.run(request).await;
+    timer.set_status(response.status());
+
+    let is_stream = response
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"));
+    if !is_stream {
+        return response;
+    }
+
+    // A stream is done, and timed, when its last event has been sent
+    let (parts, body) = response.into_parts();
+    let body = Body::from_stream(body.into_data_stream().map(move |chunk| {
+        let _ = &timer;
+        chunk
+    }));
+    Response::from_parts(parts, body)
+}
+
+//================================================================================
+// Admin API Handlers
+//================================================================================
+
+async fn admin_login(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<LoginRequest>,
+) -> Result<Response, AppError> {
+    let subjects = [
+        Subject::ip(state.login_guard.client_ip(peer.ip(), &headers)),
+        Subject::username(&payload.username),
+    ];
+    check_lockout(&state, &subjects)?;
+
+    let user = sqlx::query(
+        "SELECT id, username, password_hash, totp_enabled FROM admin_users WHERE username = ? AND is_active = TRUE"
+    )
+    .bind(payload.username.trim())
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    // Verify credentials; unknown users are checked against a dummy hash so
+    // they take as long to reject as wrong passwords
+    let password_hash = user
+        .as_ref()
+        .map(|row| row.get::<String, _>("password_hash"))
+        .unwrap_or_else(|| admin_users::dummy_hash().to_string());
+    let valid = admin_users::verify_password(&password_hash, &payload.password);
+    let user = match user {
+        Some(user) if valid => user,
+        _ => {
+            state.login_guard.record_failure(&subjects);
+            return Err(AppError::BadRequest("Invalid credentials".to_string()));
+        }
+    };
+    let user_id: String = user.get("id");
+    state.login_guard.record_success(&subjects[1]);
+
+    // With two-factor login, the password only earns a short-lived token for
+    // the second step
+    if user.get::<bool, _>("totp_enabled") {
+        let claims = TotpChallengeClaims {
+            uid: user_id,
+            purpose: TOTP_CHALLENGE_PURPOSE.to_string(),
+            use_cookies: payload.use_cookies,
+            exp: (Utc::now().timestamp() + TOTP_CHALLENGE_TTL_SECS) as usize,
+        };
+        let totp_token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(state.jwt_secret.as_ref()),
+        )
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+        return Ok(Json(TotpChallengeResponse {
+            totp_required: true,
+            totp_token,
+            expires_in: TOTP_CHALLENGE_TTL_SECS as u64,
+        })
+        .into_response());
+    }
+
+    start_admin_session(&state, &user_id, &user.get::<String, _>("username"), payload.use_cookies).await
+}
+
+const TOTP_CHALLENGE_PURPOSE: &str = "totp_challenge";
+const TOTP_CHALLENGE_TTL_SECS: i64 = 5 * 60;
+const TOTP_ISSUER: &str = "Gemini Pool";
+
+/// Second login step for users with two-factor login: checks a TOTP code or
+/// a recovery code against the intermediate token from `admin_login`.
+async fn admin_login_totp(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<TotpLoginRequest>,
+) -> Result<Response, AppError> {
+    let claims = decode::<TotpChallengeClaims>(
+        &payload.totp_token,
+        &DecodingKey::from_secret(state.jwt_secret.as_ref()),
+        &Validation::default(),
+    )
+    .map_err(|_| AppError::Unauthorized("Invalid or expired two-factor token".to_string()))?
+    .claims;
+    if claims.purpose != TOTP_CHALLENGE_PURPOSE {
+        return Err(AppError::Unauthorized("Invalid or expired two-factor token".to_string()));
+    }
+
+    let user = sqlx::query(
+        r#"
+        SELECT id, username, totp_secret, totp_wrapped_key, totp_last_step, totp_failures, totp_locked_until
+        FROM admin_users
+        WHERE id = ? AND is_active = TRUE AND totp_enabled = TRUE
+        "#,
+    )
+    .bind(&claims.uid)
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?
+    .ok_or_else(|| AppError::Unauthorized("Invalid or expired two-factor token".to_string()))?;
+    let username: String = user.get("username");
+    let subjects = [
+        Subject::ip(state.login_guard.client_ip(peer.ip(), &headers)),
+        Subject::username(&username),
+    ];
+    check_lockout(&state, &subjects)?;
+
+    let now = Utc::now();
+    if let Some(locked_until) = user.get::<Option<DateTime<Utc>>, _>("totp_locked_until") {
+        if locked_until > now {
+            return Err(AppError::TooManyRequests {
+                message: "Too many invalid codes; try again later".to_string(),
+                retry_after: (locked_until - now).num_seconds().max(1) as u64,
+            });
+        }
+    }
+
+    // Count the attempt before checking the code. The increment is atomic and
+    // refused while locked, so parallel guesses cannot all pass the check above
+    // on the same count.
+    let attempts: Option<i64> = sqlx::query_scalar(
+        r#"
+        UPDATE admin_users SET totp_failures = totp_failures + 1
+        WHERE id = ? AND (totp_locked_until IS NULL OR totp_locked_until <= ?)
+        RETURNING totp_failures
+        "#,
+    )
+    .bind(&claims.uid)
+    .bind(now.format("%Y-%m-%d %H:%M:%S").to_string())
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+    let too_many = || AppError::TooManyRequests {
+        message: "Too many invalid codes; try again later".to_string(),
+        retry_after: totp::LOCKOUT.as_secs(),
+    };
+    let Some(attempts) = attempts else {
+        return Err(too_many());
+    };
+    if attempts > totp::MAX_FAILURES {
+        lock_totp(&state, &claims.uid, now).await?;
+        return Err(too_many());
+    }
+
+    let secret = state
+        .master_key
+        .open(user.get("totp_secret"), user.get("totp_wrapped_key"))
+        .map_err(AppError::Internal)?;
+    let step = totp::verify(&secret, &payload.code, now.timestamp(), user.get("totp_last_step"));
+    let valid = match step {
+        // Only one request can move the last step forward, so a code cannot
+        // be replayed by concurrent requests
+        Some(step) => sqlx::query(
+            "UPDATE admin_users SET totp_last_step = ? WHERE id = ? AND (totp_last_step IS NULL OR totp_last_step < ?)",
+        )
+        .bind(step)
+        .bind(&claims.uid)
+        .bind(step)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?
+        .rows_affected()
+            == 1,
+        None => use_recovery_code(&state, &claims.uid, &payload.code).await?,
+    };
+
+    if !valid {
+        state.login_guard.record_failure(&subjects);
+        if attempts >= totp::MAX_FAILURES {
+            lock_totp(&state, &claims.uid, now).await?;
+            tracing::warn!("Two-factor login for admin {} locked after {} invalid codes", username, attempts);
+        }
+        return Err(AppError::BadRequest("Invalid verification code".to_string()));
+    }
+
+    sqlx::query("UPDATE admin_users SET totp_failures = 0, totp_locked_until = NULL WHERE id = ?")
+        .bind(&claims.uid)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    state.login_guard.record_success(&subjects[1]);
+
+    start_admin_session(&state, &claims.uid, &username, claims.use_cookies).await
+}
+
+/// Locks two-factor login of a user for [`totp::LOCKOUT`] from `now`.
+async fn lock_totp(state: &AppState, user_id: &str, now: DateTime<Utc>) -> Result<(), AppError> {
+    let locked_until = now + chrono::Duration::from_std(totp::LOCKOUT).unwrap_or(chrono::Duration::minutes(5));
+    sqlx::query("UPDATE admin_users SET totp_failures = 0, totp_locked_until = ? WHERE id = ?")
+        .bind(locked_until.format("%Y-%m-%d %H:%M:%S").to_string())
+        .bind(user_id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    Ok(())
+}
+
+/// Rejects the attempt while any of `subjects` is locked out.
+fn check_lockout(state: &AppState, subjects: &[Subject]) -> Result<(), AppError> {
+    match state.login_guard.locked_for(subjects) {
+        Some(remaining) => Err(AppError::TooManyRequests {
+            message: "Too many failed attempts; try again later".to_string(),
+            retry_after: remaining.as_secs_f64().ceil().max(1.0) as u64,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Marks a matching unused recovery code of the user as used.
+async fn use_recovery_code(state: &AppState, user_id: &str, code: &str) -> Result<bool, AppError> {
+    let code_hash = client_keys::hash_api_key(&state.api_key_salt, &totp::normalize_recovery_code(code));
+    let result = sqlx::query(
+        "UPDATE admin_recovery_codes SET used_at = ? WHERE user_id = ? AND code_hash = ? AND used_at IS NULL"
+    )
+    .bind(Utc::now().format("%Y-%m-%d %H:%M:%S").to_string())
+    .bind(user_id)
+    .bind(&code_hash)
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    if result.rows_affected() > 0 {
+        info!("Admin user {} signed in with a recovery code", user_id);
+    }
+    Ok(result.rows_affected() > 0)
+}
+
+/// Replaces the recovery codes of a user and returns the new ones.
+async fn replace_recovery_codes(state: &AppState, user_id: &str) -> Result<Vec<String>, AppError> {
+    let codes = totp::generate_recovery_codes();
+    let mut tx = state.db_pool.begin().await.map_err(|e| AppError::Internal(e.into()))?;
+    sqlx::query("DELETE FROM admin_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    for code in &codes {
+        sqlx::query("INSERT INTO admin_recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(client_keys::hash_api_key(&state.api_key_salt, &totp::normalize_recovery_code(code)))
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+    }
+    tx.commit().await.map_err(|e| AppError::Internal(e.into()))?;
+
+    Ok(codes)
+}
+
+/// Records the login and hands out a new session.
+async fn start_admin_session(state: &AppState, user_id: &str, username: &str, use_cookies: bool) -> Result<Response, AppError> {
+    sqlx::query("UPDATE admin_users SET last_login_at = ? WHERE id = ?")
+        .bind(Utc::now().format("%Y-%m-%d %H:%M:%S").to_string())
+        .bind(user_id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    let (session_id, refresh_token) = create_admin_session(state, user_id).await?;
+    let access_token = issue_access_token(state, username, user_id, &session_id)?;
+
+    Ok(session_response(state, access_token, refresh_token, use_cookies))
+}
+
+/// Exchanges a refresh token, from the body or the refresh cookie, for a new
+/// access token. The refresh token is rotated: the old one stops working.
+async fn admin_refresh_token(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    payload: Option<Json<RefreshRequest>>,
+) -> Result<Response, AppError> {
+    let body_token = payload.and_then(|Json(payload)| payload.refresh_token);
+    let use_cookies = body_token.is_none();
+    let refresh_token = body_token
+        .or_else(|| admin_sessions::cookie_value(&headers, admin_sessions::REFRESH_COOKIE))
+        .ok_or_else(|| AppError::Unauthorized("Missing refresh token".to_string()))?;
+
+    let now = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let session = sqlx::query(
+        r#"
+        SELECT s.id, u.id AS user_id, u.username
+        FROM admin_sessions s
+        JOIN admin_users u ON u.id = s.user_id
+        WHERE s.refresh_token_hash = ? AND s.revoked_at IS NULL AND s.expires_at > ? AND u.is_active = TRUE
+        "#,
+    )
+    .bind(admin_sessions::hash_refresh_token(&refresh_token))
+    .bind(&now)
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?
+    .ok_or_else(|| AppError::Unauthorized("Invalid or expired refresh token".to_string()))?;
+    let session_id: String = session.get("id");
+    let user_id: String = session.get("user_id");
+
+    // Only one of several concurrent refreshes with the same token may rotate it
+    let new_refresh_token = admin_sessions::generate_refresh_token();
+    let rotated = sqlx::query(
+        "UPDATE admin_sessions SET refresh_token_hash = ?, last_used_at = ?
+         WHERE id = ? AND refresh_token_hash = ? AND revoked_at IS NULL"
+    )
+    .bind(admin_sessions::hash_refresh_token(&new_refresh_token))
+    .bind(&now)
+    .bind(&session_id)
+    .bind(admin_sessions::hash_refresh_token(&refresh_token))
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+    if rotated.rows_affected() != 1 {
+        return Err(AppError::Unauthorized("Invalid or expired refresh token".to_string()));
+    }
+
+    let access_token = issue_access_token(&state, &session.get::<String, _>("username"), &user_id, &session_id)?;
+    Ok(session_response(&state, access_token, new_refresh_token, use_cookies))
+}
+
+/// Revokes the current session, identified by its refresh token or access
+/// token (expired ones included), and clears the session cookies. Succeeds
+/// even without a session so that logging out never gets stuck.
+async fn admin_logout(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    payload: Option<Json<RefreshRequest>>,
+) -> Result<Response, AppError> {
+    let refresh_token = payload
+        .and_then(|Json(payload)| payload.refresh_token)
+        .or_else(|| admin_sessions::cookie_value(&headers, admin_sessions::REFRESH_COOKIE));
+    let now = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+
+    if let Some(refresh_token) = refresh_token {
+        sqlx::query("UPDATE admin_sessions SET revoked_at = ? WHERE refresh_token_hash = ? AND revoked_at IS NULL")
+            .bind(&now)
+            .bind(admin_sessions::hash_refresh_token(&refresh_token))
+            .execute(&state.db_pool)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+    } else if let Some(token) = access_token(&headers) {
+        let mut validation = Validation::default();
+        validation.validate_exp = false;
+        if let Ok(data) = decode::<Claims>(&token, &DecodingKey::from_secret(state.jwt_secret.as_ref()), &validation) {
+            sqlx::query("UPDATE admin_sessions SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL")
+                .bind(&now)
+                .bind(&data.claims.sid)
+                .execute(&state.db_pool)
+                .await
+                .map_err(|e| AppError::Internal(e.into()))?;
+        }
+    }
+
+    let mut response = StatusCode::OK.into_response();
+    for cookie in state.sessions.clear_cookies() {
+        response.headers_mut().append(http::header::SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// Signs the current admin out of every session, this one included.
+async fn admin_logout_all(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+) -> Result<Response, AppError> {
+    let revoked = revoke_admin_sessions(&state.db_pool, &admin.id, None).await?;
+    info!("Admin {} signed out of {} sessions", admin.username, revoked);
+
+    let mut response = StatusCode::OK.into_response();
+    for cookie in state.sessions.clear_cookies() {
+        response.headers_mut().append(http::header::SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// Starts a session for `user_id` and returns its id and refresh token.
+async fn create_admin_session(state: &AppState, user_id: &str) -> Result<(String, String), AppError> {
+    let now = Utc::now();
+    let expires_at = now + chrono::Duration::from_std(state.sessions.refresh_ttl).unwrap_or(chrono::Duration::days(7));
+    let session_id = Uuid::new_v4().to_string();
+    let refresh_token = admin_sessions::generate_refresh_token();
+
+    // Drop sessions that can no longer be used
+    sqlx::query("DELETE FROM admin_sessions WHERE expires_at <= ? OR revoked_at IS NOT NULL")
+        .bind(now.format("%Y-%m-%d %H:%M:%S").to_string())
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    sqlx::query(
+        "INSERT INTO admin_sessions (id, user_id, refresh_token_hash, created_at, expires_at) VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(&session_id)
+    .bind(user_id)
+    .bind(admin_sessions::hash_refresh_token(&refresh_token))
+    .bind(now.format("%Y-%m-%d %H:%M:%S").to_string())
+    .bind(expires_at.format("%Y-%m-%d %H:%M:%S").to_string())
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    Ok((session_id, refresh_token))
+}
+
+/// Revokes the sessions of `user_id`, except `keep` if given. Returns how many
+/// sessions were revoked.
+async fn revoke_admin_sessions(pool: &SqlitePool, user_id: &str, keep: Option<&str>) -> Result<u64, AppError> {
+    let result = sqlx::query(
+        "UPDATE admin_sessions SET revoked_at = ? WHERE user_id = ? AND id != ? AND revoked_at IS NULL"
+    )
+    .bind(Utc::now().format("%Y-%m-%d %H:%M:%S").to_string())
+    .bind(user_id)
+    .bind(keep.unwrap_or(""))
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    Ok(result.rows_affected())
+}
+
+fn issue_access_token(state: &AppState, username: &str, user_id: &str, session_id: &str) -> Result<String, AppError> {
+    let claims = Claims {
+        sub: username.to_string(),
+        uid: user_id.to_string(),
+        sid: session_id.to_string(),
+        exp: (Utc::now().timestamp() as u64 + state.sessions.access_ttl.as_secs()) as usize,
+    };
+
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(state.jwt_secret.as_ref()),
+    )
+    .map_err(|e| AppError::Internal(e.into()))
+}
+
+/// Hands out a token pair, either as HttpOnly cookies or in the JSON body.
+fn session_response(state: &AppState, access_token: String, refresh_token: String, use_cookies: bool) -> Response {
+    let expires_in = state.sessions.access_ttl.as_secs();
+    if !use_cookies {
+        return Json(LoginResponse {
+            token: Some(access_token),
+            refresh_token: Some(refresh_token),
+            expires_in,
+        })
+        .into_response();
+    }
+
+    let mut response = Json(LoginResponse {
+        token: None,
+        refresh_token: None,
+        expires_in,
+    })
+    .into_response();
+    for cookie in state.sessions.session_cookies(&access_token, &refresh_token) {
+        response.headers_mut().append(http::header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+async fn admin_verify_token(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user = authenticate_admin(&state, &headers).await?;
+    let totp_enabled = sqlx::query_scalar::<_, bool>("SELECT totp_enabled FROM admin_users WHERE id = ?")
+        .bind(&user.id)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    Ok(Json(serde_json::json!({
+        "status": "valid",
+        "id": user.id,
+        "username": user.username,
+        "role": user.role,
+        "totp_enabled": totp_enabled,
+    })))
+}
+
+/// The access token from the `Authorization` header, or else the access cookie.
+fn access_token(headers: &HeaderMap) -> Option<String> {
+    match headers.get("authorization").and_then(|value| value.to_str().ok()) {
+        Some(header) => header.strip_prefix("Bearer ").map(str::to_string),
+        None => admin_sessions::cookie_value(headers, admin_sessions::ACCESS_COOKIE),
+    }
+}
+
+/// Resolves the admin session of a request. The session and user are read
+/// from the database, so revoking a session, disabling a user or changing
+/// their role takes effect immediately.
+async fn authenticate_admin(state: &AppState, headers: &HeaderMap) -> Result<AdminUser, AppError> {
+    let token = access_token(headers)
+        .ok_or_else(|| AppError::Unauthorized("Missing or invalid Authorization header".to_string()))?;
+
+    let claims = decode::<Claims>(
+        &token,
+        &DecodingKey::from_secret(state.jwt_secret.as_ref()),
+        &Validation::default(),
+    )
+    .map_err(|_| AppError::Unauthorized("Invalid or expired token".to_string()))?
+    .claims;
+
+    let row = sqlx::query(
+        r#"
+        SELECT u.id, u.username, u.role
+        FROM admin_sessions s
+        JOIN admin_users u ON u.id = s.user_id
+        WHERE s.id = ? AND s.user_id = ? AND s.revoked_at IS NULL AND u.is_active = TRUE
+        "#,
+    )
+    .bind(&claims.sid)
+    .bind(&claims.uid)
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?
+    .ok_or_else(|| AppError::Unauthorized("Session revoked or admin user disabled".to_string()))?;
+
+    Ok(AdminUser {
+        id: row.get("id"),
+        username: row.get("username"),
+        role: AdminRole::parse(&row.get::<String, _>("role")).unwrap_or(AdminRole::Viewer),
+        session_id: claims.sid,
+    })
+}
+
+async fn admin_dashboard(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<DashboardStats>, AppError> {
+    let total_api_keys = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM api_keys")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap_or(0);
+
+    let total_requests = sqlx::query_scalar::<_, i64>("SELECT SUM(total_requests) FROM api_keys")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap_or(0);
+
+    let total_input_tokens = sqlx::query_scalar::<_, i64>("SELECT SUM(total_input_tokens) FROM api_keys")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap_or(0);
+
+    let total_output_tokens = sqlx::query_scalar::<_, i64>("SELECT SUM(total_output_tokens) FROM api_keys")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap_or(0);
+
+    let active_keys = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM api_keys WHERE is_active = TRUE")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap_or(0);
+
+    let retried_requests = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(DISTINCT request_id) FROM upstream_attempts WHERE attempt > 1",
+    )
+    .fetch_one(&state.db_pool)
+    .await
+    .unwrap_or(0);
+
+    let failover_saves = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(DISTINCT request_id) FROM upstream_attempts WHERE attempt > 1 AND success = TRUE",
+    )
+    .fetch_one(&state.db_pool)
+    .await
+    .unwrap_or(0);
+
+    let total_cost_usd = sqlx::query_scalar::<_, f64>("SELECT COALESCE(SUM(cost_usd), 0.0) FROM usage_logs")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap_or(0.0);
+
+    let month_start = UsageGrouping::Month.truncate(Utc::now());
+    let month_cost_usd = sqlx::query_scalar::<_, f64>(
+        "SELECT COALESCE(SUM(cost_usd), 0.0) FROM usage_logs WHERE timestamp >= ?",
+    )
+    .bind(month_start.format(usage::TIMESTAMP_FORMAT).to_string())
+    .fetch_one(&state.db_pool)
+    .await
+    .unwrap_or(0.0);
+
+    Ok(Json(DashboardStats {
+        total_api_keys,
+        total_requests,
+        total_tokens: total_input_tokens + total_output_tokens,
+        active_keys,
+        retried_requests,
+        failover_saves,
+        total_cost_usd,
+        month_cost_usd,
+        upstream_keys: state.key_pool.snapshot(),
+    }))
+}
+
+/// Conditions shared by the usage analytics queries. Optional filters match
+/// everything when bound to NULL.
+const USAGE_FILTER: &str = "timestamp >= ? AND timestamp < ?
+    AND (? IS NULL OR api_key_id = ?)
+    AND (? IS NULL OR model = ?)
+    AND (? IS NULL OR endpoint = ?)
+    AND (? IS NULL OR success = ?)";
+
+const USAGE_AMOUNTS: &str = "COUNT(*) AS requests,
+    COALESCE(SUM(CASE WHEN success THEN 0 ELSE 1 END), 0) AS failed_requests,
+    COALESCE(SUM(input_tokens), 0) AS input_tokens,
+    COALESCE(SUM(cached_tokens), 0) AS cached_tokens,
+    COALESCE(SUM(output_tokens), 0) AS output_tokens,
+    COALESCE(SUM(cost_usd), 0.0) AS cost_usd";
+
+fn usage_grouping(query: &UsageQuery) -> Result<UsageGrouping, AppError> {
+    match query.group_by.as_deref() {
+        None => Ok(UsageGrouping::Hour),
+        Some(value) => UsageGrouping::parse(value)
+            .ok_or_else(|| AppError::BadRequest("group_by must be hour, day or month".to_string())),
+    }
+}
+
+/// Parses the `from` or `to` of a usage query, if given.
+fn usage_bound(name: &str, value: &Option<String>) -> Result<Option<DateTime<Utc>>, AppError> {
+    match value.as_deref().filter(|v| !v.trim().is_empty()) {
+        Some(value) => usage::parse_time(value)
+            .map(Some)
+            .ok_or_else(|| AppError::BadRequest(format!("Invalid {}: expected RFC 3339 or YYYY-MM-DD", name))),
+        None => Ok(None),
+    }
+}
+
+/// Resolves the time range of a usage query. `to` defaults to the end of the
+/// current period and `from` to the default range of the grouping before it.
+fn usage_range(query: &UsageQuery, grouping: UsageGrouping) -> Result<(DateTime<Utc>, DateTime<Utc>), AppError> {
+    let to = usage_bound("to", &query.to)?.unwrap_or_else(|| grouping.next(grouping.truncate(Utc::now())));
+    let from = usage_bound("from", &query.from)?.unwrap_or(to - grouping.default_range());
+    if from >= to {
+        return Err(AppError::BadRequest("from must be before to".to_string()));
+    }
+    Ok((from, to))
+}
+
+fn usage_periods(grouping: UsageGrouping, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<DateTime<Utc>>, AppError> {
+    grouping.periods(from, to).ok_or_else(|| {
+        AppError::BadRequest(format!(
+            "Time range too long; at most {} periods per series, use a coarser group_by",
+            usage::MAX_PERIODS
+        ))
+    })
+}
+
+/// Client key names by id, for labelling usage by key.
+async fn client_key_names(pool: &SqlitePool) -> Result<HashMap<String, String>, AppError> {
+    Ok(sqlx::query("SELECT id, key_name FROM api_keys")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?
+        .iter()
+        .map(|row| (row.get("id"), row.get("key_name")))
+        .collect())
+}
+
+fn bind_usage_filter<'q>(
+    query: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    usage_query: &'q UsageQuery,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+    query
+        .bind(from.format(usage::TIMESTAMP_FORMAT).to_string())
+        .bind(to.format(usage::TIMESTAMP_FORMAT).to_string())
+        .bind(&usage_query.api_key_id)
+        .bind(&usage_query.api_key_id)
+        .bind(&usage_query.model)
+        .bind(&usage_query.model)
+        .bind(&usage_query.endpoint)
+        .bind(&usage_query.endpoint)
+        .bind(usage_query.success)
+        .bind(usage_query.success)
+}
+
+fn usage_amounts_from_row(row: &sqlx::sqlite::SqliteRow) -> UsageAmounts {
+    UsageAmounts {
+        requests: row.get("requests"),
+        failed_requests: row.get("failed_requests"),
+        input_tokens: row.get("input_tokens"),
+        cached_tokens: row.get("cached_tokens"),
+        output_tokens: row.get("output_tokens"),
+        cost_usd: row.get("cost_usd"),
+    }
+}
+
+async fn usage_totals(
+    pool: &SqlitePool,
+    query: &UsageQuery,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<UsageAmounts, AppError> {
+    let sql = format!("SELECT {} FROM usage_logs WHERE {}", USAGE_AMOUNTS, USAGE_FILTER);
+    let row = bind_usage_filter(sqlx::query(&sql), query, from, to)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    Ok(usage_amounts_from_row(&row))
+}
+
+async fn admin_usage_timeseries(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsageSeriesResponse>, AppError> {
+    let grouping = usage_grouping(&query)?;
+    let (from, to) = usage_range(&query, grouping)?;
+    let periods = usage_periods(grouping, from, to)?;
+
+    // The format comes from the grouping, never from the request
+    let sql = format!(
+        "SELECT strftime('{}', timestamp) AS period, {} FROM usage_logs WHERE {} GROUP BY period",
+        grouping.sql_format(),
+        USAGE_AMOUNTS,
+        USAGE_FILTER
+    );
+    let rows = bind_usage_filter(sqlx::query(&sql), &query, from, to)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    let mut by_period: HashMap<String, UsageAmounts> = rows
+        .iter()
+        .map(|row| (row.get::<String, _>("period"), usage_amounts_from_row(row)))
+        .collect();
+
+    let mut totals = UsageAmounts::default();
+    let points = periods
+        .into_iter()
+        .map(|start| {
+            let period = start.format(usage::TIMESTAMP_FORMAT).to_string();
+            let usage = by_period.remove(&period).unwrap_or_default();
+            totals.add(&usage);
+            UsagePoint { period, usage }
+        })
+        .collect();
+
+    Ok(Json(UsageSeriesResponse {
+        from: from.format(usage::TIMESTAMP_FORMAT).to_string(),
+        to: to.format(usage::TIMESTAMP_FORMAT).to_string(),
+        group_by: grouping,
+        points,
+        totals,
+    }))
+}
+
+async fn admin_usage_top(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsageBreakdownResponse>, AppError> {
+    let by = match query.by.as_deref() {
+        None => UsageDimension::ApiKey,
+        Some(value) => UsageDimension::parse(value)
+            .ok_or_else(|| AppError::BadRequest("by must be api_key, model or endpoint".to_string()))?,
+    };
+    let order_by = match query.order_by.as_deref() {
+        None => UsageOrder::Requests,
+        Some(value) => UsageOrder::parse(value)
+            .ok_or_else(|| AppError::BadRequest("order_by must be requests, tokens or cost".to_string()))?,
+    };
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    // A breakdown has no periods; the grouping only picks the default range
+    let (from, to) = usage_range(&query, usage_grouping(&query)?)?;
+
+    let sql = format!(
+        "SELECT {column} AS key, {amounts} FROM usage_logs WHERE {filter}
+         GROUP BY {column} ORDER BY {order} DESC, key LIMIT ?",
+        column = by.column(),
+        amounts = USAGE_AMOUNTS,
+        filter = USAGE_FILTER,
+        order = order_by.sql()
+    );
+    let rows = bind_usage_filter(sqlx::query(&sql), &query, from, to)
+        .bind(limit)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    let names = if by == UsageDimension::ApiKey {
+        client_key_names(&state.db_pool).await?
+    } else {
+        HashMap::new()
+    };
+
+    let mut listed = UsageAmounts::default();
+    let items: Vec<UsageBreakdownItem> = rows
+        .iter()
+        .map(|row| {
+            let key: String = row.get("key");
+            let usage = usage_amounts_from_row(row);
+            listed.add(&usage);
+            UsageBreakdownItem {
+                name: names.get(&key).cloned(),
+                key,
+                usage,
+            }
+        })
+        .collect();
+    let totals = usage_totals(&state.db_pool, &query, from, to).await?;
+
+    Ok(Json(UsageBreakdownResponse {
+        from: from.format(usage::TIMESTAMP_FORMAT).to_string(),
+        to: to.format(usage::TIMESTAMP_FORMAT).to_string(),
+        by,
+        order_by,
+        items,
+        others: totals.minus(&listed),
+        totals,
+    }))
+}
+
+/// Spend per period, client key and model, as JSON or as a CSV download.
+async fn admin_usage_spend(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Response, AppError> {
+    let csv = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(_) => return Err(AppError::BadRequest("format must be json or csv".to_string())),
+    };
+    let grouping = usage_grouping(&query)?;
+    let (from, to) = usage_range(&query, grouping)?;
+    usage_periods(grouping, from, to)?;
+
+    let sql = format!(
+        "SELECT strftime('{}', timestamp) AS period, api_key_id, model, {} FROM usage_logs WHERE {}
+         GROUP BY period, api_key_id, model ORDER BY period, cost_usd DESC, api_key_id, model",
+        grouping.sql_format(),
+        USAGE_AMOUNTS,
+        USAGE_FILTER
+    );
+    let rows = bind_usage_filter(sqlx::query(&sql), &query, from, to)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    let names = client_key_names(&state.db_pool).await?;
+
+    let mut totals = UsageAmounts::default();
+    let rows: Vec<SpendRow> = rows
+        .iter()
+        .map(|row| {
+            let api_key_id: String = row.get("api_key_id");
+            let usage = usage_amounts_from_row(row);
+            totals.add(&usage);
+            SpendRow {
+                period: row.get("period"),
+                key_name: names.get(&api_key_id).cloned(),
+                api_key_id,
+                model: row.get("model"),
+                usage,
+            }
+        })
+        .collect();
+    let from = from.format(usage::TIMESTAMP_FORMAT).to_string();
+    let to = to.format(usage::TIMESTAMP_FORMAT).to_string();
+
+    if !csv {
+        return Ok(Json(SpendReportResponse {
+            from,
+            to,
+            group_by: grouping,
+            rows,
+            totals,
+        })
+        .into_response());
+    }
+
+    let mut body = usage::csv_line(&[
+        "period",
+        "api_key_id",
+        "key_name",
+        "model",
+        "requests",
+        "failed_requests",
+        "input_tokens",
+        "cached_tokens",
+        "output_tokens",
+        "cost_usd",
+    ]);
+    for row in &rows {
+        body.push_str(&usage::csv_line(&[
+            &row.period,
+            &row.api_key_id,
+            row.key_name.as_deref().unwrap_or(""),
+            &row.model,
+            &row.usage.requests.to_string(),
+            &row.usage.failed_requests.to_string(),
+            &row.usage.input_tokens.to_string(),
+            &row.usage.cached_tokens.to_string(),
+            &row.usage.output_tokens.to_string(),
+            &format!("{:.6}", row.usage.cost_usd),
+        ]));
+    }
+    let filename = format!("gemini-pool-spend-{}-{}.csv", &from[..10], &to[..10]);
+    Ok((
+        [
+            (http::header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Rows read from the database at a time by an export.
+const EXPORT_PAGE_ROWS: i64 = 1000;
+
+/// Streams `usage_logs` rows as CSV or NDJSON, oldest first. Rows are read a
+/// page at a time as the response is sent, so exports of any size need little
+/// memory. No database connection is held while waiting for the client, so
+/// slow downloads cannot starve the usage logging of live requests.
+async fn admin_usage_export(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Response, AppError> {
+    let ndjson = match query.format.as_deref() {
+        None | Some("csv") => false,
+        Some("ndjson") => true,
+        Some(_) => return Err(AppError::BadRequest("format must be csv or ndjson".to_string())),
+    };
+    // Without a range, export everything logged so far
+    let hour = UsageGrouping::Hour;
+    let to = usage_bound("to", &query.to)?.unwrap_or_else(|| hour.next(hour.truncate(Utc::now())));
+    let from = usage_bound("from", &query.from)?.unwrap_or(DateTime::UNIX_EPOCH);
+    if from >= to {
+        return Err(AppError::BadRequest("from must be before to".to_string()));
+    }
+
+    let filename = format!(
+        "gemini-pool-usage-{}-{}.{}",
+        from.format("%Y-%m-%d"),
+        to.format("%Y-%m-%d"),
+        if ndjson { "ndjson" } else { "csv" }
+    );
+    let content_type = if ndjson { "application/x-ndjson" } else { "text/csv; charset=utf-8" };
+
+    let pool = state.db_pool.clone();
+    let (tx, rx) = mpsc::channel::<Result<String, std::io::Error>>(8);
+    tokio::spawn(async move {
+        // Pages continue after the last row sent, so rows logged meanwhile
+        // neither shift nor repeat them
+        let sql = format!(
+            "SELECT u.id, u.timestamp, u.api_key_id, k.key_name, u.endpoint, u.model,
+                    u.input_tokens, u.cached_tokens, u.output_tokens, u.cost_usd, u.success
+             FROM usage_logs u LEFT JOIN api_keys k ON k.id = u.api_key_id
+             WHERE {} AND (? IS NULL OR (u.timestamp, u.id) > (?, ?))
+             ORDER BY u.timestamp, u.id LIMIT ?",
+            USAGE_FILTER
+        );
+        let mut chunk = if ndjson {
+            String::new()
+        } else {
+            usage::csv_line(UsageExportRow::CSV_HEADER)
+        };
+        let mut cursor: Option<(String, String)> = None;
+        let mut exported = 0u64;
+
+        loop {
+            let (after_timestamp, after_id) = cursor.clone().unzip();
+            let page = bind_usage_filter(sqlx::query(&sql), &query, from, to)
+                .bind(after_timestamp.clone())
+                .bind(after_timestamp)
+                .bind(after_id)
+                .bind(EXPORT_PAGE_ROWS)
+                .fetch_all(&pool)
+                .await;
+            let page = match page {
+                Ok(page) => page,
+                Err(e) => {
+                    // Failing the body makes the client see an incomplete download
+                    tracing::error!("Usage export failed after {} rows: {}", exported, e);
+                    let _ = tx.send(Err(std::io::Error::other(e))).await;
+                    return;
+                }
+            };
+            let last_page = (page.len() as i64) < EXPORT_PAGE_ROWS;
+
+            for row in &page {
+                let row = UsageExportRow {
+                    id: row.get("id"),
+                    timestamp: row.get("timestamp"),
+                    api_key_id: row.get("api_key_id"),
+                    key_name: row.get("key_name"),
+                    endpoint: row.get("endpoint"),
+                    model: row.get("model"),
+                    input_tokens: row.get("input_tokens"),
+                    cached_tokens: row.get("cached_tokens"),
+                    output_tokens: row.get("output_tokens"),
+                    cost_usd: row.get("cost_usd"),
+                    success: row.get("success"),
+                };
+                if ndjson {
+                    if let Ok(line) = serde_json::to_string(&row) {
+                        chunk.push_str(&line);
+                        chunk.push('\n');
+                    }
+                } else {
+                    chunk.push_str(&row.csv_line());
+                }
+                exported += 1;
+                cursor = Some((row.timestamp, row.id));
+            }
+
+            if !chunk.is_empty() && tx.send(Ok(std::mem::take(&mut chunk))).await.is_err() {
+                info!("Usage export cancelled by the client after {} rows", exported);
+                return;
+            }
+            if last_page {
+                break;
+            }
+        }
+        info!("Exported {} usage rows", exported);
+    });
+
+    Ok((
+        [
+            (http::header::CONTENT_TYPE, content_type.to_string()),
+            (http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response())
+}
+
+async fn admin_list_model_prices(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ModelPricesResponse>, AppError> {
+    Ok(Json(ModelPricesResponse {
+        prices: state.prices.list(),
+    }))
+}
+
+/// Creates or replaces the price of a model (or model name prefix). New prices
+/// apply to requests from now on; logged costs are not recomputed.
+async fn admin_set_model_price(
+    State(state): State<Arc<AppState>>,
+    Path(model): Path<String>,
+    Json(payload): Json<SetModelPriceRequest>,
+) -> Result<StatusCode, AppError> {
+    if !is_valid_model_name(&model) {
+        return Err(AppError::BadRequest("Invalid model name".to_string()));
+    }
+    let prices = [
+        Some(payload.input_per_million),
+        payload.cached_input_per_million,
+        Some(payload.output_per_million),
+    ];
+    if prices.into_iter().flatten().any(|price| price < 0.0 || !price.is_finite()) {
+        return Err(AppError::BadRequest("Prices must be zero or more".to_string()));
+    }
+
+    sqlx::query(
+        "INSERT INTO model_prices (model, input_per_million, cached_input_per_million, output_per_million)
+         VALUES (?, ?, ?, ?)
+         ON CONFLICT (model) DO UPDATE SET
+             input_per_million = excluded.input_per_million,
+             cached_input_per_million = excluded.cached_input_per_million,
+             output_per_million = excluded.output_per_million"
+    )
+    .bind(&model)
+    .bind(payload.input_per_million)
+    .bind(payload.cached_input_per_million)
+    .bind(payload.output_per_million)
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    state.prices.set_prices(load_model_prices(&state.db_pool).await?);
+
+    Ok(StatusCode::OK)
+}
+
+async fn admin_delete_model_price(
+    State(state): State<Arc<AppState>>,
+    Path(model): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let result = sqlx::query("DELETE FROM model_prices WHERE model = ?")
+        .bind(&model)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("Model price not found".to_string()));
+    }
+
+    state.prices.set_prices(load_model_prices(&state.db_pool).await?);
+
+    Ok(StatusCode::OK)
+}
+
+/// Builds the admin view of a key. `quota_usage` is only shown for keys
+/// with an active quota.
+fn api_key_from_row(row: &sqlx::sqlite::SqliteRow, quota_usage: Option<QuotaUsage>) -> ApiKey {
+    let id: String = row.get("id");
+    let quota = quota_from_row(row);
+    let quota_usage = quota.active_period().map(|period| {
+        quota_usage.unwrap_or(QuotaUsage {
+            requests: 0,
+            tokens: 0,
+            cost_usd: 0.0,
+            resets_at: period.next_reset(Utc::now()),
+        })
+    });
+
+    ApiKey {
+        id: Uuid::parse_str(&id).unwrap(),
+        key_name: row.get("key_name"),
+        key_prefix: row.get("key_prefix"),
+        is_active: row.get("is_active"),
+        created_at: row.get("created_at"),
+        total_requests: row.get("total_requests"),
+        total_input_tokens: row.get("total_input_tokens"),
+        total_output_tokens: row.get("total_output_tokens"),
+        limits: rate_limits_from_row(row),
+        quota,
+        quota_usage,
+        allowed_models: allowed_models_from_row(row),
+    }
+}
+
+async fn admin_list_api_keys(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiKeysResponse>, AppError> {
+    let rows = sqlx::query(
+        "SELECT id, key_name, key_prefix, is_active, created_at, total_requests, total_input_tokens, total_output_tokens,
+                rpm_limit, tpm_limit, max_concurrent, quota_period, quota_tokens, quota_requests, budget_usd, allowed_models
+         FROM api_keys ORDER BY created_at DESC"
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    let usage = quota_usage_by_key(&state.db_pool).await.map_err(AppError::Internal)?;
+    let api_keys = rows
+        .iter()
+        .map(|row| api_key_from_row(row, usage.get(&row.get::<String, _>("id")).copied()))
+        .collect();
+
+    Ok(Json(ApiKeysResponse { api_keys }))
+}
+
+async fn admin_get_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(key_id): Path<String>,
+) -> Result<Json<ApiKey>, AppError> {
+    let row = sqlx::query(
+        "SELECT id, key_name, key_prefix, is_active, created_at, total_requests, total_input_tokens, total_output_tokens,
+                rpm_limit, tpm_limit, max_concurrent, quota_period, quota_tokens, quota_requests, budget_usd, allowed_models
+         FROM api_keys WHERE id = ?"
+    )
+    .bind(&key_id)
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?
+    .ok_or_else(|| AppError::BadRequest("API Key not found".to_string()))?;
+
+    let quota_usage = match quota_from_row(&row).active_period() {
+        Some(period) => Some(
+            quota_usage(&state.db_pool, &row.get::<String, _>("id"), period)
+                .await
+                .map_err(AppError::Internal)?,
+        ),
+        None => None,
+    };
+    Ok(Json(api_key_from_row(&row, quota_usage)))
+}
+
+async fn admin_create_api_key(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<Json<CreateApiKeyResponse>, AppError> {
+    validate_rate_limits(&payload.limits)?;
+    validate_quota(&payload.quota)?;
+    let allowed_models = serde_json::to_string(&normalize_allowed_models(payload.allowed_models)?)?;
+
+    let api_key = payload.api_key.unwrap_or_else(client_keys::generate_api_key);
+    if api_key.trim().is_empty() {
+        return Err(AppError::BadRequest("api_key must not be empty".to_string()));
+    }
+    let key_hash = client_keys::hash_api_key(&state.api_key_salt, &api_key);
+
+    let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM api_keys WHERE key_hash = ?")
+        .bind(&key_hash)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    if exists > 0 {
+        return Err(AppError::BadRequest("This API key already exists".to_string()));
+    }
+
+    let id = Uuid::new_v4();
+
+    sqlx::query(
+        "INSERT INTO api_keys (id, key_name, key_hash, key_prefix, rpm_limit, tpm_limit, max_concurrent, quota_period, quota_tokens, quota_requests, budget_usd, allowed_models)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(id.to_string())
+    .bind(&payload.key_name)
+    .bind(&key_hash)
+    .bind(client_keys::display_prefix(&api_key))
+    .bind(payload.limits.rpm_limit)
+    .bind(payload.limits.tpm_limit)
+    .bind(payload.limits.max_concurrent)
+    .bind(payload.quota.quota_period.map(|period| period.as_str()))
+    .bind(payload.quota.quota_tokens)
+    .bind(payload.quota.quota_requests)
+    .bind(payload.quota.budget_usd)
+    .bind(&allowed_models)
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    Ok(Json(CreateApiKeyResponse {
+        id: id.to_string(),
+        api_key,
+    }))
+}
+
+async fn admin_update_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(key_id): Path<String>,
+    Json(payload): Json<UpdateApiKeyRequest>,
+) -> Result<StatusCode, AppError> {
+    validate_rate_limits(&payload.limits)?;
+    validate_quota(&payload.quota)?;
+    let allowed_models = serde_json::to_string(&normalize_allowed_models(payload.allowed_models)?)?;
+
+    let result = sqlx::query(
+        "UPDATE api_keys
+         SET key_name = ?, is_active = ?, rpm_limit = ?, tpm_limit = ?, max_concurrent = ?,
+             quota_period = ?, quota_tokens = ?, quota_requests = ?, budget_usd = ?, allowed_models = ?
+         WHERE id = ?"
+    )
+    .bind(&payload.key_name)
+    .bind(payload.is_active)
+    .bind(payload.limits.rpm_limit)
+    .bind(payload.limits.tpm_limit)
+    .bind(payload.limits.max_concurrent)
+    .bind(payload.quota.quota_period.map(|period| period.as_str()))
+    .bind(payload.quota.quota_tokens)
+    .bind(payload.quota.quota_requests)
+    .bind(payload.quota.budget_usd)
+    .bind(&allowed_models)
+    .bind(&key_id)
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest("API Key not found".to_string()));
+    }
+
+    Ok(StatusCode::OK)
+}
+
+async fn admin_delete_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(key_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let result = sqlx::query("DELETE FROM api_keys WHERE id = ?")
+        .bind(&key_id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest("API Key not found".to_string()));
+    }
+
+    // Also delete related usage logs
+    sqlx::query("DELETE FROM usage_logs WHERE api_key_id = ?")
+        .bind(&key_id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    Ok(StatusCode::OK)
+}
+
+async fn admin_list_model_aliases(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ModelAliasesResponse>, AppError> {
+    Ok(Json(ModelAliasesResponse {
+        aliases: state.model_aliases.list(),
+    }))
+}
+
+/// Creates or replaces an alias.
+async fn admin_set_model_alias(
+    State(state): State<Arc<AppState>>,
+    Path(alias): Path<String>,
+    Json(payload): Json<SetModelAliasRequest>,
+) -> Result<StatusCode, AppError> {
+    let model = payload.model.trim();
+    if !is_valid_model_name(&alias) || !is_valid_model_name(model) {
+        return Err(AppError::BadRequest("Invalid alias or model name".to_string()));
+    }
+    if alias == model {
+        return Err(AppError::BadRequest("An alias cannot point to itself".to_string()));
+    }
+
+    sqlx::query(
+        "INSERT INTO model_aliases (alias, model) VALUES (?, ?)
+         ON CONFLICT (alias) DO UPDATE SET model = excluded.model"
+    )
+    .bind(&alias)
+    .bind(model)
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    state.model_aliases.set_aliases(load_model_aliases(&state.db_pool).await?);
+
+    Ok(StatusCode::OK)
+}
+
+async fn admin_delete_model_alias(
+    State(state): State<Arc<AppState>>,
+    Path(alias): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let result = sqlx::query("DELETE FROM model_aliases WHERE alias = ?")
+        .bind(&alias)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest("Model alias not found".to_string()));
+    }
+
+    state.model_aliases.set_aliases(load_model_aliases(&state.db_pool).await?);
+
+    Ok(StatusCode::OK)
+}
+
+/// Builds the admin view of an upstream key. The key is decrypted only to be masked.
+fn upstream_key_from_row(
+    master_key: &MasterKey,
+    row: &sqlx::sqlite::SqliteRow,
+    health: Option<KeyHealth>,
+) -> Result<UpstreamKeyInfo, AppError> {
+    let api_key = master_key
+        .open(row.get("key_ciphertext"), row.get("wrapped_key"))
+        .map_err(AppError::Internal)?;
+
+    Ok(UpstreamKeyInfo {
+        id: Uuid::parse_str(&row.get::<String, _>("id")).unwrap(),
+        label: row.get("label"),
+        api_key: mask_key(&api_key),
+        notes: row.get("notes"),
+        is_active: row.get("is_active"),
+        created_at: row.get("created_at"),
+        success_count: row.get("success_count"),
+        error_count: row.get("error_count"),
+        last_error: row.get("last_error"),
+        last_error_at: row.get("last_error_at"),
+        last_used_at: row.get("last_used_at"),
+        health,
+    })
+}
+
+/// Looks up the pool health of an upstream key by id.
+fn upstream_key_health(statuses: &[KeyStatus], id: &str) -> Option<KeyHealth> {
+    statuses
+        .iter()
+        .find(|status| status.id == id)
+        .map(|status| status.health.clone())
+}
+
+async fn admin_list_upstream_keys(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<UpstreamKeysResponse>, AppError> {
+    let rows = sqlx::query(
+        "SELECT id, label, key_ciphertext, wrapped_key, notes, is_active, created_at, success_count, error_count, last_error, last_error_at, last_used_at
+         FROM upstream_keys ORDER BY created_at, rowid"
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    let statuses = state.key_pool.snapshot();
+    let upstream_keys = rows
+        .iter()
+        .map(|row| {
+            let health = upstream_key_health(&statuses, &row.get::<String, _>("id"));
+            upstream_key_from_row(&state.master_key, row, health)
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Json(UpstreamKeysResponse { upstream_keys }))
+}
+
+async fn admin_get_upstream_key(
+    State(state): State<Arc<AppState>>,
+    Path(key_id): Path<String>,
+) -> Result<Json<UpstreamKeyInfo>, AppError> {
+    let row = sqlx::query(
+        "SELECT id, label, key_ciphertext, wrapped_key, notes, is_active, created_at, success_count, error_count, last_error, last_error_at, last_used_at
+         FROM upstream_keys WHERE id = ?"
+    )
+    .bind(&key_id)
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?
+    .ok_or_else(|| AppError::BadRequest("Upstream key not found".to_string()))?;
+
+    let health = upstream_key_health(&state.key_pool.snapshot(), &key_id);
+    Ok(Json(upstream_key_from_row(&state.master_key, &row, health)?))
+}
+
+async fn admin_create_upstream_key(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateUpstreamKeyRequest>,
+) -> Result<Json<CreateUpstreamKeyResponse>, AppError> {
+    let api_key = payload.api_key.trim();
+    if api_key.is_empty() {
+        return Err(AppError::BadRequest("Upstream key must not be empty".to_string()));
+    }
+
+    let key_hash = client_keys::hash_api_key(&state.api_key_salt, api_key);
+    let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM upstream_keys WHERE key_hash = ?")
+        .bind(&key_hash)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    if exists > 0 {
+        return Err(AppError::BadRequest("Upstream key already exists".to_string()));
+    }
+
+    let id = Uuid::new_v4();
+    let sealed = state.master_key.seal(api_key);
+
+    sqlx::query(
+        "INSERT INTO upstream_keys (id, label, key_hash, key_ciphertext, wrapped_key, notes) VALUES (?, ?, ?, ?, ?, ?)"
+    )
+    .bind(id.to_string())
+    .bind(&payload.label)
+    .bind(&key_hash)
+    .bind(&sealed.ciphertext)
+    .bind(&sealed.wrapped_key)
+    .bind(&payload.notes)
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    state.reload_key_pool().await?;
+    info!("Added upstream key {} ({})", id, payload.label);
+
+    Ok(Json(CreateUpstreamKeyResponse { id: id.to_string() }))
+}
+
+async fn admin_update_upstream_key(
+    State(state): State<Arc<AppState>>,
+    Path(key_id): Path<String>,
+    Json(payload): Json<UpdateUpstreamKeyRequest>,
+) -> Result<StatusCode, AppError> {
+    let result = sqlx::query(
+        "UPDATE upstream_keys SET label = ?, notes = ?, is_active = ? WHERE id = ?"
+    )
+    .bind(&payload.label)
+    .bind(&payload.notes)
+    .bind(payload.is_active)
+    .bind(&key_id)
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest("Upstream key not found".to_string()));
+    }
+
+    state.reload_key_pool().await?;
+    if payload.is_active {
+        // Saving an active key gives a key that Gemini rejected another chance
+        state.key_pool.clear_disabled(&key_id);
+    }
+
+    Ok(StatusCode::OK)
+}
+
+async fn admin_delete_upstream_key(
+    State(state): State<Arc<AppState>>,
+    Path(key_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let result = sqlx::query("DELETE FROM upstream_keys WHERE id = ?")
+        .bind(&key_id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest("Upstream key not found".to_string()));
+    }
+
+    state.reload_key_pool().await?;
+
+    Ok(StatusCode::OK)
+}
+
+fn admin_user_from_row(row: &sqlx::sqlite::SqliteRow) -> AdminUserInfo {
+    AdminUserInfo {
+        id: Uuid::parse_str(&row.get::<String, _>("id")).unwrap(),
+        username: row.get("username"),
+        role: AdminRole::parse(&row.get::<String, _>("role")).unwrap_or(AdminRole::Viewer),
+        is_active: row.get("is_active"),
+        created_at: row.get("created_at"),
+        last_login_at: row.get("last_login_at"),
+        totp_enabled: row.get("totp_enabled"),
+        active_sessions: row.get("active_sessions"),
+    }
+}
+
+fn validate_new_password(password: &str) -> Result<(), AppError> {
+    if password.chars().count() < admin_users::MIN_PASSWORD_LEN {
+        return Err(AppError::BadRequest(format!(
+            "Password must be at least {} characters",
+            admin_users::MIN_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+async fn admin_list_users(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<AdminUsersResponse>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT u.id, u.username, u.role, u.is_active, u.created_at, u.last_login_at, u.totp_enabled,
+               (SELECT COUNT(*) FROM admin_sessions s
+                WHERE s.user_id = u.id AND s.revoked_at IS NULL AND s.expires_at > ?) AS active_sessions
+        FROM admin_users u
+        ORDER BY u.created_at, u.rowid
+        "#,
+    )
+    .bind(Utc::now().format("%Y-%m-%d %H:%M:%S").to_string())
+    .fetch_all(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    Ok(Json(AdminUsersResponse {
+        users: rows.iter().map(admin_user_from_row).collect(),
+    }))
+}
+
+async fn admin_invite_user(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Json(payload): Json<InviteAdminUserRequest>,
+) -> Result<Json<InviteAdminUserResponse>, AppError> {
+    let username = payload.username.trim();
+    if username.is_empty() || username.chars().count() > 64 {
+        return Err(AppError::BadRequest("Username must be 1 to 64 characters".to_string()));
+    }
+
+    let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM admin_users WHERE username = ?")
+        .bind(username)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    if exists > 0 {
+        return Err(AppError::BadRequest("Username already exists".to_string()));
+    }
+
+    let id = Uuid::new_v4();
+    let temporary_password = admin_users::generate_temporary_password();
+
+    sqlx::query("INSERT INTO admin_users (id, username, password_hash, role) VALUES (?, ?, ?, ?)")
+        .bind(id.to_string())
+        .bind(username)
+        .bind(admin_users::hash_password(&temporary_password).map_err(AppError::Internal)?)
+        .bind(payload.role.as_str())
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    info!("Admin {} invited {} as {}", admin.username, username, payload.role.as_str());
+
+    Ok(Json(InviteAdminUserResponse {
+        id: id.to_string(),
+        username: username.to_string(),
+        temporary_password,
+    }))
+}
+
+async fn admin_update_user(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<UpdateAdminUserRequest>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = state.db_pool.begin().await.map_err(|e| AppError::Internal(e.into()))?;
+
+    let row = sqlx::query("SELECT username, role, is_active FROM admin_users WHERE id = ?")
+        .bind(&user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?
+        .ok_or_else(|| AppError::BadRequest("Admin user not found".to_string()))?;
+
+    // Never leave the installation without an active owner
+    let was_owner = row.get::<String, _>("role") == AdminRole::Owner.as_str() && row.get::<bool, _>("is_active");
+    let stays_owner = payload.role == AdminRole::Owner && payload.is_active;
+    if was_owner && !stays_owner {
+        let other_owners = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM admin_users WHERE role = 'owner' AND is_active = TRUE AND id != ?"
+        )
+        .bind(&user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+        if other_owners == 0 {
+            return Err(AppError::BadRequest("At least one active owner is required".to_string()));
+        }
+    }
+
+    sqlx::query("UPDATE admin_users SET role = ?, is_active = ? WHERE id = ?")
+        .bind(payload.role.as_str())
+        .bind(payload.is_active)
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.into()))?;
+
+    // Sessions of disabled users must not come back if they are enabled again
+    if !payload.is_active {
+        revoke_admin_sessions(&state.db_pool, &user_id, None).await?;
+    }
+
+    info!(
+        "Admin {} set {} to {} ({})",
+        admin.username,
+        row.get::<String, _>("username"),
+        payload.role.as_str(),
+        if payload.is_active { "active" } else { "disabled" }
+    );
+
+    Ok(StatusCode::OK)
+}
+
+async fn admin_reset_user_password(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Path(user_id): Path<String>,
+) -> Result<Json<ResetPasswordResponse>, AppError> {
+    let temporary_password = admin_users::generate_temporary_password();
+
+    let result = sqlx::query("UPDATE admin_users SET password_hash = ? WHERE id = ?")
+        .bind(admin_users::hash_password(&temporary_password).map_err(AppError::Internal)?)
+        .bind(&user_id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest("Admin user not found".to_string()));
+    }
+    revoke_admin_sessions(&state.db_pool, &user_id, None).await?;
+
+    info!("Admin {} reset the password of user {}", admin.username, user_id);
+
+    Ok(Json(ResetPasswordResponse { temporary_password }))
+}
+
+/// Signs a user out of all their sessions.
+async fn admin_revoke_user_sessions(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Path(user_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let revoked = revoke_admin_sessions(&state.db_pool, &user_id, None).await?;
+    info!("Admin {} revoked {} sessions of user {}", admin.username, revoked, user_id);
+
+    Ok(StatusCode::OK)
+}
+
+/// Lets any signed-in admin change their own password. Their other sessions
+/// are signed out.
+async fn admin_change_password(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    validate_new_password(&payload.new_password)?;
+
+    let password_hash = sqlx::query_scalar::<_, String>("SELECT password_hash FROM admin_users WHERE id = ?")
+        .bind(&admin.id)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    if !admin_users::verify_password(&password_hash, &payload.current_password) {
+        return Err(AppError::BadRequest("Current password is incorrect".to_string()));
+    }
+
+    sqlx::query("UPDATE admin_users SET password_hash = ? WHERE id = ?")
+        .bind(admin_users::hash_password(&payload.new_password).map_err(AppError::Internal)?)
+        .bind(&admin.id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    revoke_admin_sessions(&state.db_pool, &admin.id, Some(&admin.session_id)).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Starts two-factor enrolment for the current admin. The secret only takes
+/// effect once a code from it is confirmed with `totp/enable`.
+async fn admin_totp_setup(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+) -> Result<Json<TotpSetupResponse>, AppError> {
+    let enabled = sqlx::query_scalar::<_, bool>("SELECT totp_enabled FROM admin_users WHERE id = ?")
+        .bind(&admin.id)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    if enabled {
+        return Err(AppError::BadRequest("Two-factor login is already enabled".to_string()));
+    }
+
+    let secret = totp::generate_secret();
+    let sealed = state.master_key.seal(&secret);
+    sqlx::query("UPDATE admin_users SET totp_secret = ?, totp_wrapped_key = ?, totp_last_step = NULL WHERE id = ?")
+        .bind(&sealed.ciphertext)
+        .bind(&sealed.wrapped_key)
+        .bind(&admin.id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    let otpauth_uri = totp::provisioning_uri(TOTP_ISSUER, &admin.username, &secret);
+    let qr_svg = totp::qr_svg(&otpauth_uri).map_err(AppError::Internal)?;
+
+    Ok(Json(TotpSetupResponse {
+        secret,
+        otpauth_uri,
+        qr_svg,
+    }))
+}
+
+/// Turns on two-factor login after checking a code from the new secret, and
+/// returns the recovery codes.
+async fn admin_totp_enable(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Json(payload): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    let row = sqlx::query("SELECT totp_secret, totp_wrapped_key, totp_enabled FROM admin_users WHERE id = ?")
+        .bind(&admin.id)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    if row.get::<bool, _>("totp_enabled") {
+        return Err(AppError::BadRequest("Two-factor login is already enabled".to_string()));
+    }
+    let (Some(ciphertext), Some(wrapped_key)) = (
+        row.get::<Option<String>, _>("totp_secret"),
+        row.get::<Option<String>, _>("totp_wrapped_key"),
+    ) else {
+        return Err(AppError::BadRequest("Start two-factor setup first".to_string()));
+    };
+
+    let secret = state.master_key.open(&ciphertext, &wrapped_key).map_err(AppError::Internal)?;
+    let step = totp::verify(&secret, &payload.code, Utc::now().timestamp(), None)
+        .ok_or_else(|| AppError::BadRequest("Invalid verification code".to_string()))?;
+
+    sqlx::query("UPDATE admin_users SET totp_enabled = TRUE, totp_last_step = ?, totp_failures = 0 WHERE id = ?")
+        .bind(step)
+        .bind(&admin.id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    let recovery_codes = replace_recovery_codes(&state, &admin.id).await?;
+
+    info!("Admin {} enabled two-factor login", admin.username);
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turns off two-factor login for the current admin; needs their password.
+async fn admin_totp_disable(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Json(payload): Json<PasswordConfirmationRequest>,
+) -> Result<StatusCode, AppError> {
+    confirm_password(&state, &admin.id, &payload.password).await?;
+    clear_totp(&state, &admin.id).await?;
+
+    info!("Admin {} disabled two-factor login", admin.username);
+
+    Ok(StatusCode::OK)
+}
+
+/// Replaces the recovery codes of the current admin; needs their password.
+async fn admin_totp_recovery_codes(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Json(payload): Json<PasswordConfirmationRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    confirm_password(&state, &admin.id, &payload.password).await?;
+
+    let enabled = sqlx::query_scalar::<_, bool>("SELECT totp_enabled FROM admin_users WHERE id = ?")
+        .bind(&admin.id)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    if !enabled {
+        return Err(AppError::BadRequest("Two-factor login is not enabled".to_string()));
+    }
+
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes: replace_recovery_codes(&state, &admin.id).await?,
+    }))
+}
+
+/// Turns off two-factor login for another user, e.g. after they lost their device.
+async fn admin_reset_user_totp(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Path(user_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    if !clear_totp(&state, &user_id).await? {
+        return Err(AppError::BadRequest("Admin user not found".to_string()));
+    }
+
+    info!("Admin {} turned off two-factor login for user {}", admin.username, user_id);
+
+    Ok(StatusCode::OK)
+}
+
+async fn confirm_password(state: &AppState, user_id: &str, password: &str) -> Result<(), AppError> {
+    let password_hash = sqlx::query_scalar::<_, String>("SELECT password_hash FROM admin_users WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+    if !admin_users::verify_password(&password_hash, password) {
+        return Err(AppError::BadRequest("Password is incorrect".to_string()));
+    }
+    Ok(())
+}
+
+/// Removes the TOTP secret and recovery codes of a user. Returns false if the
+/// user does not exist.
+async fn clear_totp(state: &AppState, user_id: &str) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE admin_users
+        SET totp_enabled = FALSE, totp_secret = NULL, totp_wrapped_key = NULL, totp_last_step = NULL,
+            totp_failures = 0, totp_locked_until = NULL
+        WHERE id = ?
+        "#,
+    )
+    .bind(user_id)
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    sqlx::query("DELETE FROM admin_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn admin_list_lockouts(
+    State(state): State<Arc<AppState>>,
+) -> Json<LockoutsResponse> {
+    Json(LockoutsResponse {
+        lockouts: state.login_guard.entries(),
+    })
+}
+
+/// Lifts a lockout, e.g. `DELETE /admin/api/lockouts/ip/203.0.113.7`. For a
+/// username this also lifts the two-factor lockout of that admin.
+async fn admin_clear_lockout(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminUser>,
+    Path((kind, value)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    let subject = Subject::parse(&kind, &value)
+        .ok_or_else(|| AppError::BadRequest("Expected an IP address or a username".to_string()))?;
+    let mut cleared = state.login_guard.clear(&subject);
+
+    if subject.kind == SubjectKind::Username {
+        let result = sqlx::query(
+            "UPDATE admin_users SET totp_failures = 0, totp_locked_until = NULL WHERE username = ? AND totp_locked_until IS NOT NULL"
+        )
+        .bind(&subject.value)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+        cleared |= result.rows_affected() > 0;
+    }
+
+    if !cleared {
+        return Err(AppError::NotFound("No failed attempts recorded".to_string()));
+    }
+
+    tracing::warn!(
+        target: "security",
+        "Admin {} cleared the lockout of {} {}",
+        admin.username,
+        subject.kind.as_str(),
+        subject.value
+    );
+
+    Ok(StatusCode::OK)
+}
+
+async fn admin_auth_middleware(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    matched_path: MatchedPath,
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<axum::response::Response, AppError> {
+    let user = authenticate_admin(&state, &headers).await?;
+
+    let required = admin_users::required_role(request.method(), matched_path.as_str());
+    if user.role < required {
+        return Err(AppError::Forbidden(format!(
+            "This action requires the {} role",
+            required.as_str()
+        )));
+    }
+
+    request.extensions_mut().insert(user);
     Ok(next.run(request).await)
 }
 
@@ -670,7 +4027,59 @@ fn count_tokens(text: &str) -> i32 {
 }
 
 fn count_tokens_in_messages(messages: &[OpenAIMessage]) -> i32 {
-    messages.iter().map(|msg| count_tokens(&msg.content)).sum()
+    messages
+        .iter()
+        .map(|msg| {
+            let content_tokens = msg.content.as_ref().map_or(0, |content| count_tokens(&content.text()));
+            let tool_call_tokens: i32 = msg
+                .tool_calls
+                .iter()
+                .flatten()
+                .map(|call| count_tokens(&call.function.arguments))
+                .sum();
+            content_tokens + tool_call_tokens
+        })
+        .sum()
+}
+
+/// Builds the OpenAI usage object, preferring Gemini's `usageMetadata` and
+/// falling back to the character estimate when it is missing.
+///
+/// Gemini reports thinking tokens separately from `candidatesTokenCount`,
+/// while OpenAI counts reasoning tokens as part of `completion_tokens`.
+fn resolve_usage(
+    usage_metadata: Option<&GeminiUsageMetadata>,
+    estimated_prompt_tokens: i32,
+    completion_text: &str,
+) -> OpenAIUsage {
+    match usage_metadata {
+        Some(usage) => {
+            let completion_tokens = usage.candidates_token_count + usage.thoughts_token_count;
+            OpenAIUsage {
+                prompt_tokens: usage.prompt_token_count,
+                completion_tokens,
+                total_tokens: usage
+                    .total_token_count
+                    .unwrap_or(usage.prompt_token_count + completion_tokens),
+                prompt_tokens_details: PromptTokensDetails {
+                    cached_tokens: usage.cached_content_token_count,
+                },
+                completion_tokens_details: CompletionTokensDetails {
+                    reasoning_tokens: usage.thoughts_token_count,
+                },
+            }
+        }
+        None => {
+            let completion_tokens = count_tokens(completion_text);
+            OpenAIUsage {
+                prompt_tokens: estimated_prompt_tokens,
+                completion_tokens,
+                total_tokens: estimated_prompt_tokens + completion_tokens,
+                prompt_tokens_details: PromptTokensDetails::default(),
+                completion_tokens_details: CompletionTokensDetails::default(),
+            }
+        }
+    }
 }
 
 //================================================================================
@@ -690,180 +4099,1239 @@ async fn root_handler() -> impl IntoResponse {
             "Index page not found"
         ).into_response()
     }
-}
+}
+
+/// Serves Prometheus metrics. With `METRICS_TOKEN` set, scrapers must send it
+/// as a bearer token.
+async fn metrics_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Some(token) = &state.metrics_token {
+        let provided = headers
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !provided.is_some_and(|provided| metrics::token_matches(token, provided)) {
+            return Err(AppError::Unauthorized("Invalid metrics token".to_string()));
+        }
+    }
+
+    Ok((
+        [(http::header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        state.metrics.render(&state.key_pool.snapshot()),
+    )
+        .into_response())
+}
+
+/// Lists the available models by fetching them from the Gemini API.
+async fn list_models_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(client): Extension<ClientKey>,
+) -> Result<Json<ModelList>, AppError> {
+    info!("Received request to list models");
+    let result = fetch_model_list(&state, &client).await;
+    record_usage(&state, &client.id, "/v1/models", "", OpenAIUsage::default(), result.is_ok()).await;
+    result.map(Json)
+}
+
+/// Fetches the Gemini model list and converts it into the OpenAI format,
+/// keeping only the models and aliases the client key may use.
+async fn fetch_model_list(state: &AppState, client_key: &ClientKey) -> Result<ModelList, AppError> {
+    let response = send_upstream(state, "/v1/models", "", |api_key| state.gemini.list_models(api_key)).await?;
+
+    // Deserialize the successful response
+    let gemini_model_list: GeminiModelList = response.json().await?;
+    state.metrics.set_upstream_models(
+        gemini_model_list
+            .models
+            .iter()
+            .map(|m| m.name.strip_prefix("models/").unwrap_or(&m.name).to_string())
+            .collect(),
+    );
+
+    // Transform the Gemini model list to the OpenAI format
+    let mut openai_models: Vec<ModelObject> = gemini_model_list
+        .models
+        .into_iter()
+        // We only care about models that can be used for chat completions
+        .filter(|m| m.supported_generation_methods.contains(&"generateContent".to_string()))
+        // We also filter for non-embedding models
+        .filter(|m| !m.name.contains("embedding"))
+        .map(|m| ModelObject {
+            // Strip the "models/" prefix to get the clean model ID
+            id: m.name.strip_prefix("models/").unwrap_or(&m.name).to_string(),
+            object: "model".to_string(),
+            created: 1, // Placeholder timestamp, as Gemini API doesn't provide it
+            owned_by: "google".to_string(),
+        })
+        .filter(|m| is_allowed(&client_key.allowed_models, &[&m.id]))
+        .collect();
+
+    // Aliases are listed as models of their own
+    openai_models.extend(
+        state
+            .model_aliases
+            .list()
+            .into_iter()
+            .filter(|alias| is_allowed(&client_key.allowed_models, &[&alias.alias, &alias.model]))
+            .map(|alias| ModelObject {
+                id: alias.alias,
+                object: "model".to_string(),
+                created: 1,
+                owned_by: "gemini-pool".to_string(),
+            }),
+    );
+
+    Ok(ModelList {
+        object: "list".to_string(),
+        data: openai_models,
+    })
+}
+
+/// Sends a request to Gemini, retrying on a different key according to
+/// `AppState::retry_policy`. `build` creates the request for a given key.
+///
+/// Every attempt is written to `upstream_attempts`. Only a successful response
+/// is returned; the last upstream error becomes an `AppError`.
+async fn send_upstream<F>(
+    state: &AppState,
+    endpoint: &str,
+    model: &str,
+    build: F,
+) -> Result<reqwest::Response, AppError>
+where
+    F: Fn(&str) -> reqwest::RequestBuilder,
+{
+    let policy = &state.retry_policy;
+    let request_id = Uuid::new_v4();
+    let mut tried = Vec::new();
+    let mut last_error = None;
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        let api_key = match state.get_next_api_key(&tried) {
+            Ok(api_key) => api_key,
+            // Every healthy key has been tried; report the last upstream error
+            Err(e) => return Err(last_error.unwrap_or(e)),
+        };
+        tried.push(api_key.id.clone());
+
+        let started = Instant::now();
+        let result = build(&api_key.key).send().await;
+        let mut record = UpstreamAttempt {
+            id: Uuid::new_v4(),
+            request_id,
+            attempt,
+            upstream_key_id: api_key.id.clone(),
+            timestamp: Utc::now(),
+            endpoint: endpoint.to_string(),
+            model: model.to_string(),
+            status_code: None,
+            error: None,
+            latency_ms: started.elapsed().as_millis() as i64,
+            success: false,
+        };
+
+        let (error, retryable) = match result {
+            Ok(response) if response.status().is_success() => {
+                record.status_code = Some(response.status().as_u16());
+                record.success = true;
+                state.metrics.record_upstream_attempt(&record.upstream_key_id, record.status_code);
+                if let Err(e) = log_upstream_attempt(&state.db_pool, &record).await {
+                    tracing::error!("Failed to log upstream attempt: {:?}", e);
+                }
+                if attempt > 1 {
+                    info!("Request {} succeeded after failing over (attempt {})", request_id, attempt);
+                }
+                return Ok(response);
+            }
+            Ok(response) => {
+                let status = response.status();
+                let headers = response.headers().clone();
+                let error_body = response
+                    .text()
+                    .await
+                    .unwrap_or_else(|_| "Could not read error body".to_string());
+                tracing::error!(
+                    "Gemini API returned an error on {} (attempt {}). Status: {}. Body: {}",
+                    endpoint,
+                    attempt,
+                    status,
+                    error_body
+                );
+                let key_rejected = state.key_pool.report_failure(&api_key.id, status, &headers, &error_body);
+                record.status_code = Some(status.as_u16());
+                record.error = Some(error_body.clone());
+                let error = AppError::Internal(anyhow::anyhow!(
+                    "Upstream Gemini API Error: {} - {}",
+                    status,
+                    error_body
+                ));
+                // The request itself may be fine, so it deserves a key that works
+                (error, key_rejected || policy.should_retry_status(status.as_u16()))
+            }
+            Err(e) => {
+                tracing::error!("Request to Gemini failed on {} (attempt {}): {}", endpoint, attempt, e);
+                record.error = Some(e.to_string());
+                // Connection problems are always worth another key
+                let retryable = e.is_connect() || e.is_timeout() || e.is_request();
+                (AppError::Internal(e.into()), retryable)
+            }
+        };
+
+        state.metrics.record_upstream_attempt(&record.upstream_key_id, record.status_code);
+        if let Err(e) = log_upstream_attempt(&state.db_pool, &record).await {
+            tracing::error!("Failed to log upstream attempt: {:?}", e);
+        }
+        if !retryable || attempt >= policy.max_attempts {
+            return Err(error);
+        }
+        last_error = Some(error);
+        tokio::time::sleep(policy.backoff(attempt)).await;
+    }
+}
+
+/// Outcome of a proxied chat completion.
+enum ChatCompletion {
+    /// A full response; its `usage` is recorded by the handler.
+    Complete(OpenAIChatResponse),
+    /// An SSE response whose usage is recorded by the streaming task.
+    Stream(Response),
+    /// A response that failed strict validation. Its tokens were still spent,
+    /// so `usage` is recorded before `error` goes to the client.
+    Invalid { usage: OpenAIUsage, error: AppError },
+}
+
+/// Handles the chat completions request.
+async fn chat_completions_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(client): Extension<ClientKey>,
+    Extension(labels): Extension<RequestLabels>,
+    payload: Result<Json<OpenAIChatRequest>, JsonRejection>,
+) -> Result<Response, AppError> {
+    let payload = match payload {
+        Ok(Json(payload)) => payload,
+        Err(rejection) => {
+            record_usage(&state, &client.id, "/v1/chat/completions", "", OpenAIUsage::default(), false).await;
+            return Ok(rejection.into_response());
+        }
+    };
+
+    // Usage is logged under the Gemini model, which is what gets priced
+    let upstream_model = match resolve_model(&state, &client, &payload.model) {
+        Ok(model) => model,
+        Err(e) => {
+            // The name comes straight from the client, so only well-formed ones are kept
+            let model = if is_valid_model_name(&payload.model) { payload.model.as_str() } else { "" };
+            record_usage(&state, &client.id, "/v1/chat/completions", model, OpenAIUsage::default(), false).await;
+            return Err(e);
+        }
+    };
+    labels.set_model(&metrics_model_label(&state, &upstream_model));
+
+    match proxy_chat_completion(&state, client.id.clone(), upstream_model.clone(), payload).await {
+        Ok(ChatCompletion::Complete(response)) => {
+            record_usage(&state, &client.id, "/v1/chat/completions", &upstream_model, response.usage, true).await;
+            Ok(Json(response).into_response())
+        }
+        Ok(ChatCompletion::Stream(response)) => Ok(response),
+        Ok(ChatCompletion::Invalid { usage, error }) => {
+            record_usage(&state, &client.id, "/v1/chat/completions", &upstream_model, usage, false).await;
+            Err(error)
+        }
+        Err(e) => {
+            record_usage(&state, &client.id, "/v1/chat/completions", &upstream_model, OpenAIUsage::default(), false).await;
+            Err(e)
+        }
+    }
+}
+
+/// Resolves the requested model through the alias table and checks it against
+/// the client key's allowlist. Returns the Gemini model to call.
+fn resolve_model(state: &AppState, client: &ClientKey, requested: &str) -> Result<String, AppError> {
+    let requested = requested.strip_prefix("models/").unwrap_or(requested);
+    let model = state.model_aliases.resolve(requested);
+    if !is_valid_model_name(&model) {
+        return Err(AppError::BadRequest(format!("Invalid model name: {}", requested)));
+    }
+    if !is_allowed(&client.allowed_models, &[requested, &model]) {
+        return Err(AppError::NotFound(format!(
+            "The model `{}` does not exist or you do not have access to it.",
+            requested
+        )));
+    }
+    Ok(model)
+}
+
+/// Sends a chat completion to Gemini and converts the result. `upstream_model`
+/// is the resolved Gemini model; responses keep the model name the client sent.
+async fn proxy_chat_completion(
+    state: &Arc<AppState>,
+    api_key_id: String,
+    upstream_model: String,
+    payload: OpenAIChatRequest,
+) -> Result<ChatCompletion, AppError> {
+    info!("Received OpenAI chat request for model: {} ({})", payload.model, upstream_model);
+    check_unknown_params(&payload.extra, state.strict_params)?;
+
+    // 1. Convert OpenAI request to Gemini request
+    let model_name = payload.model.clone();
+    let stream = payload.stream;
+    let include_usage = payload
+        .stream_options
+        .as_ref()
+        .is_some_and(|options| options.include_usage);
+    let prompt_tokens = count_tokens_in_messages(&payload.messages);
+    let response_validator = match &payload.response_format {
+        Some(OpenAIResponseFormat::JsonSchema { json_schema }) if json_schema.strict => json_schema
+            .schema
+            .as_ref()
+            .map(|schema| {
+                jsonschema::validator_for(schema)
+                    .map_err(|e| AppError::BadRequest(format!("Invalid response_format JSON schema: {}", e)))
+            })
+            .transpose()?,
+        _ => None,
+    };
+    let gemini_request = convert_to_gemini_request(payload, &state.gemini)?;
+
+    // 2. Send request to Gemini API, failing over to other keys if needed
+    let response = send_upstream(state, "/v1/chat/completions", &upstream_model, |api_key| {
+        if stream {
+            state.gemini.stream_generate_content(api_key, &upstream_model, &gemini_request)
+        } else {
+            state.gemini.generate_content(api_key, &upstream_model, &gemini_request)
+        }
+    })
+    .await?;
+    let status = response.status();
+
+    if stream {
+        // Forward the upstream SSE stream from a background task. The channel
+        // closes when the client disconnects, which ends the task as well.
+        let (tx, rx) = mpsc::channel(32);
+        let context = StreamContext {
+            api_key_id,
+            model_name,
+            upstream_model,
+            prompt_tokens,
+            include_usage,
+            response_validator,
+        };
+        tokio::spawn(forward_gemini_stream(state.clone(), context, response, tx));
+
+        let events = ReceiverStream::new(rx).map(Ok::<_, Infallible>);
+        let response = Sse::new(events).keep_alive(KeepAlive::default()).into_response();
+        return Ok(ChatCompletion::Stream(response));
+    }
+
+    // Deserialize the successful response, with robust error handling
+    let response_body = response.text().await?;
+    let gemini_response: GeminiResponse = match serde_json::from_str(&response_body) {
+        Ok(res) => res,
+        Err(e) => {
+            tracing::error!(
+                "Failed to deserialize Gemini response. Status: {}. Error: {}. Body: {}",
+                status,
+                e,
+                response_body
+            );
+            return Err(AppError::Internal(anyhow::anyhow!(
+                "Failed to deserialize upstream Gemini response"
+            )));
+        }
+    };
+
+    // 3. Convert Gemini response back to OpenAI response
+    let openai_response = convert_to_openai_response(gemini_response, model_name, prompt_tokens)?;
+
+    // 4. With `strict: true`, only hand out output that matches the schema
+    if let Some(validator) = &response_validator {
+        let texts = openai_response
+            .choices
+            .iter()
+            .map(|choice| choice.message.content.as_ref().map(OpenAIContent::text).unwrap_or_default());
+        if let Err(error) = validate_structured_output(validator, texts) {
+            return Ok(ChatCompletion::Invalid { usage: openai_response.usage, error });
+        }
+    }
+
+    Ok(ChatCompletion::Complete(openai_response))
+}
+
+/// Request details the streaming task needs besides the upstream response.
+struct StreamContext {
+    api_key_id: String,
+    /// Model name as sent by the client, echoed in every chunk.
+    model_name: String,
+    /// Resolved Gemini model, used for usage logging.
+    upstream_model: String,
+    prompt_tokens: i32,
+    include_usage: bool,
+    /// Set for `json_schema` response formats with `strict: true`.
+    response_validator: Option<jsonschema::Validator>,
+}
+
+/// Reads Gemini's SSE stream, re-emits each chunk as an OpenAI
+/// `chat.completion.chunk` event and logs usage once the stream is over.
+async fn forward_gemini_stream(
+    state: Arc<AppState>,
+    context: StreamContext,
+    response: reqwest::Response,
+    tx: mpsc::Sender<Event>,
+) {
+    let StreamContext {
+        api_key_id,
+        model_name,
+        upstream_model,
+        prompt_tokens,
+        include_usage,
+        response_validator,
+    } = context;
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4());
+    let created = unix_timestamp();
+    let mut upstream = response.bytes_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut completion_text = String::new();
+    let mut usage_metadata: Option<GeminiUsageMetadata> = None;
+    let mut stream_state = StreamState::default();
+    let mut success = true;
+    let mut client_connected = true;
+
+    'read: loop {
+        let upstream_done = match upstream.next().await {
+            Some(Ok(chunk)) => {
+                buffer.extend_from_slice(&chunk);
+                false
+            }
+            Some(Err(e)) => {
+                tracing::error!("Error while reading Gemini stream: {}", e);
+                success = false;
+                let error = serde_json::json!({
+                    "error": {"message": "Upstream stream interrupted", "type": "upstream_error"}
+                });
+                let _ = tx.send(Event::default().data(error.to_string())).await;
+                break;
+            }
+            None if buffer.is_empty() => break,
+            None => {
+                // The last line may end without a newline
+                buffer.push(b'\n');
+                true
+            }
+        };
+
+        // Gemini sends one JSON document per `data:` line
+        while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim_end().strip_prefix("data:") else {
+                continue;
+            };
+
+            let gemini_chunk: GeminiResponse = match serde_json::from_str(data.trim_start()) {
+                Ok(res) => res,
+                Err(e) => {
+                    tracing::error!("Failed to deserialize Gemini stream chunk. Error: {}. Data: {}", e, data);
+                    continue;
+                }
+            };
+
+            // Every chunk repeats the running totals, so keep the latest
+            if gemini_chunk.usage_metadata.is_some() {
+                usage_metadata = gemini_chunk.usage_metadata;
+            }
+
+            for chunk in convert_to_openai_chunks(gemini_chunk, &id, created, &model_name, &mut stream_state) {
+                for choice in &chunk.choices {
+                    if let Some(content) = &choice.delta.content {
+                        completion_text.push_str(content);
+                    }
+                    for call in choice.delta.tool_calls.iter().flatten() {
+                        completion_text.push_str(&call.function.arguments);
+                    }
+                }
+                if !send_chunk(&tx, &chunk).await {
+                    client_connected = false;
+                    break 'read;
+                }
+            }
+        }
+        if upstream_done {
+            break;
+        }
+    }
+
+    let usage = resolve_usage(usage_metadata.as_ref(), prompt_tokens, &completion_text);
+
+    // Streamed text has already reached the client, so a schema mismatch can
+    // only be reported in place of the closing chunks
+    if client_connected && success {
+        if let Some(validator) = &response_validator {
+            let mut choices: Vec<(&u32, &ChoiceStreamState)> = stream_state.choices.iter().collect();
+            choices.sort_by_key(|(index, _)| **index);
+            let texts = choices.into_iter().map(|(_, choice)| choice.text.clone());
+            if let Err(AppError::BadGateway(message)) = validate_structured_output(validator, texts) {
+                success = false;
+                let error = serde_json::json!({
+                    "error": {"message": message, "type": "invalid_response_format"}
+                });
+                let _ = tx.send(Event::default().data(error.to_string())).await;
+            }
+        }
+    }
+
+    if client_connected && success {
+        // Close any choice Gemini ended without a finishReason
+        let mut unfinished: Vec<(u32, bool)> = stream_state
+            .choices
+            .iter()
+            .filter(|(_, choice)| !choice.finished)
+            .map(|(index, choice)| (*index, choice.tool_call_count > 0))
+            .collect();
+        if stream_state.choices.is_empty() {
+            unfinished.push((0, false));
+        }
+        unfinished.sort_unstable();
+        if !unfinished.is_empty() {
+            let chunk = OpenAIChatChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model: model_name.clone(),
+                choices: unfinished
+                    .into_iter()
+                    .map(|(index, has_tool_calls)| OpenAIChunkChoice {
+                        index,
+                        delta: OpenAIDelta::default(),
+                        finish_reason: Some(if has_tool_calls { "tool_calls" } else { "stop" }.to_string()),
+                    })
+                    .collect(),
+                usage: None,
+            };
+            send_chunk(&tx, &chunk).await;
+        }
+        if include_usage {
+            let chunk = OpenAIChatChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model: model_name.clone(),
+                choices: Vec::new(),
+                usage: Some(usage),
+            };
+            send_chunk(&tx, &chunk).await;
+        }
+        let _ = tx.send(Event::default().data("[DONE]")).await;
+    } else if !client_connected {
+        info!("Client disconnected before the stream finished");
+    }
+
+    record_usage(
+        &state,
+        &api_key_id,
+        "/v1/chat/completions",
+        &upstream_model,
+        usage,
+        success,
+    )
+    .await;
+}
+
+/// Serializes a chunk into an SSE event. Returns `false` once the client is gone.
+async fn send_chunk(tx: &mpsc::Sender<Event>, chunk: &OpenAIChatChunk) -> bool {
+    match serde_json::to_string(chunk) {
+        Ok(data) => tx.send(Event::default().data(data)).await.is_ok(),
+        Err(e) => {
+            tracing::error!("Failed to serialize stream chunk: {}", e);
+            true
+        }
+    }
+}
+
+/// Converts an OpenAI request payload into a Gemini request payload. `gemini`
+/// resolves Files API references against the configured endpoint.
+fn convert_to_gemini_request(req: OpenAIChatRequest, gemini: &GeminiClient) -> Result<GeminiRequest, AppError> {
+    let mut gemini_contents: Vec<GeminiContent> = Vec::new();
+    let mut system_instruction = None;
+    // Tool results only carry the call id, but Gemini wants the function name
+    let mut tool_call_names: HashMap<String, String> = HashMap::new();
+
+    for message in req.messages {
+        match message.role.as_str() {
+            "system" => {
+                // The Gemini API currently only supports one system instruction.
+                // If multiple are found, we'll use the first one.
+                if system_instruction.is_none() {
+                    system_instruction = Some(SystemInstruction {
+                        parts: convert_content_parts(require_content(message.content)?, gemini)?,
+                    });
+                }
+            }
+            "user" => {
+                gemini_contents.push(GeminiContent {
+                    role: "user".to_string(),
+                    parts: convert_content_parts(require_content(message.content)?, gemini)?,
+                });
+            }
+            "assistant" => {
+                let mut parts = match message.content {
+                    Some(OpenAIContent::Text(text)) if text.is_empty() => Vec::new(),
+                    Some(content) => convert_content_parts(content, gemini)?,
+                    None => Vec::new(),
+                };
+                for call in message.tool_calls.unwrap_or_default() {
+                    let args = if call.function.arguments.trim().is_empty() {
+                        serde_json::json!({})
+                    } else {
+                        serde_json::from_str(&call.function.arguments).map_err(|e| {
+                            AppError::BadRequest(format!("Invalid JSON arguments for tool call {}: {}", call.id, e))
+                        })?
+                    };
+                    let thought_signature = call
+                        .id
+                        .split_once(THOUGHT_SIGNATURE_SEPARATOR)
+                        .map(|(_, signature)| signature.to_string());
+                    tool_call_names.insert(call.id, call.function.name.clone());
+                    parts.push(GeminiPart::FunctionCall {
+                        function_call: GeminiFunctionCall {
+                            id: None,
+                            name: call.function.name,
+                            args,
+                        },
+                        thought_signature,
+                    });
+                }
+                if parts.is_empty() {
+                    return Err(AppError::BadRequest("Assistant message requires content or tool_calls".to_string()));
+                }
+                gemini_contents.push(GeminiContent {
+                    role: "model".to_string(),
+                    parts,
+                });
+            }
+            "tool" => {
+                let tool_call_id = message
+                    .tool_call_id
+                    .ok_or_else(|| AppError::BadRequest("Tool message requires tool_call_id".to_string()))?;
+                let name = tool_call_names.get(&tool_call_id).cloned().ok_or_else(|| {
+                    AppError::BadRequest(format!("No assistant tool call found for tool_call_id {}", tool_call_id))
+                })?;
+                let output = message.content.map(|content| content.text()).unwrap_or_default();
+                // Gemini expects an object; wrap anything else
+                let response = match serde_json::from_str::<serde_json::Value>(&output) {
+                    Ok(value @ serde_json::Value::Object(_)) => value,
+                    _ => serde_json::json!({ "content": output }),
+                };
+                let part = GeminiPart::FunctionResponse {
+                    function_response: GeminiFunctionResponse { name, response },
+                };
+
+                // Results of parallel calls must be sent back together in one turn
+                match gemini_contents.last_mut() {
+                    Some(last)
+                        if last.role == "user"
+                            && last.parts.iter().all(|p| matches!(p, GeminiPart::FunctionResponse { .. })) =>
+                    {
+                        last.parts.push(part)
+                    }
+                    _ => gemini_contents.push(GeminiContent {
+                        role: "user".to_string(),
+                        parts: vec![part],
+                    }),
+                }
+            }
+            _ => return Err(AppError::BadRequest(format!("Unsupported role: {}", message.role))),
+        }
+    }
+
+    // The Gemini API requires that the conversation starts with a "user" role.
+    // If the first message after a potential system prompt is from the "model",
+    // it's an invalid sequence.
+    if let Some(first_content) = gemini_contents.first() {
+        if first_content.role == "model" {
+            return Err(AppError::BadRequest("Conversation must start with a user message after the system prompt.".to_string()));
+        }
+    }
+
+    let tools = req.tools.map(convert_tools).transpose()?;
+    let tool_config = req.tool_choice.map(convert_tool_choice).transpose()?;
+
+    if req.n == Some(0) {
+        return Err(AppError::BadRequest("n must be at least 1".to_string()));
+    }
+    let (response_mime_type, response_schema) = match req.response_format {
+        None | Some(OpenAIResponseFormat::Text) => (None, None),
+        Some(OpenAIResponseFormat::JsonObject) => (Some("application/json".to_string()), None),
+        Some(OpenAIResponseFormat::JsonSchema { json_schema }) => (
+            Some("application/json".to_string()),
+            json_schema.schema.as_ref().map(convert_json_schema).transpose()?,
+        ),
+    };
+    let generation_config = GeminiGenerationConfig {
+        temperature: req.temperature,
+        top_p: req.top_p,
+        max_output_tokens: req.max_completion_tokens.or(req.max_tokens),
+        stop_sequences: req.stop.map(|stop| match stop {
+            OpenAIStop::Single(sequence) => vec![sequence],
+            OpenAIStop::Multiple(sequences) => sequences,
+        }),
+        presence_penalty: req.presence_penalty,
+        frequency_penalty: req.frequency_penalty,
+        seed: req.seed,
+        candidate_count: req.n.filter(|n| *n > 1),
+        response_mime_type,
+        response_schema,
+    };
+
+    Ok(GeminiRequest {
+        contents: gemini_contents,
+        system_instruction,
+        tools,
+        tool_config,
+        generation_config: Some(generation_config),
+    })
+}
+
+/// Logs or rejects request parameters the proxy does not understand.
+fn check_unknown_params(extra: &HashMap<String, serde_json::Value>, strict: bool) -> Result<(), AppError> {
+    let mut unknown: Vec<&str> = extra
+        .keys()
+        .map(String::as_str)
+        .filter(|name| !IGNORED_OPENAI_PARAMS.contains(name))
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    unknown.sort_unstable();
+
+    if strict {
+        return Err(AppError::BadRequest(format!("Unsupported parameter(s): {}", unknown.join(", "))));
+    }
+    tracing::warn!("Ignoring unsupported parameter(s): {}", unknown.join(", "));
+    Ok(())
+}
+
+fn require_content(content: Option<OpenAIContent>) -> Result<OpenAIContent, AppError> {
+    content.ok_or_else(|| AppError::BadRequest("Message content is required".to_string()))
+}
+
+/// Converts OpenAI function tools into a single Gemini tool declaration.
+fn convert_tools(tools: Vec<OpenAITool>) -> Result<Vec<GeminiTool>, AppError> {
+    let function_declarations = tools
+        .into_iter()
+        .map(|tool| {
+            if tool.tool_type != "function" {
+                return Err(AppError::BadRequest(format!("Unsupported tool type: {}", tool.tool_type)));
+            }
+            // Gemini rejects object schemas without properties, so omit them
+            let parameters = tool
+                .function
+                .parameters
+                .filter(|schema| {
+                    schema
+                        .get("properties")
+                        .and_then(|properties| properties.as_object())
+                        .is_some_and(|properties| !properties.is_empty())
+                })
+                .as_ref()
+                .map(convert_json_schema)
+                .transpose()?;
+            Ok(GeminiFunctionDeclaration {
+                name: tool.function.name,
+                description: tool.function.description,
+                parameters,
+            })
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    if function_declarations.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(vec![GeminiTool { function_declarations }])
+}
+
+/// Keywords of Gemini's OpenAPI-style schema subset that can be copied as-is.
+const GEMINI_SCHEMA_KEYWORDS: &[&str] = &[
+    "format", "title", "description", "nullable", "maxItems", "minItems", "required", "minimum",
+    "maximum", "minLength", "maxLength", "pattern", "example", "default", "minProperties", "maxProperties",
+];
+
+/// Maximum nesting depth when inlining `$ref`s, which also stops recursive schemas.
+const MAX_SCHEMA_DEPTH: usize = 32;
+
+/// Down-converts an OpenAI JSON Schema into the schema subset Gemini accepts.
+///
+/// Local `$ref`s are inlined, `oneOf` becomes `anyOf`, type unions with
+/// `null` become `nullable`, and validation-only keywords Gemini does not
+/// know are dropped. Keywords that would change the meaning of the schema
+/// (`not`, `if`/`then`/`else`, multi-member `allOf`) are rejected.
+fn convert_json_schema(schema: &serde_json::Value) -> Result<serde_json::Value, AppError> {
+    let defs = schema.get("$defs").or_else(|| schema.get("definitions"));
+    convert_schema_node(schema, defs, 0)
+}
+
+fn convert_schema_node(
+    node: &serde_json::Value,
+    defs: Option<&serde_json::Value>,
+    depth: usize,
+) -> Result<serde_json::Value, AppError> {
+    use serde_json::Value;
+
+    if depth > MAX_SCHEMA_DEPTH {
+        return Err(AppError::BadRequest("JSON schema is too deeply nested or recursive".to_string()));
+    }
+    let object = match node {
+        Value::Object(object) => object,
+        Value::Bool(true) => return Ok(serde_json::json!({})),
+        _ => return Err(AppError::BadRequest("JSON schema nodes must be objects".to_string())),
+    };
+
+    if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+        let name = reference
+            .strip_prefix("#/$defs/")
+            .or_else(|| reference.strip_prefix("#/definitions/"))
+            .ok_or_else(|| AppError::BadRequest(format!("Unsupported $ref {}: only local definitions can be used", reference)))?;
+        let target = defs
+            .and_then(|defs| defs.get(name))
+            .ok_or_else(|| AppError::BadRequest(format!("Unresolved $ref {}", reference)))?;
+        return convert_schema_node(target, defs, depth + 1);
+    }
+
+    for keyword in ["not", "if", "then", "else"] {
+        if object.contains_key(keyword) {
+            return Err(AppError::BadRequest(format!("JSON schema keyword '{}' is not supported by Gemini", keyword)));
+        }
+    }
+
+    if let Some(all_of) = object.get("allOf") {
+        let [single] = all_of.as_array().map(Vec::as_slice).unwrap_or_default() else {
+            return Err(AppError::BadRequest("JSON schema 'allOf' with more than one member is not supported by Gemini".to_string()));
+        };
+        // Fold the only member into the parent and convert the result
+        let mut merged = object.clone();
+        merged.remove("allOf");
+        if let Value::Object(member) = single {
+            for (key, value) in member {
+                merged.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        return convert_schema_node(&Value::Object(merged), defs, depth + 1);
+    }
+
+    let mut out = serde_json::Map::new();
+    for (key, value) in object {
+        match key.as_str() {
+            "type" => match value {
+                Value::Array(types) => {
+                    let non_null: Vec<&Value> = types.iter().filter(|t| t.as_str() != Some("null")).collect();
+                    if non_null.len() < types.len() {
+                        out.insert("nullable".to_string(), Value::Bool(true));
+                    }
+                    match non_null.as_slice() {
+                        [] => return Err(AppError::BadRequest("JSON schema type must not be only 'null'".to_string())),
+                        [single] => {
+                            out.insert("type".to_string(), (*single).clone());
+                        }
+                        many => {
+                            let any_of = many.iter().map(|t| serde_json::json!({ "type": t })).collect();
+                            out.insert("anyOf".to_string(), Value::Array(any_of));
+                        }
+                    }
+                }
+                _ => {
+                    out.insert("type".to_string(), value.clone());
+                }
+            },
+            "properties" => {
+                let properties = value
+                    .as_object()
+                    .ok_or_else(|| AppError::BadRequest("JSON schema 'properties' must be an object".to_string()))?;
+                let mut converted = serde_json::Map::new();
+                for (name, property) in properties {
+                    converted.insert(name.clone(), convert_schema_node(property, defs, depth + 1)?);
+                }
+                out.insert("properties".to_string(), Value::Object(converted));
+            }
+            "items" => {
+                if value.is_array() {
+                    return Err(AppError::BadRequest("Tuple 'items' are not supported by Gemini".to_string()));
+                }
+                out.insert("items".to_string(), convert_schema_node(value, defs, depth + 1)?);
+            }
+            "anyOf" | "oneOf" => {
+                let members = value
+                    .as_array()
+                    .ok_or_else(|| AppError::BadRequest(format!("JSON schema '{}' must be an array", key)))?;
+                // `{"type": "null"}` members become `nullable`
+                let mut converted = Vec::new();
+                for member in members {
+                    if member.get("type").and_then(Value::as_str) == Some("null") {
+                        out.insert("nullable".to_string(), Value::Bool(true));
+                    } else {
+                        converted.push(convert_schema_node(member, defs, depth + 1)?);
+                    }
+                }
+                match converted.len() {
+                    0 => {}
+                    1 => {
+                        if let Some(Value::Object(member)) = converted.pop() {
+                            for (key, value) in member {
+                                out.entry(key).or_insert(value);
+                            }
+                        }
+                    }
+                    _ => {
+                        out.insert("anyOf".to_string(), Value::Array(converted));
+                    }
+                }
+            }
+            // Gemini only supports string enums; other enums are dropped and
+            // left to strict validation
+            "enum" => {
+                if value.as_array().is_some_and(|values| values.iter().all(Value::is_string)) {
+                    out.insert("enum".to_string(), value.clone());
+                }
+            }
+            "const" => {
+                if value.is_string() {
+                    out.insert("enum".to_string(), Value::Array(vec![value.clone()]));
+                }
+            }
+            key if GEMINI_SCHEMA_KEYWORDS.contains(&key) => {
+                out.insert(key.to_string(), value.clone());
+            }
+            _ => tracing::debug!("Dropping JSON schema keyword unsupported by Gemini: {}", key),
+        }
+    }
 
+    if out.contains_key("enum") && !out.contains_key("type") {
+        out.insert("type".to_string(), Value::String("string".to_string()));
+    }
+    Ok(Value::Object(out))
+}
 
-/// Lists the available models by fetching them from the Gemini API.
-async fn list_models_handler(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<ModelList>, AppError> {
-    info!("Received request to list models");
-    let api_key = state.get_next_api_key();
+/// Checks every choice of a response against the requested JSON schema.
+fn validate_structured_output(
+    validator: &jsonschema::Validator,
+    texts: impl IntoIterator<Item = String>,
+) -> Result<(), AppError> {
+    for text in texts {
+        let instance: serde_json::Value = serde_json::from_str(&text)
+            .map_err(|e| AppError::BadGateway(format!("Model output is not valid JSON: {}", e)))?;
+        if let Err(e) = validator.validate(&instance) {
+            return Err(AppError::BadGateway(format!("Model output does not match the response schema: {}", e)));
+        }
+    }
+    Ok(())
+}
 
-    let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models?key={}",
-        api_key
-    );
+/// Maps `tool_choice` onto Gemini's function calling mode.
+fn convert_tool_choice(choice: OpenAIToolChoice) -> Result<GeminiToolConfig, AppError> {
+    let (mode, allowed_function_names) = match choice {
+        OpenAIToolChoice::Mode(mode) => match mode.as_str() {
+            "none" => ("NONE", None),
+            "auto" => ("AUTO", None),
+            "required" => ("ANY", None),
+            _ => return Err(AppError::BadRequest(format!("Unsupported tool_choice: {}", mode))),
+        },
+        OpenAIToolChoice::Function { function } => ("ANY", Some(vec![function.name])),
+    };
 
-    let client = reqwest::Client::new();
-    let response = client.get(&url).send().await?;
+    Ok(GeminiToolConfig {
+        function_calling_config: GeminiFunctionCallingConfig {
+            mode: mode.to_string(),
+            allowed_function_names,
+        },
+    })
+}
 
-    // Handle potential errors from the Gemini API
-    let status = response.status();
-    if !status.is_success() {
-        let error_body = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Could not read error body".to_string());
-        tracing::error!(
-            "Gemini API returned an error on model list. Status: {}. Body: {}",
-            status,
-            error_body
-        );
-        return Err(AppError::Internal(anyhow::anyhow!(
-            "Upstream Gemini API Error on model list: {} - {}",
-            status,
-            error_body
-        )));
+/// Separates a tool call id from the thought signature appended to it.
+const THOUGHT_SIGNATURE_SEPARATOR: &str = "__thought__";
+
+/// Converts a Gemini function call into an OpenAI tool call. Gemini's own call
+/// id is reused when present so repeated conversions yield the same id.
+///
+/// OpenAI has no place for a thought signature, so it rides in the tool call id,
+/// which clients send back unchanged with the assistant message.
+fn convert_function_call(call: &GeminiFunctionCall, thought_signature: Option<&str>, index: Option<u32>) -> OpenAIToolCall {
+    let mut id = call
+        .id
+        .clone()
+        .unwrap_or_else(|| format!("call_{}", Uuid::new_v4().simple()));
+    if let Some(signature) = thought_signature {
+        id = format!("{}{}{}", id, THOUGHT_SIGNATURE_SEPARATOR, signature);
+    }
+    OpenAIToolCall {
+        index,
+        id,
+        call_type: "function".to_string(),
+        function: OpenAIFunctionCall {
+            name: call.name.clone(),
+            arguments: call.args.to_string(),
+        },
     }
+}
 
-    // Deserialize the successful response
-    let gemini_model_list: GeminiModelList = response.json().await?;
+/// Converts OpenAI message content into Gemini parts.
+///
+/// Media is sent inline when it arrives as a base64 data URL, and by
+/// reference when it points at Cloud Storage (`gs://`) or the Gemini Files API.
+fn convert_content_parts(content: OpenAIContent, gemini: &GeminiClient) -> Result<Vec<GeminiPart>, AppError> {
+    let parts = match content {
+        OpenAIContent::Text(text) => return Ok(vec![GeminiPart::text(text)]),
+        OpenAIContent::Parts(parts) => parts,
+    };
 
-    // Transform the Gemini model list to the OpenAI format
-    let openai_models = gemini_model_list
-        .models
+    parts
         .into_iter()
-        // We only care about models that can be used for chat completions
-        .filter(|m| m.supported_generation_methods.contains(&"generateContent".to_string()))
-        // We also filter for non-embedding models
-        .filter(|m| !m.name.contains("embedding"))
-        .map(|m| ModelObject {
-            // Strip the "models/" prefix to get the clean model ID
-            id: m.name.strip_prefix("models/").unwrap_or(&m.name).to_string(),
-            object: "model".to_string(),
-            created: 1, // Placeholder timestamp, as Gemini API doesn't provide it
-            owned_by: "google".to_string(),
+        .map(|part| match part {
+            OpenAIContentPart::Text { text } => Ok(GeminiPart::text(text)),
+            OpenAIContentPart::ImageUrl { image_url } => convert_media_url(gemini, &image_url.url, None),
+            OpenAIContentPart::InputAudio { input_audio } => Ok(GeminiPart::InlineData {
+                inline_data: GeminiBlob {
+                    mime_type: format!("audio/{}", input_audio.format),
+                    data: input_audio.data,
+                },
+            }),
+            OpenAIContentPart::File { file } => {
+                if let Some(file_data) = file.file_data {
+                    if file_data.starts_with("data:") {
+                        convert_media_url(gemini, &file_data, None)
+                    } else {
+                        // Raw base64 without a data URL prefix; the filename tells us the type
+                        let mime_type = file
+                            .filename
+                            .as_deref()
+                            .and_then(guess_mime_type)
+                            .ok_or_else(|| {
+                                AppError::BadRequest("Cannot determine the MIME type of file_data; use a data URL".to_string())
+                            })?;
+                        Ok(GeminiPart::InlineData {
+                            inline_data: GeminiBlob {
+                                mime_type: mime_type.to_string(),
+                                data: file_data,
+                            },
+                        })
+                    }
+                } else if let Some(file_id) = file.file_id {
+                    convert_media_url(gemini, &file_id, file.filename.as_deref())
+                } else {
+                    Err(AppError::BadRequest("File content part requires file_data or file_id".to_string()))
+                }
+            }
+            OpenAIContentPart::Unsupported => {
+                Err(AppError::BadRequest("Unsupported content part type".to_string()))
+            }
         })
-        .collect();
-
-    Ok(Json(ModelList {
-        object: "list".to_string(),
-        data: openai_models,
-    }))
+        .collect()
 }
 
-/// Handles the chat completions request.
-async fn chat_completions_handler(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<OpenAIChatRequest>,
-) -> Result<Json<OpenAIChatResponse>, AppError> {
-    info!("Received OpenAI chat request for model: {}", payload.model);
-
-    // 1. Select an API key from the pool
-    let api_key = state.get_next_api_key();
-    info!("Using API key ending with: ...{}", &api_key[api_key.len().saturating_sub(4)..]);
-
-    // 2. Convert OpenAI request to Gemini request
-    let model_name = payload.model.clone();
-    let gemini_request = convert_to_gemini_request(payload)?;
-
-    // 3. Send request to Gemini API
-    let client = reqwest::Client::new();
-    let gemini_url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
-        model_name, api_key
-    );
-
-    let response = client
-        .post(&gemini_url)
-        .json(&gemini_request)
-        .send()
-        .await?;
-
-    // Check if the response from Gemini is successful
-    let status = response.status();
-    if !status.is_success() {
-        let error_body = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Could not read error body".to_string());
-        tracing::error!(
-            "Gemini API returned an error. Status: {}. Body: {}",
-            status,
-            error_body
-        );
-        // Forward the error from Gemini to the client
-        return Err(AppError::Internal(anyhow::anyhow!(
-            "Upstream Gemini API Error: {} - {}",
-            status,
-            error_body
-        )));
+/// Converts a media reference (data URL, `gs://` URI or Files API URI) into a Gemini part.
+fn convert_media_url(gemini: &GeminiClient, url: &str, filename: Option<&str>) -> Result<GeminiPart, AppError> {
+    if let Some(rest) = url.strip_prefix("data:") {
+        // data:<mime type>[;<parameter>...];base64,<data>
+        let (header, data) = rest
+            .split_once(',')
+            .filter(|(header, _)| header.ends_with(";base64"))
+            .ok_or_else(|| AppError::BadRequest("Only base64-encoded data URLs are supported".to_string()))?;
+        let mime_type = header.split(';').next().unwrap_or_default();
+        if !mime_type.contains('/') {
+            return Err(AppError::BadRequest("Data URLs must name the MIME type of their content".to_string()));
+        }
+        return Ok(GeminiPart::InlineData {
+            inline_data: GeminiBlob {
+                mime_type: mime_type.to_string(),
+                data: data.to_string(),
+            },
+        });
     }
 
-    // Deserialize the successful response, with robust error handling
-    let response_body = response.text().await?;
-    let gemini_response: GeminiResponse = match serde_json::from_str(&response_body) {
-        Ok(res) => res,
-        Err(e) => {
-            tracing::error!(
-                "Failed to deserialize Gemini response. Status: {}. Error: {}. Body: {}",
-                status,
-                e,
-                response_body
-            );
-            return Err(AppError::Internal(anyhow::anyhow!(
-                "Failed to deserialize upstream Gemini response"
-            )));
-        }
+    let file_uri = if url.starts_with("gs://") {
+        url.to_string()
+    } else if let Some(file_uri) = gemini.file_uri(url) {
+        file_uri
+    } else {
+        return Err(AppError::BadRequest(
+            "Media URLs must be base64 data URLs, gs:// URIs or Gemini Files API URIs".to_string(),
+        ));
     };
 
-    // 4. Convert Gemini response back to OpenAI response
-    let openai_response = convert_to_openai_response(gemini_response, model_name)?;
+    Ok(GeminiPart::FileData {
+        file_data: GeminiFileData {
+            mime_type: guess_mime_type(filename.unwrap_or(url)).map(str::to_string),
+            file_uri,
+        },
+    })
+}
 
-    Ok(Json(openai_response))
+/// Guesses a MIME type from a file name or URI extension.
+fn guess_mime_type(name: &str) -> Option<&'static str> {
+    let extension = name.rsplit_once('.')?.1.to_ascii_lowercase();
+    let mime_type = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "gif" => "image/gif",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        "wav" => "audio/wav",
+        "mp3" => "audio/mp3",
+        "flac" => "audio/flac",
+        "ogg" => "audio/ogg",
+        "aac" => "audio/aac",
+        "mp4" => "video/mp4",
+        "mov" => "video/mov",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(mime_type)
 }
 
-/// Converts an OpenAI request payload into a Gemini request payload.
-fn convert_to_gemini_request(req: OpenAIChatRequest) -> Result<GeminiRequest, AppError> {
-    let mut gemini_contents = Vec::new();
-    let mut system_instruction = None;
+/// Per-stream state carried across Gemini chunks, keyed by choice index.
+#[derive(Default)]
+struct StreamState {
+    choices: HashMap<u32, ChoiceStreamState>,
+}
 
-    for message in req.messages {
-        match message.role.as_str() {
-            "system" => {
-                // The Gemini API currently only supports one system instruction.
-                // If multiple are found, we'll use the first one.
-                if system_instruction.is_none() {
-                    system_instruction = Some(SystemInstruction {
-                        parts: vec![GeminiPart { text: message.content }],
-                    });
+#[derive(Default)]
+struct ChoiceStreamState {
+    /// The first delta of a choice carries the `assistant` role.
+    sent_role: bool,
+    /// Tool calls are numbered per choice across all of its chunks.
+    tool_call_count: u32,
+    finished: bool,
+    /// Text streamed so far, kept for structured output validation.
+    text: String,
+}
+
+/// Converts one streamed Gemini response into OpenAI chunk deltas.
+fn convert_to_openai_chunks(
+    res: GeminiResponse,
+    id: &str,
+    created: u64,
+    model_name: &str,
+    stream_state: &mut StreamState,
+) -> Vec<OpenAIChatChunk> {
+    res.candidates
+        .into_iter()
+        .enumerate()
+        .map(|(position, candidate)| {
+            let index = candidate.index.unwrap_or(position as u32);
+            let choice_state = stream_state.choices.entry(index).or_default();
+            let text: String = candidate.content.parts.iter().filter_map(GeminiPart::as_text).collect();
+            let tool_calls: Vec<OpenAIToolCall> = candidate
+                .content
+                .parts
+                .iter()
+                .filter_map(|part| match part {
+                    GeminiPart::FunctionCall { function_call, thought_signature } => {
+                        let call = convert_function_call(
+                            function_call,
+                            thought_signature.as_deref(),
+                            Some(choice_state.tool_call_count),
+                        );
+                        choice_state.tool_call_count += 1;
+                        Some(call)
+                    }
+                    _ => None,
+                })
+                .collect();
+            choice_state.text.push_str(&text);
+            let role = if choice_state.sent_role {
+                None
+            } else {
+                choice_state.sent_role = true;
+                Some("assistant".to_string())
+            };
+            let finish_reason = candidate.finish_reason.as_deref().map(|reason| {
+                choice_state.finished = true;
+                if choice_state.tool_call_count > 0 {
+                    "tool_calls".to_string()
+                } else {
+                    map_finish_reason(reason)
                 }
+            });
+            OpenAIChatChunk {
+                id: id.to_string(),
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model: model_name.to_string(),
+                choices: vec![OpenAIChunkChoice {
+                    index,
+                    delta: OpenAIDelta {
+                        role,
+                        content: if text.is_empty() && !tool_calls.is_empty() { None } else { Some(text) },
+                        tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+                    },
+                    finish_reason,
+                }],
+                usage: None,
             }
-            "user" | "assistant" => {
-                let role = if message.role == "assistant" { "model" } else { "user" };
-                gemini_contents.push(GeminiContent {
-                    role: role.to_string(),
-                    parts: vec![GeminiPart { text: message.content }],
-                });
-            }
-            _ => return Err(AppError::BadRequest(format!("Unsupported role: {}", message.role))),
-        }
+        })
+        .collect()
+}
+
+/// Maps a Gemini `finishReason` onto the OpenAI equivalent.
+fn map_finish_reason(reason: &str) -> String {
+    match reason {
+        "MAX_TOKENS" => "length",
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" => "content_filter",
+        _ => "stop",
     }
+    .to_string()
+}
 
-    // The Gemini API requires that the conversation starts with a "user" role.
-    // If the first message after a potential system prompt is from the "model",
-    // it's an invalid sequence.
-    if let Some(first_content) = gemini_contents.first() {
-        if first_content.role == "model" {
-            return Err(AppError::BadRequest("Conversation must start with a user message after the system prompt.".to_string()));
-        }
+/// Returns the current Unix timestamp in seconds.
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Converts a Gemini candidate into an OpenAI choice. Returns `None` when the
+/// candidate has neither text nor function calls.
+fn convert_candidate(candidate: GeminiCandidate, index: u32) -> Option<OpenAIChoice> {
+    let parts = &candidate.content.parts;
+    let has_text = parts.iter().any(|part| part.as_text().is_some());
+    let tool_calls: Vec<OpenAIToolCall> = parts
+        .iter()
+        .filter_map(|part| match part {
+            GeminiPart::FunctionCall { function_call, thought_signature } => {
+                Some(convert_function_call(function_call, thought_signature.as_deref(), None))
+            }
+            _ => None,
+        })
+        .collect();
+    if !has_text && tool_calls.is_empty() {
+        return None;
     }
 
+    let text: String = parts.iter().filter_map(GeminiPart::as_text).collect();
+    let finish_reason = if !tool_calls.is_empty() {
+        "tool_calls".to_string()
+    } else {
+        candidate
+            .finish_reason
+            .as_deref()
+            .map(map_finish_reason)
+            .unwrap_or_else(|| "stop".to_string())
+    };
 
-    Ok(GeminiRequest {
-        contents: gemini_contents,
-        system_instruction,
+    Some(OpenAIChoice {
+        index,
+        message: OpenAIMessage {
+            role: "assistant".to_string(),
+            content: if has_text { Some(OpenAIContent::Text(text)) } else { None },
+            tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+            tool_call_id: None,
+        },
+        finish_reason,
     })
 }
 
@@ -871,31 +5339,40 @@ fn convert_to_gemini_request(req: OpenAIChatRequest) -> Result<GeminiRequest, Ap
 fn convert_to_openai_response(
     res: GeminiResponse,
     model_name: String,
+    estimated_prompt_tokens: i32,
 ) -> Result<OpenAIChatResponse, AppError> {
-    let choice = res
+    let usage_metadata = res.usage_metadata;
+    let choices: Vec<OpenAIChoice> = res
         .candidates
         .into_iter()
-        .next()
-        .and_then(|c| c.content.parts.into_iter().next())
-        .map(|part| OpenAIChoice {
-            index: 0,
-            message: OpenAIMessage {
-                role: "assistant".to_string(),
-                content: part.text,
-            },
-            finish_reason: "stop".to_string(),
+        .enumerate()
+        .filter_map(|(position, c)| {
+            let index = c.index.unwrap_or(position as u32);
+            convert_candidate(c, index)
         })
-        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("No content found in Gemini response")))?;
+        .collect();
+    if choices.is_empty() {
+        return Err(AppError::Internal(anyhow::anyhow!("No content found in Gemini response")));
+    }
+
+    let mut completion_text = String::new();
+    for choice in &choices {
+        if let Some(content) = &choice.message.content {
+            completion_text.push_str(&content.text());
+        }
+        for call in choice.message.tool_calls.iter().flatten() {
+            completion_text.push_str(&call.function.arguments);
+        }
+    }
+    let usage = resolve_usage(usage_metadata.as_ref(), estimated_prompt_tokens, &completion_text);
 
     Ok(OpenAIChatResponse {
         id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
         object: "chat.completion".to_string(),
-        created: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs())
-            .unwrap_or(0),
+        created: unix_timestamp(),
         model: model_name,
-        choices: vec![choice],
+        choices,
+        usage,
     })
 }
 
@@ -904,6 +5381,28 @@ fn convert_to_openai_response(
 // Main Function
 //================================================================================
 
+/// `gemini-pool rotate-master-key`: re-encrypts every upstream key and
+/// two-factor secret from `MASTER_KEY`/`MASTER_KEY_FILE` to
+/// `NEW_MASTER_KEY`/`NEW_MASTER_KEY_FILE`.
+async fn rotate_master_key_command() -> anyhow::Result<()> {
+    let old_key = MasterKey::from_env("MASTER_KEY", "MASTER_KEY_FILE")?;
+    let new_key = MasterKey::from_env("NEW_MASTER_KEY", "NEW_MASTER_KEY_FILE")?;
+
+    let database_url = env::var("DATABASE_URL")
+        .unwrap_or_else(|_| "sqlite:./gemini_pool.db".to_string());
+    let db_pool = SqlitePool::connect(&database_url).await?;
+    initialize_database(&db_pool, &old_key).await?;
+
+    let (keys, secrets) = rotate_master_key(&db_pool, &old_key, &new_key).await?;
+    info!("Re-encrypted {} upstream keys and {} two-factor secrets with the new master key", keys, secrets);
+    println!(
+        "Re-encrypted {} upstream keys and {} two-factor secrets. Replace MASTER_KEY with the new key before the next start.",
+        keys, secrets
+    );
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Initialize logging
@@ -914,29 +5413,78 @@ async fn main() -> anyhow::Result<()> {
     // Load .env file
     dotenvy::dotenv().ok();
 
-    // Load API keys from environment
-    let api_keys_str = env::var("GEMINI_API_KEYS")
-        .expect("GEMINI_API_KEYS must be set in .env file");
-    let api_keys: Vec<String> = api_keys_str.split(',').map(|s| s.trim().to_string()).collect();
-    if api_keys.is_empty() || api_keys.iter().any(|k| k.is_empty()) {
-        panic!("GEMINI_API_KEYS must contain at least one non-empty key");
+    if env::args().nth(1).as_deref() == Some("rotate-master-key") {
+        return rotate_master_key_command().await;
     }
-    info!("Loaded {} API keys.", api_keys.len());
 
-    // Load admin credentials
+    // Master key for the upstream keys stored in the database
+    let master_key = MasterKey::from_env("MASTER_KEY", "MASTER_KEY_FILE").context(
+        "Failed to load the master key. Upstream keys and two-factor secrets are stored encrypted \
+         with it. When upgrading from a version without a master key, set MASTER_KEY to a new \
+         key and keep a copy outside the database backups; the existing keys are encrypted \
+         on the next start.",
+    )?;
+
+    // Load initial API keys from environment; more can be added in the admin UI
+    let api_keys: Vec<String> = env::var("GEMINI_API_KEYS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    // Admin credentials only bootstrap the first owner account
     let admin_username = env::var("ADMIN_USERNAME")
         .unwrap_or_else(|_| "admin".to_string());
-    let admin_password = env::var("ADMIN_PASSWORD")
-        .expect("ADMIN_PASSWORD must be set in .env file for admin authentication");
-    if admin_password.is_empty() {
-        panic!("ADMIN_PASSWORD must not be empty");
-    }
+    let admin_password = env::var("ADMIN_PASSWORD").ok().filter(|password| !password.is_empty());
     
     // Load JWT secret
+    // Without a configured secret, access tokens are signed with a random one
+    // and simply refreshed after a restart
     let jwt_secret = env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "default_jwt_secret_change_in_production".to_string());
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .unwrap_or_else(|| {
+            info!("JWT_SECRET is not set; using a random secret for this run.");
+            format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+        });
+    let sessions = SessionConfig::from_env();
     
-    info!("Admin authentication configured.");
+    info!(
+        "Admin authentication configured: access tokens last {}s, sessions {}s.",
+        sessions.access_ttl.as_secs(),
+        sessions.refresh_ttl.as_secs()
+    );
+
+    // Unknown chat completion parameters are logged unless strict mode is on
+    let strict_params = env::var("STRICT_PARAMS")
+        .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+        .unwrap_or(false);
+
+    let gemini = GeminiClient::from_env();
+    info!("Gemini API base URL: {}", gemini.base_url());
+
+    // Retry and failover across upstream keys
+    let retry_policy = RetryPolicy::from_env();
+    info!(
+        "Upstream retry policy: {} attempts, retrying on {:?}",
+        retry_policy.max_attempts, retry_policy.retry_statuses
+    );
+
+    // Brute-force protection for admin logins and client keys
+    let login_guard = LoginGuard::from_env().expect("Failed to read TRUSTED_PROXIES");
+    info!(
+        "Login lockout after {} failed attempts, starting at {}s; trusted proxies: {:?}",
+        login_guard.policy().max_failures,
+        login_guard.policy().lockout.as_secs(),
+        login_guard.trusted_proxies()
+    );
+
+    // Prometheus metrics; open to anyone who can reach /metrics unless a token is set
+    let metrics_token = env::var("METRICS_TOKEN").ok().filter(|token| !token.is_empty());
+    if metrics_token.is_none() {
+        info!("METRICS_TOKEN is not set; /metrics is served without authentication.");
+    }
 
     // Initialize database
     let database_url = env::var("DATABASE_URL")
@@ -946,18 +5494,62 @@ async fn main() -> anyhow::Result<()> {
         .await
         .expect("Failed to connect to database");
     
-    initialize_database(&db_pool)
+    initialize_database(&db_pool, &master_key)
         .await
         .expect("Failed to initialize database");
 
+    let api_key_salt = load_api_key_salt(&db_pool)
+        .await
+        .expect("Failed to load API key salt");
+
+    bootstrap_owner(&db_pool, &admin_username, admin_password.as_deref())
+        .await
+        .expect("Failed to create the first admin user");
+
+    seed_upstream_keys(&db_pool, &master_key, &api_key_salt, &api_keys)
+        .await
+        .expect("Failed to import GEMINI_API_KEYS");
+    let prices = PriceTable::default();
+    prices.set_prices(
+        load_model_prices(&db_pool)
+            .await
+            .expect("Failed to load model prices"),
+    );
+
+    let model_aliases = ModelAliases::default();
+    model_aliases.set_aliases(
+        load_model_aliases(&db_pool)
+            .await
+            .expect("Failed to load model aliases"),
+    );
+
+    let key_pool = KeyPool::new(
+        load_active_upstream_keys(&db_pool, &master_key)
+            .await
+            .expect("Failed to load upstream keys"),
+    );
+    if key_pool.len() == 0 {
+        tracing::warn!("No active upstream API keys. Add keys via GEMINI_API_KEYS or the admin interface.");
+    }
+    info!("Loaded {} API keys.", key_pool.len());
+
     // Create shared state
     let app_state = Arc::new(AppState {
-        api_keys,
-        counter: AtomicUsize::new(0),
+        key_pool,
         db_pool,
         jwt_secret,
-        admin_username,
-        admin_password,
+        sessions,
+        strict_params,
+        retry_policy,
+        rate_limiter: RateLimiter::default(),
+        login_guard,
+        metrics: Arc::new(Metrics::default()),
+        metrics_token,
+        prices,
+        model_aliases,
+        api_key_salt,
+        master_key,
+        gemini,
     });
 
     // Create protected API routes that require client API key authentication
@@ -972,11 +5564,40 @@ async fn main() -> anyhow::Result<()> {
     // Create admin routes that require admin JWT authentication
     let admin_routes = Router::new()
         .route("/admin/api/dashboard", get(admin_dashboard))
+        .route("/admin/api/usage/timeseries", get(admin_usage_timeseries))
+        .route("/admin/api/usage/top", get(admin_usage_top))
+        .route("/admin/api/usage/spend", get(admin_usage_spend))
+        .route("/admin/api/usage/export", get(admin_usage_export))
+        .route("/admin/api/model-prices", get(admin_list_model_prices))
+        .route("/admin/api/model-prices/{model}", put(admin_set_model_price))
+        .route("/admin/api/model-prices/{model}", delete(admin_delete_model_price))
         .route("/admin/api/api-keys", get(admin_list_api_keys))
         .route("/admin/api/api-keys", post(admin_create_api_key))
         .route("/admin/api/api-keys/{id}", get(admin_get_api_key))
         .route("/admin/api/api-keys/{id}", put(admin_update_api_key))
         .route("/admin/api/api-keys/{id}", delete(admin_delete_api_key))
+        .route("/admin/api/model-aliases", get(admin_list_model_aliases))
+        .route("/admin/api/model-aliases/{alias}", put(admin_set_model_alias))
+        .route("/admin/api/model-aliases/{alias}", delete(admin_delete_model_alias))
+        .route("/admin/api/upstream-keys", get(admin_list_upstream_keys))
+        .route("/admin/api/upstream-keys", post(admin_create_upstream_key))
+        .route("/admin/api/upstream-keys/{id}", get(admin_get_upstream_key))
+        .route("/admin/api/upstream-keys/{id}", put(admin_update_upstream_key))
+        .route("/admin/api/upstream-keys/{id}", delete(admin_delete_upstream_key))
+        .route("/admin/api/users", get(admin_list_users))
+        .route("/admin/api/users", post(admin_invite_user))
+        .route("/admin/api/users/{id}", put(admin_update_user))
+        .route("/admin/api/users/{id}/reset-password", post(admin_reset_user_password))
+        .route("/admin/api/users/{id}/revoke-sessions", post(admin_revoke_user_sessions))
+        .route("/admin/api/auth/change-password", post(admin_change_password))
+        .route("/admin/api/auth/logout-all", post(admin_logout_all))
+        .route("/admin/api/auth/totp/setup", post(admin_totp_setup))
+        .route("/admin/api/auth/totp/enable", post(admin_totp_enable))
+        .route("/admin/api/auth/totp/disable", post(admin_totp_disable))
+        .route("/admin/api/auth/totp/recovery-codes", post(admin_totp_recovery_codes))
+        .route("/admin/api/users/{id}/totp", delete(admin_reset_user_totp))
+        .route("/admin/api/lockouts", get(admin_list_lockouts))
+        .route("/admin/api/lockouts/{kind}/{value}", delete(admin_clear_lockout))
         .layer(middleware::from_fn_with_state(
             app_state.clone(),
             admin_auth_middleware,
@@ -985,17 +5606,29 @@ async fn main() -> anyhow::Result<()> {
     // Create public admin auth routes
     let auth_routes = Router::new()
         .route("/admin/api/auth/login", post(admin_login))
-        .route("/admin/api/auth/verify", get(admin_verify_token));
+        .route("/admin/api/auth/login/totp", post(admin_login_totp))
+        .route("/admin/api/auth/verify", get(admin_verify_token))
+        .route("/admin/api/auth/refresh", post(admin_refresh_token))
+        .route("/admin/api/auth/logout", post(admin_logout));
 
     // Create static file service for web interface
     let static_service = ServeDir::new("web");
 
+    // Count and time every API request for /metrics
+    let api_routes = Router::new()
+        .merge(protected_api_routes)
+        .merge(admin_routes)
+        .merge(auth_routes)
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            metrics_middleware,
+        ));
+
     // Create main Axum router
     let app = Router::new()
         .route("/", get(root_handler))
-        .merge(protected_api_routes)
-        .merge(admin_routes)  // API routes must come first to have higher priority
-        .merge(auth_routes)   // Auth routes must come before static service
+        .route("/metrics", get(metrics_handler))
+        .merge(api_routes)  // API and auth routes must come before the static service
         .nest_service("/admin", static_service)  // Static files come last
         .fallback_service(ServeDir::new("web"))
         .layer(CorsLayer::permissive())
@@ -1006,7 +5639,422 @@ async fn main() -> anyhow::Result<()> {
     info!("Starting server on {}", listen_addr);
 
     let listener = tokio::net::TcpListener::bind(&listen_addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    const MASTER_KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+    const NEW_MASTER_KEY: &str = "ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=";
+
+    /// An in-memory database holding one upstream key and one two-factor secret.
+    async fn sealed_database(master_key: &MasterKey) -> SqlitePool {
+        // Every connection to `:memory:` opens a database of its own
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        initialize_database(&pool, master_key).await.unwrap();
+
+        let sealed = master_key.seal("AIzaSy-upstream-key");
+        sqlx::query("INSERT INTO upstream_keys (id, key_hash, key_ciphertext, wrapped_key) VALUES ('key-1', 'hash-1', ?, ?)")
+            .bind(&sealed.ciphertext)
+            .bind(&sealed.wrapped_key)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let sealed = master_key.seal("JBSWY3DPEHPK3PXP");
+        sqlx::query(
+            "INSERT INTO admin_users (id, username, password_hash, role, totp_secret, totp_wrapped_key)
+             VALUES ('user-1', 'owner', 'hash', 'owner', ?, ?)",
+        )
+        .bind(&sealed.ciphertext)
+        .bind(&sealed.wrapped_key)
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool
+    }
+
+    async fn open_secrets(pool: &SqlitePool, master_key: &MasterKey) -> anyhow::Result<(String, String)> {
+        let key = sqlx::query("SELECT key_ciphertext, wrapped_key FROM upstream_keys WHERE id = 'key-1'")
+            .fetch_one(pool)
+            .await?;
+        let user = sqlx::query("SELECT totp_secret, totp_wrapped_key FROM admin_users WHERE id = 'user-1'")
+            .fetch_one(pool)
+            .await?;
+        Ok((
+            master_key.open(key.get("key_ciphertext"), key.get("wrapped_key"))?,
+            master_key.open(user.get("totp_secret"), user.get("totp_wrapped_key"))?,
+        ))
+    }
+
+    #[tokio::test]
+    async fn rotating_the_master_key_re_encrypts_every_secret() {
+        let old_key = MasterKey::parse(MASTER_KEY).unwrap();
+        let new_key = MasterKey::parse(NEW_MASTER_KEY).unwrap();
+        let pool = sealed_database(&old_key).await;
+
+        assert_eq!(rotate_master_key(&pool, &old_key, &new_key).await.unwrap(), (1, 1));
+        let secrets = open_secrets(&pool, &new_key).await.unwrap();
+        assert_eq!(secrets, ("AIzaSy-upstream-key".to_string(), "JBSWY3DPEHPK3PXP".to_string()));
+        assert!(open_secrets(&pool, &old_key).await.is_err());
+
+        // Rotating back restores the original key
+        rotate_master_key(&pool, &new_key, &old_key).await.unwrap();
+        assert_eq!(open_secrets(&pool, &old_key).await.unwrap(), secrets);
+    }
+
+    #[tokio::test]
+    async fn a_failed_rotation_changes_nothing() {
+        let old_key = MasterKey::parse(MASTER_KEY).unwrap();
+        let new_key = MasterKey::parse(NEW_MASTER_KEY).unwrap();
+        let pool = sealed_database(&old_key).await;
+
+        // A secret that does not decrypt aborts the rotation
+        let tampered = old_key.seal("JBSWY3DPEHPK3PXP");
+        let other = old_key.seal("unrelated");
+        sqlx::query("UPDATE admin_users SET totp_secret = ?, totp_wrapped_key = ? WHERE id = 'user-1'")
+            .bind(&tampered.ciphertext)
+            .bind(&other.wrapped_key)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let error = rotate_master_key(&pool, &old_key, &new_key).await.unwrap_err();
+        assert!(error.to_string().contains("admin user user-1"), "{}", error);
+
+        let key = sqlx::query("SELECT key_ciphertext, wrapped_key FROM upstream_keys WHERE id = 'key-1'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(old_key.open(key.get("key_ciphertext"), key.get("wrapped_key")).unwrap(), "AIzaSy-upstream-key");
+
+        // The wrong old key fails the same way
+        let pool = sealed_database(&old_key).await;
+        assert!(rotate_master_key(&pool, &new_key, &old_key).await.is_err());
+        assert!(open_secrets(&pool, &old_key).await.is_ok());
+    }
+
+    fn convert(schema: serde_json::Value) -> serde_json::Value {
+        match convert_json_schema(&schema) {
+            Ok(converted) => converted,
+            Err(_) => panic!("schema was rejected: {}", schema),
+        }
+    }
+
+    #[test]
+    fn refs_are_inlined_from_defs_and_definitions() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "home": { "$ref": "#/$defs/address" },
+                "work": { "$ref": "#/$defs/address" },
+            },
+            "$defs": {
+                "address": { "type": "object", "properties": { "city": { "type": "string" } } },
+            },
+        });
+        let address = json!({ "type": "object", "properties": { "city": { "type": "string" } } });
+        assert_eq!(
+            convert(schema),
+            json!({ "type": "object", "properties": { "home": address, "work": address } })
+        );
+
+        let schema = json!({
+            "type": "array",
+            "items": { "$ref": "#/definitions/tag" },
+            "definitions": { "tag": { "type": "string" } },
+        });
+        assert_eq!(convert(schema), json!({ "type": "array", "items": { "type": "string" } }));
+    }
+
+    #[test]
+    fn unusable_refs_are_rejected() {
+        let recursive = json!({
+            "$ref": "#/$defs/node",
+            "$defs": {
+                "node": { "type": "object", "properties": { "next": { "$ref": "#/$defs/node" } } },
+            },
+        });
+        let remote = json!({ "$ref": "https://example.com/schema.json" });
+        let missing = json!({ "$ref": "#/$defs/missing" });
+        for schema in [recursive, remote, missing] {
+            assert!(matches!(convert_json_schema(&schema), Err(AppError::BadRequest(_))), "{}", schema);
+        }
+    }
+
+    #[test]
+    fn unions_become_any_of_or_nullable() {
+        assert_eq!(
+            convert(json!({ "anyOf": [{ "type": "string" }, { "type": "null" }] })),
+            json!({ "type": "string", "nullable": true })
+        );
+        assert_eq!(
+            convert(json!({ "oneOf": [{ "type": "string" }, { "type": "integer" }] })),
+            json!({ "anyOf": [{ "type": "string" }, { "type": "integer" }] })
+        );
+        assert_eq!(
+            convert(json!({ "type": ["string", "null"] })),
+            json!({ "type": "string", "nullable": true })
+        );
+        assert_eq!(
+            convert(json!({ "type": ["string", "number"] })),
+            json!({ "anyOf": [{ "type": "string" }, { "type": "number" }] })
+        );
+    }
+
+    #[test]
+    fn enums_without_a_type_are_strings() {
+        assert_eq!(
+            convert(json!({ "enum": ["red", "green"] })),
+            json!({ "type": "string", "enum": ["red", "green"] })
+        );
+        assert_eq!(convert(json!({ "const": "red" })), json!({ "type": "string", "enum": ["red"] }));
+        // Gemini only knows string enums; the rest is left to strict validation
+        assert_eq!(convert(json!({ "type": "integer", "enum": [1, 2] })), json!({ "type": "integer" }));
+    }
+
+    #[test]
+    fn unsupported_keywords_are_dropped_or_rejected() {
+        let schema = json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "name": { "type": "string", "minLength": 1, "patternProperties": {} },
+                "age": { "type": "integer", "minimum": 0, "multipleOf": 1 },
+            },
+            "required": ["name"],
+        });
+        assert_eq!(
+            convert(schema),
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string", "minLength": 1 },
+                    "age": { "type": "integer", "minimum": 0 },
+                },
+                "required": ["name"],
+            })
+        );
+
+        assert_eq!(
+            convert(json!({ "allOf": [{ "type": "string" }], "description": "Name" })),
+            json!({ "type": "string", "description": "Name" })
+        );
+        for schema in [
+            json!({ "not": { "type": "string" } }),
+            json!({ "allOf": [{ "type": "string" }, { "minLength": 1 }] }),
+            json!({ "type": "array", "items": [{ "type": "string" }] }),
+        ] {
+            assert!(matches!(convert_json_schema(&schema), Err(AppError::BadRequest(_))), "{}", schema);
+        }
+    }
+
+    /// Converts an OpenAI request body into the JSON sent to Gemini.
+    fn gemini_request(request: serde_json::Value) -> Result<serde_json::Value, AppError> {
+        let request: OpenAIChatRequest = serde_json::from_value(request).unwrap();
+        let gemini = GeminiClient::new("https://generativelanguage.googleapis.com/v1beta");
+        convert_to_gemini_request(request, &gemini).map(|request| serde_json::to_value(request).unwrap())
+    }
+
+    fn converted(request: serde_json::Value) -> serde_json::Value {
+        gemini_request(request.clone()).unwrap_or_else(|_| panic!("request was rejected: {}", request))
+    }
+
+    fn with_params(params: serde_json::Value) -> serde_json::Value {
+        let mut request = json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "Hi" }],
+        });
+        request.as_object_mut().unwrap().extend(params.as_object().unwrap().clone());
+        request
+    }
+
+    #[test]
+    fn sampling_parameters_map_onto_the_generation_config() {
+        let request = converted(with_params(json!({
+            "temperature": 0.5,
+            "top_p": 0.25,
+            "max_tokens": 100,
+            "stop": "END",
+            "seed": 7,
+            "presence_penalty": 0.75,
+            "frequency_penalty": -0.5,
+            "n": 1,
+        })));
+        assert_eq!(
+            request["generationConfig"],
+            json!({
+                "temperature": 0.5,
+                "topP": 0.25,
+                "maxOutputTokens": 100,
+                "stopSequences": ["END"],
+                "seed": 7,
+                "presencePenalty": 0.75,
+                "frequencyPenalty": -0.5,
+            })
+        );
+
+        let request = converted(with_params(json!({
+            "max_tokens": 100,
+            "max_completion_tokens": 200,
+            "stop": ["END", "STOP"],
+            "n": 3,
+        })));
+        assert_eq!(
+            request["generationConfig"],
+            json!({ "maxOutputTokens": 200, "stopSequences": ["END", "STOP"], "candidateCount": 3 })
+        );
+
+        assert_eq!(converted(with_params(json!({})))["generationConfig"], json!({}));
+        assert!(matches!(gemini_request(with_params(json!({ "n": 0 }))), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn every_candidate_becomes_a_choice() {
+        let candidate = |index: u32, text: &str| {
+            json!({
+                "index": index,
+                "content": { "role": "model", "parts": [{ "text": text }] },
+                "finishReason": "STOP",
+            })
+        };
+        let response: GeminiResponse = serde_json::from_value(json!({
+            "candidates": [candidate(0, "one"), candidate(1, "two"), candidate(2, "three")],
+        }))
+        .unwrap();
+        let Ok(response) = convert_to_openai_response(response, "gemini-2.5-flash".to_string(), 5) else {
+            panic!("response was rejected");
+        };
+        let choices: Vec<(u32, String)> = response
+            .choices
+            .iter()
+            .map(|choice| (choice.index, choice.message.content.as_ref().unwrap().text()))
+            .collect();
+        assert_eq!(choices, [(0, "one".to_string()), (1, "two".to_string()), (2, "three".to_string())]);
+
+        // Streamed candidates keep their own index as well
+        let chunk: GeminiResponse = serde_json::from_value(json!({
+            "candidates": [candidate(1, "two"), candidate(0, "one")],
+        }))
+        .unwrap();
+        let chunks = convert_to_openai_chunks(chunk, "chatcmpl-1", 0, "gemini-2.5-flash", &mut StreamState::default());
+        let indexes: Vec<u32> = chunks.iter().map(|chunk| chunk.choices[0].index).collect();
+        assert_eq!(indexes, [1, 0]);
+    }
+
+    #[test]
+    fn unknown_parameters_are_rejected_only_in_strict_mode() {
+        let extra: HashMap<String, serde_json::Value> = serde_json::from_value(json!({
+            "logit_bias": {},
+            "logprobs": true,
+            "user": "user-1",
+        }))
+        .unwrap();
+        match check_unknown_params(&extra, true) {
+            Err(AppError::BadRequest(message)) => assert_eq!(message, "Unsupported parameter(s): logit_bias, logprobs"),
+            _ => panic!("unknown parameters were accepted in strict mode"),
+        }
+        assert!(check_unknown_params(&extra, false).is_ok());
+
+        // Parameters without a Gemini equivalent are dropped even in strict mode
+        let ignored: HashMap<String, serde_json::Value> =
+            IGNORED_OPENAI_PARAMS.iter().map(|name| (name.to_string(), json!(null))).collect();
+        assert!(check_unknown_params(&ignored, true).is_ok());
+
+        // Unknown parameters end up in `extra`, known ones do not
+        let request = with_params(json!({ "temperature": 1.0, "logprobs": true }));
+        let request: OpenAIChatRequest = serde_json::from_value(request).unwrap();
+        assert_eq!(request.extra.keys().collect::<Vec<_>>(), ["logprobs"]);
+    }
+
+    /// The Gemini parts of a single user message with the given content parts.
+    fn user_parts(content: serde_json::Value) -> Result<serde_json::Value, AppError> {
+        let request = json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": content }],
+        });
+        gemini_request(request).map(|request| request["contents"][0]["parts"].clone())
+    }
+
+    #[test]
+    fn data_urls_are_sent_inline() {
+        let parts = user_parts(json!([
+            { "type": "text", "text": "What is this?" },
+            { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" } },
+            { "type": "image_url", "image_url": { "url": "data:image/jpeg;name=cat.jpg;base64,/9j/4AAQ", "detail": "low" } },
+        ]));
+        assert_eq!(
+            parts.ok().unwrap(),
+            json!([
+                { "text": "What is this?" },
+                { "inlineData": { "mimeType": "image/png", "data": "iVBORw0KGgo=" } },
+                { "inlineData": { "mimeType": "image/jpeg", "data": "/9j/4AAQ" } },
+            ])
+        );
+    }
+
+    #[test]
+    fn audio_and_files_are_sent_inline() {
+        let parts = user_parts(json!([
+            { "type": "input_audio", "input_audio": { "data": "UklGRg==", "format": "wav" } },
+            { "type": "file", "file": { "file_data": "data:application/pdf;base64,JVBERi0=" } },
+            { "type": "file", "file": { "file_data": "JVBERi0=", "filename": "report.PDF" } },
+        ]));
+        assert_eq!(
+            parts.ok().unwrap(),
+            json!([
+                { "inlineData": { "mimeType": "audio/wav", "data": "UklGRg==" } },
+                { "inlineData": { "mimeType": "application/pdf", "data": "JVBERi0=" } },
+                { "inlineData": { "mimeType": "application/pdf", "data": "JVBERi0=" } },
+            ])
+        );
+    }
+
+    #[test]
+    fn remote_media_becomes_file_data() {
+        let parts = user_parts(json!([
+            { "type": "image_url", "image_url": { "url": "gs://bucket/photos/cat.webp" } },
+            { "type": "image_url", "image_url": { "url": "https://generativelanguage.googleapis.com/v1beta/files/abc-123" } },
+            { "type": "file", "file": { "file_id": "files/def-456", "filename": "notes.txt" } },
+        ]));
+        assert_eq!(
+            parts.ok().unwrap(),
+            json!([
+                { "fileData": { "mimeType": "image/webp", "fileUri": "gs://bucket/photos/cat.webp" } },
+                { "fileData": { "fileUri": "https://generativelanguage.googleapis.com/v1beta/files/abc-123" } },
+                {
+                    "fileData": {
+                        "mimeType": "text/plain",
+                        "fileUri": "https://generativelanguage.googleapis.com/v1beta/files/def-456",
+                    },
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn unusable_content_parts_are_rejected() {
+        let image = |url: &str| json!([{ "type": "image_url", "image_url": { "url": url } }]);
+        for content in [
+            // Not base64, or no MIME type
+            image("data:image/png,iVBORw0KGgo="),
+            image("data:;base64,iVBORw0KGgo="),
+            image("data:base64,iVBORw0KGgo="),
+            // Plain web URLs are not fetched
+            image("https://example.com/cat.png"),
+            json!([{ "type": "file", "file": { "file_data": "JVBERi0=" } }]),
+            json!([{ "type": "file", "file": { "filename": "report.pdf" } }]),
+            json!([{ "type": "video_url", "video_url": { "url": "gs://bucket/clip.mp4" } }]),
+        ] {
+            assert!(matches!(user_parts(content.clone()), Err(AppError::BadRequest(_))), "{}", content);
+        }
+    }
+}
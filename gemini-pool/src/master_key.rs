@@ -0,0 +1,151 @@
+//! Envelope encryption for upstream Gemini keys stored in the database.
+//!
+//! Every key is encrypted with its own random data key, and the data key is
+//! encrypted ("wrapped") with the master key, both with AES-256-GCM. The
+//! master key comes from `MASTER_KEY` or the file named by `MASTER_KEY_FILE`
+//! and is never written to the database, so a copy of the database alone
+//! does not reveal any key.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::env;
+
+const NONCE_LEN: usize = 12;
+
+/// An upstream key encrypted for storage, both parts base64 encoded.
+pub struct SealedSecret {
+    /// Nonce and ciphertext of the key under its data key.
+    pub ciphertext: String,
+    /// Nonce and ciphertext of the data key under the master key.
+    pub wrapped_key: String,
+}
+
+pub struct MasterKey(Aes256Gcm);
+
+impl MasterKey {
+    /// Parses a base64 encoded 32-byte key, e.g. the output of `openssl rand -base64 32`.
+    pub fn parse(encoded: &str) -> anyhow::Result<Self> {
+        let bytes = BASE64
+            .decode(encoded.trim())
+            .context("master key is not valid base64")?;
+        if bytes.len() != 32 {
+            bail!("master key must be 32 bytes, got {}", bytes.len());
+        }
+        Ok(Self(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes))))
+    }
+
+    /// Reads the master key from the variable `var`, or from the file named by `file_var`.
+    pub fn from_env(var: &str, file_var: &str) -> anyhow::Result<Self> {
+        if let Ok(encoded) = env::var(var) {
+            return Self::parse(&encoded).with_context(|| format!("invalid {}", var));
+        }
+        if let Ok(path) = env::var(file_var) {
+            let encoded = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {} ({})", file_var, path))?;
+            return Self::parse(&encoded).with_context(|| format!("invalid key in {}", path));
+        }
+        bail!(
+            "{} or {} must be set. Generate a key with `openssl rand -base64 32`.",
+            var,
+            file_var
+        )
+    }
+
+    /// Encrypts `plaintext` under a fresh data key.
+    pub fn seal(&self, plaintext: &str) -> SealedSecret {
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        SealedSecret {
+            ciphertext: encrypt(&Aes256Gcm::new(&data_key), plaintext.as_bytes()),
+            wrapped_key: encrypt(&self.0, &data_key),
+        }
+    }
+
+    /// Decrypts a secret sealed with this master key.
+    pub fn open(&self, ciphertext: &str, wrapped_key: &str) -> anyhow::Result<String> {
+        let data_key = decrypt(&self.0, wrapped_key)
+            .context("failed to unwrap data key; is the master key correct?")?;
+        if data_key.len() != 32 {
+            bail!("wrapped data key has the wrong length");
+        }
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key));
+        let plaintext = decrypt(&cipher, ciphertext)?;
+        String::from_utf8(plaintext).context("decrypted key is not valid UTF-8")
+    }
+}
+
+fn encrypt(cipher: &Aes256Gcm, plaintext: &[u8]) -> String {
+    let nonce = Aes256Gcm::generate_nonce(OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .expect("AES-GCM encryption does not fail for in-memory buffers");
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    BASE64.encode(sealed)
+}
+
+fn decrypt(cipher: &Aes256Gcm, encoded: &str) -> anyhow::Result<Vec<u8>> {
+    let sealed = BASE64.decode(encoded).context("ciphertext is not valid base64")?;
+    if sealed.len() < NONCE_LEN {
+        bail!("ciphertext is too short");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("decryption failed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+    const OTHER_KEY: &str = "ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=";
+
+    #[test]
+    fn sealed_secrets_open_again() {
+        let master_key = MasterKey::parse(KEY).unwrap();
+        let sealed = master_key.seal("AIzaSy-upstream-key");
+        assert!(!sealed.ciphertext.contains("upstream"));
+        assert_eq!(master_key.open(&sealed.ciphertext, &sealed.wrapped_key).unwrap(), "AIzaSy-upstream-key");
+
+        // Every seal uses a fresh data key and nonce
+        let again = master_key.seal("AIzaSy-upstream-key");
+        assert_ne!(again.ciphertext, sealed.ciphertext);
+        assert_ne!(again.wrapped_key, sealed.wrapped_key);
+    }
+
+    #[test]
+    fn a_wrong_master_key_cannot_open_secrets() {
+        let sealed = MasterKey::parse(KEY).unwrap().seal("AIzaSy-upstream-key");
+        let other = MasterKey::parse(OTHER_KEY).unwrap();
+        let error = other.open(&sealed.ciphertext, &sealed.wrapped_key).unwrap_err();
+        assert!(error.to_string().contains("is the master key correct"), "{}", error);
+    }
+
+    #[test]
+    fn tampered_secrets_are_rejected() {
+        let master_key = MasterKey::parse(KEY).unwrap();
+        let sealed = master_key.seal("AIzaSy-upstream-key");
+        let flip_last_byte = |encoded: &str| {
+            let mut bytes = BASE64.decode(encoded).unwrap();
+            *bytes.last_mut().unwrap() ^= 1;
+            BASE64.encode(bytes)
+        };
+
+        assert!(master_key.open(&flip_last_byte(&sealed.ciphertext), &sealed.wrapped_key).is_err());
+        assert!(master_key.open(&sealed.ciphertext, &flip_last_byte(&sealed.wrapped_key)).is_err());
+        assert!(master_key.open("AAAA", &sealed.wrapped_key).is_err());
+        // A data key from another secret does not open this one
+        let other = master_key.seal("another-key");
+        assert!(master_key.open(&sealed.ciphertext, &other.wrapped_key).is_err());
+    }
+
+    #[test]
+    fn master_keys_must_be_32_bytes_of_base64() {
+        assert!(MasterKey::parse(&format!("  {}\n", KEY)).is_ok());
+        assert!(MasterKey::parse("not base64!").is_err());
+        assert!(MasterKey::parse(&BASE64.encode([0u8; 16])).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Prometheus metrics, served at `/metrics` in the text exposition format.
+//!
+//! Everything is counted in memory since the last start, which is what
+//! Prometheus expects from counters. Requests are timed until their body has
+//! been sent, so a streamed completion counts as in flight, and towards the
+//! latency histogram, for as long as the stream runs.
+//!
+//! Client keys and upstream keys are labelled with their ids, never with the
+//! keys themselves. Models are labelled with their name only when the pool
+//! knows them; anything else a client sends is counted as [`OTHER_MODEL`], so
+//! made-up names cannot add series without bound.
+
+use crate::key_pool::{KeyHealth, KeyStatus};
+use hmac::{Hmac, Mac};
+use http::StatusCode;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Model label of requests for models the pool does not know.
+pub const OTHER_MODEL: &str = "other";
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct RequestKey {
+    route: String,
+    model: String,
+    status: u16,
+    client_key: String,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<HashMap<RequestKey, Histogram>>,
+    in_flight: Mutex<HashMap<String, i64>>,
+    /// Attempts by upstream key id and status; `error` for connection failures.
+    upstream_attempts: Mutex<HashMap<(String, String), u64>>,
+    /// Tokens by client key id, model and `prompt` or `completion`.
+    tokens: Mutex<HashMap<(String, String, &'static str), u64>>,
+    /// Models in the last model list fetched from Gemini.
+    upstream_models: Mutex<HashSet<String>>,
+}
+
+impl Metrics {
+    /// Starts timing a request to `route`. It counts as in flight until the
+    /// returned timer is dropped.
+    pub fn start_request(self: &Arc<Self>, route: &str) -> RequestTimer {
+        *self.in_flight.lock().unwrap().entry(route.to_string()).or_default() += 1;
+        RequestTimer {
+            metrics: self.clone(),
+            route: route.to_string(),
+            started: Instant::now(),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            labels: RequestLabels::default(),
+        }
+    }
+
+    /// Counts one attempt against an upstream key. `status` is `None` when no
+    /// response arrived.
+    pub fn record_upstream_attempt(&self, upstream_key_id: &str, status: Option<u16>) {
+        let status = status.map_or_else(|| "error".to_string(), |status| status.to_string());
+        *self
+            .upstream_attempts
+            .lock()
+            .unwrap()
+            .entry((upstream_key_id.to_string(), status))
+            .or_default() += 1;
+    }
+
+    pub fn set_upstream_models(&self, models: HashSet<String>) {
+        *self.upstream_models.lock().unwrap() = models;
+    }
+
+    pub fn is_upstream_model(&self, model: &str) -> bool {
+        self.upstream_models.lock().unwrap().contains(model)
+    }
+
+    pub fn record_tokens(&self, client_key_id: &str, model: &str, prompt_tokens: i32, completion_tokens: i32) {
+        let mut tokens = self.tokens.lock().unwrap();
+        for (kind, count) in [("prompt", prompt_tokens), ("completion", completion_tokens)] {
+            if count > 0 {
+                *tokens
+                    .entry((client_key_id.to_string(), model.to_string(), kind))
+                    .or_default() += count as u64;
+            }
+        }
+    }
+
+    /// All metrics in the Prometheus text format, with pool health gauges
+    /// taken from `pool`.
+    pub fn render(&self, pool: &[KeyStatus]) -> String {
+        let mut out = String::new();
+
+        let requests = self.requests.lock().unwrap();
+        let requests: BTreeMap<_, _> = requests.iter().collect();
+        header(&mut out, "gemini_pool_requests_total", "counter", "Requests handled, by route, model, status and client key.");
+        for (key, histogram) in &requests {
+            let _ = writeln!(out, "gemini_pool_requests_total{{{}}} {}", request_labels(key), histogram.count);
+        }
+        header(
+            &mut out,
+            "gemini_pool_request_duration_seconds",
+            "histogram",
+            "Time until the response, including a streamed body, was sent.",
+        );
+        for (key, histogram) in &requests {
+            let labels = request_labels(key);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "gemini_pool_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "gemini_pool_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(out, "gemini_pool_request_duration_seconds_sum{{{}}} {}", labels, histogram.sum);
+            let _ = writeln!(out, "gemini_pool_request_duration_seconds_count{{{}}} {}", labels, histogram.count);
+        }
+        drop(requests);
+
+        header(&mut out, "gemini_pool_requests_in_flight", "gauge", "Requests being handled, by route.");
+        let in_flight = self.in_flight.lock().unwrap();
+        for (route, count) in in_flight.iter().collect::<BTreeMap<_, _>>() {
+            let _ = writeln!(out, "gemini_pool_requests_in_flight{{route=\"{}\"}} {}", escape(route), count);
+        }
+        drop(in_flight);
+
+        header(
+            &mut out,
+            "gemini_pool_upstream_attempts_total",
+            "counter",
+            "Requests sent to Gemini, by upstream key id and status.",
+        );
+        let attempts = self.upstream_attempts.lock().unwrap();
+        for ((key, status), count) in attempts.iter().collect::<BTreeMap<_, _>>() {
+            let _ = writeln!(
+                out,
+                "gemini_pool_upstream_attempts_total{{upstream_key=\"{}\",status=\"{}\"}} {}",
+                escape(key),
+                status,
+                count
+            );
+        }
+        drop(attempts);
+
+        header(&mut out, "gemini_pool_upstream_keys", "gauge", "Active upstream keys, by health state.");
+        for state in ["healthy", "cooling_down", "disabled"] {
+            let count = pool.iter().filter(|key| health_state(&key.health) == state).count();
+            let _ = writeln!(out, "gemini_pool_upstream_keys{{state=\"{}\"}} {}", state, count);
+        }
+        header(
+            &mut out,
+            "gemini_pool_upstream_key_healthy",
+            "gauge",
+            "1 if the upstream key can be selected, 0 while cooling down or disabled.",
+        );
+        for key in pool {
+            let healthy = matches!(key.health, KeyHealth::Healthy) as u8;
+            let _ = writeln!(out, "gemini_pool_upstream_key_healthy{{upstream_key=\"{}\"}} {}", escape(&key.id), healthy);
+        }
+
+        header(&mut out, "gemini_pool_tokens_total", "counter", "Tokens used, by client key, model and type.");
+        let tokens = self.tokens.lock().unwrap();
+        for ((client_key, model, kind), count) in tokens.iter().collect::<BTreeMap<_, _>>() {
+            let _ = writeln!(
+                out,
+                "gemini_pool_tokens_total{{client_key=\"{}\",model=\"{}\",type=\"{}\"}} {}",
+                escape(client_key),
+                escape(model),
+                kind,
+                count
+            );
+        }
+
+        out
+    }
+}
+
+/// Labels that are only known once a request reaches the auth middleware or
+/// its handler. Shared through the request extensions.
+#[derive(Debug, Clone, Default)]
+pub struct RequestLabels(Arc<Mutex<(String, String)>>);
+
+impl RequestLabels {
+    pub fn set_client_key(&self, client_key_id: &str) {
+        self.0.lock().unwrap().0 = client_key_id.to_string();
+    }
+
+    pub fn set_model(&self, model: &str) {
+        self.0.lock().unwrap().1 = model.to_string();
+    }
+}
+
+/// Times a request and records it when dropped.
+pub struct RequestTimer {
+    metrics: Arc<Metrics>,
+    route: String,
+    started: Instant,
+    status: StatusCode,
+    labels: RequestLabels,
+}
+
+impl RequestTimer {
+    pub fn labels(&self) -> RequestLabels {
+        self.labels.clone()
+    }
+
+    pub fn set_status(&mut self, status: StatusCode) {
+        self.status = status;
+    }
+}
+
+impl Drop for RequestTimer {
+    fn drop(&mut self) {
+        let (client_key, model) = self.labels.0.lock().unwrap().clone();
+        let key = RequestKey {
+            route: std::mem::take(&mut self.route),
+            model,
+            status: self.status.as_u16(),
+            client_key,
+        };
+
+        if let Some(count) = self.metrics.in_flight.lock().unwrap().get_mut(&key.route) {
+            *count -= 1;
+        }
+        self.metrics
+            .requests
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .observe(self.started.elapsed().as_secs_f64());
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn request_labels(key: &RequestKey) -> String {
+    format!(
+        "route=\"{}\",model=\"{}\",status=\"{}\",client_key=\"{}\"",
+        escape(&key.route),
+        escape(&key.model),
+        key.status,
+        escape(&key.client_key)
+    )
+}
+
+fn health_state(health: &KeyHealth) -> &'static str {
+    match health {
+        KeyHealth::Healthy => "healthy",
+        KeyHealth::CoolingDown { .. } => "cooling_down",
+        KeyHealth::Disabled { .. } => "disabled",
+    }
+}
+
+/// Whether `provided` is the metrics token. Both are hashed and compared in
+/// constant time, so response times do not tell how much of a guess matched.
+pub fn token_matches(token: &str, provided: &str) -> bool {
+    let digest = |value: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"gemini-pool metrics token").expect("HMAC accepts keys of any length");
+        mac.update(value.as_bytes());
+        mac
+    };
+    digest(provided).verify_slice(&digest(token).finalize().into_bytes()).is_ok()
+}
+
+/// Escapes a label value as the exposition format requires.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
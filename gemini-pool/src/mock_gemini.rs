@@ -0,0 +1,391 @@
+//! A scripted stand-in for the Gemini API, for tests and offline development.
+//!
+//! It serves `GET /v1beta/models` and `POST /v1beta/models/{model}:generateContent`
+//! / `:streamGenerateContent`. Generation requests are answered from a queue of
+//! scripted [`MockResponse`]s, or from a response pinned to an API key; with
+//! nothing scripted they get a short text reply. Requests without an
+//! `x-goog-api-key` header are rejected like Gemini does, and every request is
+//! recorded so tests can check what the proxy sent.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, RawQuery, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// Prompt tokens reported for every generation. Reply tokens are one per word.
+pub const PROMPT_TOKENS: i64 = 5;
+
+/// Reply used when nothing is scripted.
+pub const DEFAULT_REPLY: &str = "Hello from mock Gemini";
+
+const DEFAULT_MODELS: &[&str] = &["gemini-2.5-pro", "gemini-2.5-flash", "gemini-2.0-flash"];
+
+/// A scripted response. Text replies adapt to the endpoint: a `Stream` sent to
+/// `generateContent` is joined into one reply, and a `Text` sent to
+/// `streamGenerateContent` becomes a single event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MockResponse {
+    /// A reply with the given text.
+    Text { text: String },
+    /// A streamed reply, one SSE event per chunk.
+    Stream { chunks: Vec<String> },
+    /// A Gemini error body, e.g. status 429 with reason `RATE_LIMIT_EXCEEDED`
+    /// or status 400 with reason `API_KEY_INVALID`.
+    Error {
+        status: u16,
+        message: String,
+        #[serde(default)]
+        reason: Option<String>,
+        /// Sent as `Retry-After`, in seconds.
+        #[serde(default)]
+        retry_after: Option<u64>,
+    },
+    /// Any status and JSON body, sent as-is.
+    Raw { status: u16, body: Value },
+    /// An SSE body, sent as-is with status 200.
+    RawStream { body: String },
+}
+
+impl MockResponse {
+    pub fn text(text: &str) -> Self {
+        Self::Text { text: text.to_string() }
+    }
+
+    pub fn stream(chunks: &[&str]) -> Self {
+        Self::Stream {
+            chunks: chunks.iter().map(|chunk| chunk.to_string()).collect(),
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        Self::Error {
+            status,
+            message: message.to_string(),
+            reason: None,
+            retry_after: None,
+        }
+    }
+
+    /// A 429 that asks to retry after `seconds`.
+    pub fn rate_limited(seconds: u64) -> Self {
+        Self::Error {
+            status: 429,
+            message: "Resource has been exhausted (e.g. check quota).".to_string(),
+            reason: Some("RATE_LIMIT_EXCEEDED".to_string()),
+            retry_after: Some(seconds),
+        }
+    }
+
+    /// The 400 Gemini returns for a key that does not exist.
+    pub fn invalid_key() -> Self {
+        Self::Error {
+            status: 400,
+            message: "API key not valid. Please pass a valid API key.".to_string(),
+            reason: Some("API_KEY_INVALID".to_string()),
+            retry_after: None,
+        }
+    }
+
+    /// Renders a non-text response, or returns `None` for text replies.
+    fn error_response(&self) -> Option<Response> {
+        match self {
+            Self::Text { .. } | Self::Stream { .. } => None,
+            Self::Error {
+                status,
+                message,
+                reason,
+                retry_after,
+            } => {
+                let status = StatusCode::from_u16(*status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                let mut error = json!({
+                    "code": status.as_u16(),
+                    "message": message,
+                    "status": status_name(status),
+                });
+                if let Some(reason) = reason {
+                    error["details"] = json!([{
+                        "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+                        "reason": reason,
+                        "domain": "googleapis.com",
+                    }]);
+                }
+                let mut response = (status, Json(json!({ "error": error }))).into_response();
+                if let Some(seconds) = retry_after {
+                    response
+                        .headers_mut()
+                        .insert(http::header::RETRY_AFTER, HeaderValue::from(*seconds));
+                }
+                Some(response)
+            }
+            Self::Raw { status, body } => Some(
+                (
+                    StatusCode::from_u16(*status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                    Json(body.clone()),
+                )
+                    .into_response(),
+            ),
+            Self::RawStream { body } => {
+                Some(([(http::header::CONTENT_TYPE, "text/event-stream")], body.clone()).into_response())
+            }
+        }
+    }
+
+    fn chunks(&self) -> Vec<String> {
+        match self {
+            Self::Text { text } => vec![text.clone()],
+            Self::Stream { chunks } => chunks.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A request received by the mock.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    /// The `x-goog-api-key` header.
+    pub api_key: Option<String>,
+    pub body: Option<Value>,
+}
+
+#[derive(Default)]
+struct MockState {
+    queue: Mutex<VecDeque<MockResponse>>,
+    key_responses: Mutex<HashMap<String, MockResponse>>,
+    models: Mutex<Vec<String>>,
+    requests: Mutex<Vec<RecordedRequest>>,
+}
+
+impl MockState {
+    fn record(&self, method: &str, path: String, query: Option<String>, headers: &HeaderMap, body: &[u8]) -> Option<String> {
+        let api_key = headers
+            .get("x-goog-api-key")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        self.requests.lock().unwrap().push(RecordedRequest {
+            method: method.to_string(),
+            path,
+            query,
+            api_key: api_key.clone(),
+            body: serde_json::from_slice(body).ok(),
+        });
+        api_key
+    }
+
+    fn key_response(&self, api_key: &str) -> Option<MockResponse> {
+        self.key_responses.lock().unwrap().get(api_key).cloned()
+    }
+}
+
+/// A running mock server. It stops when dropped.
+pub struct MockGemini {
+    state: Arc<MockState>,
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl MockGemini {
+    /// Starts a mock on a free local port.
+    pub async fn start() -> std::io::Result<Self> {
+        Self::bind("127.0.0.1:0").await
+    }
+
+    /// Starts a mock on `addr`, e.g. `127.0.0.1:8090`.
+    pub async fn bind(addr: &str) -> std::io::Result<Self> {
+        let state = Arc::new(MockState::default());
+        *state.models.lock().unwrap() = DEFAULT_MODELS.iter().map(|model| model.to_string()).collect();
+
+        let app = Router::new()
+            .route("/v1beta/models", get(list_models))
+            .route("/v1beta/models/{call}", post(call_model))
+            .with_state(state.clone());
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                eprintln!("mock Gemini server stopped: {}", e);
+            }
+        });
+
+        Ok(Self { state, addr, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The value to use for `GEMINI_BASE_URL`.
+    pub fn base_url(&self) -> String {
+        format!("http://{}/v1beta", self.addr)
+    }
+
+    /// Queues a response for the next generation request.
+    pub fn push(&self, response: MockResponse) {
+        self.state.queue.lock().unwrap().push_back(response);
+    }
+
+    /// Answers every request made with `api_key` with `response`, ahead of the
+    /// queue. Error responses apply to `models` requests too.
+    pub fn respond_for_key(&self, api_key: &str, response: MockResponse) {
+        self.state
+            .key_responses
+            .lock()
+            .unwrap()
+            .insert(api_key.to_string(), response);
+    }
+
+    /// Replaces the models returned by `GET /models`.
+    pub fn set_models(&self, models: &[&str]) {
+        *self.state.models.lock().unwrap() = models.iter().map(|model| model.to_string()).collect();
+    }
+
+    /// Every request received so far, oldest first.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// Runs until the server stops.
+    pub async fn wait(mut self) {
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for MockGemini {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn list_models(
+    State(state): State<Arc<MockState>>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Response {
+    let Some(api_key) = state.record("GET", "/v1beta/models".to_string(), query, &headers, &[]) else {
+        return missing_key();
+    };
+    if let Some(response) = state.key_response(&api_key).and_then(|response| response.error_response()) {
+        return response;
+    }
+
+    let models: Vec<Value> = state
+        .models
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|model| {
+            json!({
+                "name": format!("models/{}", model),
+                "displayName": model,
+                "supportedGenerationMethods": ["generateContent", "countTokens"],
+            })
+        })
+        .collect();
+    Json(json!({ "models": models })).into_response()
+}
+
+async fn call_model(
+    State(state): State<Arc<MockState>>,
+    Path(call): Path<String>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let path = format!("/v1beta/models/{}", call);
+    let Some(api_key) = state.record("POST", path, query, &headers, &body) else {
+        return missing_key();
+    };
+
+    let response = state
+        .key_response(&api_key)
+        .or_else(|| state.queue.lock().unwrap().pop_front())
+        .unwrap_or_else(|| MockResponse::text(DEFAULT_REPLY));
+    if let Some(response) = response.error_response() {
+        return response;
+    }
+
+    let chunks = response.chunks();
+    match call.split_once(':').map(|(_, method)| method) {
+        Some("generateContent") => {
+            let text = chunks.concat();
+            let mut reply = generate_response(&text, true);
+            reply["usageMetadata"] = usage(&text);
+            Json(reply).into_response()
+        }
+        Some("streamGenerateContent") => stream_response(&chunks),
+        _ => MockResponse::error(404, &format!("Unknown method in {}", call))
+            .error_response()
+            .unwrap(),
+    }
+}
+
+/// One `generateContent` response, or one streamed chunk of it.
+fn generate_response(text: &str, last: bool) -> Value {
+    let mut candidate = json!({
+        "content": { "role": "model", "parts": [{ "text": text }] },
+        "index": 0,
+    });
+    if last {
+        candidate["finishReason"] = json!("STOP");
+    }
+    json!({ "candidates": [candidate] })
+}
+
+fn usage(text: &str) -> Value {
+    let reply_tokens = text.split_whitespace().count() as i64;
+    json!({
+        "promptTokenCount": PROMPT_TOKENS,
+        "candidatesTokenCount": reply_tokens,
+        "totalTokenCount": PROMPT_TOKENS + reply_tokens,
+    })
+}
+
+fn stream_response(chunks: &[String]) -> Response {
+    let mut body = String::new();
+    let mut text = String::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        text.push_str(chunk);
+        let mut event = generate_response(chunk, index + 1 == chunks.len());
+        // Gemini repeats the running usage totals on every chunk
+        event["usageMetadata"] = usage(&text);
+        body.push_str(&format!("data: {}\r\n\r\n", event));
+    }
+    ([(http::header::CONTENT_TYPE, "text/event-stream")], body).into_response()
+}
+
+fn missing_key() -> Response {
+    MockResponse::error(
+        403,
+        "Method doesn't allow unregistered callers. Please use API Key or other form of API consumer identity.",
+    )
+    .error_response()
+    .unwrap()
+}
+
+fn status_name(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 => "INVALID_ARGUMENT",
+        401 => "UNAUTHENTICATED",
+        403 => "PERMISSION_DENIED",
+        404 => "NOT_FOUND",
+        429 => "RESOURCE_EXHAUSTED",
+        500 => "INTERNAL",
+        503 => "UNAVAILABLE",
+        504 => "DEADLINE_EXCEEDED",
+        _ => "UNKNOWN",
+    }
+}
@@ -0,0 +1,104 @@
+//! Per-client-key model allowlists and global model aliases.
+//!
+//! Allowlist patterns are model names where `*` matches any run of
+//! characters, e.g. `gemini-2.5-*`. An empty allowlist allows every model.
+//! Aliases map a client-facing name such as `gpt-4o` onto a Gemini model.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelAlias {
+    pub alias: String,
+    pub model: String,
+}
+
+#[derive(Default)]
+pub struct ModelAliases {
+    aliases: RwLock<HashMap<String, String>>,
+}
+
+impl ModelAliases {
+    pub fn set_aliases(&self, aliases: Vec<ModelAlias>) {
+        *self.aliases.write().unwrap() = aliases
+            .into_iter()
+            .map(|alias| (alias.alias, alias.model))
+            .collect();
+    }
+
+    /// Returns the Gemini model an alias points to, or the name unchanged.
+    pub fn resolve(&self, model: &str) -> String {
+        self.aliases
+            .read()
+            .unwrap()
+            .get(model)
+            .cloned()
+            .unwrap_or_else(|| model.to_string())
+    }
+
+    /// Whether some alias points to `model`.
+    pub fn is_target(&self, model: &str) -> bool {
+        self.aliases.read().unwrap().values().any(|target| target == model)
+    }
+
+    pub fn list(&self) -> Vec<ModelAlias> {
+        let mut aliases: Vec<ModelAlias> = self
+            .aliases
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(alias, model)| ModelAlias {
+                alias: alias.clone(),
+                model: model.clone(),
+            })
+            .collect();
+        aliases.sort_by(|a, b| a.alias.cmp(&b.alias));
+        aliases
+    }
+}
+
+/// Whether a model name is safe to put into a Gemini URL path.
+pub fn is_valid_model_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 128
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+}
+
+/// Whether an allowlist pattern is well-formed: a model name that may contain `*`.
+pub fn is_valid_pattern(pattern: &str) -> bool {
+    pattern == "*" || is_valid_model_name(&pattern.replace('*', ""))
+}
+
+/// Matches `name` against a pattern where `*` matches any run of characters.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` at all: the whole name must match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Whether any of `names` (e.g. the requested alias and the model it resolves
+/// to) is allowed by `patterns`.
+pub fn is_allowed(patterns: &[String], names: &[&str]) -> bool {
+    patterns.is_empty()
+        || patterns
+            .iter()
+            .any(|pattern| names.iter().any(|name| matches_pattern(pattern, name)))
+}
@@ -0,0 +1,80 @@
+//! Model price table used to turn token usage into USD cost.
+//!
+//! Prices are USD per million tokens and are matched by model name prefix,
+//! so `gemini-2.5-flash` also prices `gemini-2.5-flash-preview-05-20`. The
+//! longest matching prefix wins.
+//!
+//! Gemini counts cached tokens as part of the prompt, so they are taken out of
+//! the input tokens and charged at the cached price instead.
+
+use serde::Serialize;
+use std::sync::RwLock;
+
+/// Prices seeded into `model_prices` on first start (paid tier, prompts up to
+/// 200k tokens): input, cached input and output.
+pub const DEFAULT_PRICES: &[(&str, f64, Option<f64>, f64)] = &[
+    ("gemini-2.5-pro", 1.25, Some(0.31), 10.00),
+    ("gemini-2.5-flash-lite", 0.10, Some(0.025), 0.40),
+    ("gemini-2.5-flash", 0.30, Some(0.075), 2.50),
+    ("gemini-2.0-flash-lite", 0.075, None, 0.30),
+    ("gemini-2.0-flash", 0.10, Some(0.025), 0.40),
+    ("gemini-1.5-pro", 1.25, Some(0.3125), 5.00),
+    ("gemini-1.5-flash", 0.075, Some(0.01875), 0.30),
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelPrice {
+    pub model: String,
+    pub input_per_million: f64,
+    /// Price of cached prompt tokens; `None` charges them as regular input.
+    pub cached_input_per_million: Option<f64>,
+    pub output_per_million: f64,
+}
+
+#[derive(Default)]
+pub struct PriceTable {
+    prices: RwLock<Vec<ModelPrice>>,
+}
+
+impl PriceTable {
+    pub fn set_prices(&self, prices: Vec<ModelPrice>) {
+        *self.prices.write().unwrap() = prices;
+    }
+
+    /// All prices, sorted by model.
+    pub fn list(&self) -> Vec<ModelPrice> {
+        let mut prices = self.prices.read().unwrap().clone();
+        prices.sort_by(|a, b| a.model.cmp(&b.model));
+        prices
+    }
+
+    /// Whether there is a price entry for exactly `model`.
+    pub fn contains(&self, model: &str) -> bool {
+        self.prices.read().unwrap().iter().any(|price| price.model == model)
+    }
+
+    /// Finds the price entry with the longest prefix of `model`.
+    pub fn lookup(&self, model: &str) -> Option<ModelPrice> {
+        self.prices
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|price| model.starts_with(&price.model))
+            .max_by_key(|price| price.model.len())
+            .cloned()
+    }
+
+    /// Cost of a request in USD. `cached_tokens` are part of `input_tokens`.
+    /// Models without a price cost nothing.
+    pub fn cost(&self, model: &str, input_tokens: i32, cached_tokens: i32, output_tokens: i32) -> f64 {
+        self.lookup(model).map_or(0.0, |price| {
+            let cached = cached_tokens.clamp(0, input_tokens.max(0));
+            let uncached = input_tokens.max(0) - cached;
+            let cached_price = price.cached_input_per_million.unwrap_or(price.input_per_million);
+            (uncached as f64 * price.input_per_million
+                + cached as f64 * cached_price
+                + output_tokens.max(0) as f64 * price.output_per_million)
+                / 1_000_000.0
+        })
+    }
+}
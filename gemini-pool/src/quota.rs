@@ -0,0 +1,139 @@
+//! Per-client-key quotas: total tokens, requests or USD spend per day or month.
+//!
+//! Periods are calendar days and months in UTC. Usage is summed from
+//! `usage_logs`, so a key is rejected once a finished request pushed it over
+//! its budget, and accepted again when the next period starts.
+
+use axum::{
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaPeriod {
+    Day,
+    Month,
+}
+
+impl QuotaPeriod {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "day" => Some(Self::Day),
+            "month" => Some(Self::Month),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Month => "month",
+        }
+    }
+
+    /// Start of the period containing `now`.
+    pub fn start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let date = match self {
+            Self::Day => now.date_naive(),
+            Self::Month => NaiveDate::from_ymd_opt(now.year(), now.month(), 1).unwrap(),
+        };
+        Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+    }
+
+    /// Start of the period after the one containing `now`.
+    pub fn next_reset(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let start = self.start(now);
+        match self {
+            Self::Day => start + Duration::days(1),
+            Self::Month => {
+                let (year, month) = if start.month() == 12 {
+                    (start.year() + 1, 1)
+                } else {
+                    (start.year(), start.month() + 1)
+                };
+                Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap()
+            }
+        }
+    }
+}
+
+/// Budget configured on a client key. Limits only apply when a period is set.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Quota {
+    pub quota_period: Option<QuotaPeriod>,
+    pub quota_tokens: Option<i64>,
+    pub quota_requests: Option<i64>,
+    pub budget_usd: Option<f64>,
+}
+
+impl Quota {
+    /// The period to check usage for, or `None` if the key has no quota at all.
+    pub fn active_period(&self) -> Option<QuotaPeriod> {
+        let has_limit = self.quota_tokens.is_some() || self.quota_requests.is_some() || self.budget_usd.is_some();
+        self.quota_period.filter(|_| has_limit)
+    }
+}
+
+/// What a key used in the current period.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct QuotaUsage {
+    pub requests: i64,
+    pub tokens: i64,
+    pub cost_usd: f64,
+    pub resets_at: DateTime<Utc>,
+}
+
+impl QuotaUsage {
+    /// Returns the first limit the usage has reached, if any.
+    pub fn exceeded(&self, quota: &Quota) -> Option<QuotaExceeded> {
+        let period = quota.quota_period?;
+        let reason = if quota.quota_tokens.is_some_and(|limit| self.tokens >= limit) {
+            format!("token quota of {} per {}", quota.quota_tokens.unwrap(), period.as_str())
+        } else if quota.quota_requests.is_some_and(|limit| self.requests >= limit) {
+            format!("request quota of {} per {}", quota.quota_requests.unwrap(), period.as_str())
+        } else if quota.budget_usd.is_some_and(|budget| self.cost_usd >= budget) {
+            format!("budget of ${:.2} per {}", quota.budget_usd.unwrap(), period.as_str())
+        } else {
+            return None;
+        };
+
+        Some(QuotaExceeded {
+            reason,
+            resets_at: self.resets_at,
+        })
+    }
+}
+
+/// A key that ran out of budget, rendered as OpenAI's `insufficient_quota` 429.
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    reason: String,
+    resets_at: DateTime<Utc>,
+}
+
+impl IntoResponse for QuotaExceeded {
+    fn into_response(self) -> Response {
+        let body = Json(serde_json::json!({
+            "error": {
+                "message": format!(
+                    "You exceeded your current quota ({}). It resets at {}.",
+                    self.reason,
+                    self.resets_at.to_rfc3339()
+                ),
+                "type": "insufficient_quota",
+                "param": null,
+                "code": "insufficient_quota",
+            }
+        }));
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, body).into_response();
+        let retry_after = (self.resets_at - Utc::now()).num_seconds().max(1);
+        response
+            .headers_mut()
+            .insert(http::header::RETRY_AFTER, HeaderValue::from(retry_after));
+        response
+    }
+}
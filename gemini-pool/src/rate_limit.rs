@@ -0,0 +1,304 @@
+//! Per-client-key rate limiting: requests per minute, tokens per minute and
+//! concurrent requests.
+//!
+//! Requests and tokens are counted over a sliding one-minute window kept in
+//! memory; windows of keys that went idle are dropped. Token usage is only
+//! known once a request has finished, so a key is throttled on tokens it
+//! already used rather than on tokens it is about to use.
+
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits configured on a client key. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RateLimits {
+    pub rpm_limit: Option<u32>,
+    pub tpm_limit: Option<u32>,
+    pub max_concurrent: Option<u32>,
+}
+
+impl RateLimits {
+    fn is_unlimited(&self) -> bool {
+        self.rpm_limit.is_none() && self.tpm_limit.is_none() && self.max_concurrent.is_none()
+    }
+}
+
+#[derive(Default)]
+struct KeyWindow {
+    requests: VecDeque<Instant>,
+    tokens: VecDeque<(Instant, u64)>,
+    in_flight: Arc<AtomicU32>,
+}
+
+impl KeyWindow {
+    fn prune(&mut self, now: Instant) {
+        while self.requests.front().is_some_and(|&t| now.duration_since(t) >= WINDOW) {
+            self.requests.pop_front();
+        }
+        while self.tokens.front().is_some_and(|&(t, _)| now.duration_since(t) >= WINDOW) {
+            self.tokens.pop_front();
+        }
+    }
+
+    /// Whether the window holds nothing worth keeping, including a guard of
+    /// an in-flight request.
+    fn is_idle(&self) -> bool {
+        self.requests.is_empty() && self.tokens.is_empty() && Arc::strong_count(&self.in_flight) == 1
+    }
+
+    fn used_tokens(&self) -> u64 {
+        self.tokens.iter().map(|&(_, tokens)| tokens).sum()
+    }
+
+    /// Time until the oldest entry leaves the window.
+    fn reset_after(oldest: Option<Instant>, now: Instant) -> Duration {
+        oldest.map_or(Duration::ZERO, |t| WINDOW.saturating_sub(now.duration_since(t)))
+    }
+}
+
+/// Remaining budget of a key, reported in `x-ratelimit-*` headers.
+#[derive(Debug, Default, Clone)]
+pub struct RateLimitStatus {
+    requests: Option<(u32, u32, Duration)>,
+    tokens: Option<(u32, u32, Duration)>,
+}
+
+impl RateLimitStatus {
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        let mut insert = |name: &'static str, value: String| {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(name, value);
+            }
+        };
+        if let Some((limit, remaining, reset)) = self.requests {
+            insert("x-ratelimit-limit-requests", limit.to_string());
+            insert("x-ratelimit-remaining-requests", remaining.to_string());
+            insert("x-ratelimit-reset-requests", format_duration(reset));
+        }
+        if let Some((limit, remaining, reset)) = self.tokens {
+            insert("x-ratelimit-limit-tokens", limit.to_string());
+            insert("x-ratelimit-remaining-tokens", remaining.to_string());
+            insert("x-ratelimit-reset-tokens", format_duration(reset));
+        }
+    }
+}
+
+/// Holds a concurrency slot until dropped.
+pub struct ConcurrencyGuard(Arc<AtomicU32>);
+
+impl Drop for ConcurrencyGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Admission of a request. Keep `guard` alive until the response is fully sent.
+pub struct RateLimitPermit {
+    pub status: RateLimitStatus,
+    pub guard: Option<ConcurrencyGuard>,
+}
+
+/// A rejected request, rendered as an OpenAI-style 429.
+#[derive(Debug)]
+pub struct RateLimitExceeded {
+    message: String,
+    limit_type: &'static str,
+    retry_after: Duration,
+    status: RateLimitStatus,
+}
+
+impl IntoResponse for RateLimitExceeded {
+    fn into_response(self) -> Response {
+        let body = Json(serde_json::json!({
+            "error": {
+                "message": self.message,
+                "type": self.limit_type,
+                "param": null,
+                "code": "rate_limit_exceeded",
+            }
+        }));
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, body).into_response();
+        let headers = response.headers_mut();
+        self.status.apply_headers(headers);
+        let retry_after = self.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        headers.insert(http::header::RETRY_AFTER, HeaderValue::from(retry_after));
+        response
+    }
+}
+
+#[derive(Default)]
+struct Windows {
+    keys: HashMap<String, KeyWindow>,
+    last_sweep: Option<Instant>,
+}
+
+impl Windows {
+    /// The pruned window of `key_id`, created on first use. Once per window
+    /// length, the windows of keys that went idle are dropped.
+    fn get(&mut self, key_id: &str, now: Instant) -> &mut KeyWindow {
+        if self.last_sweep.is_none_or(|t| now.duration_since(t) >= WINDOW) {
+            self.keys.retain(|_, window| {
+                window.prune(now);
+                !window.is_idle()
+            });
+            self.last_sweep = Some(now);
+        }
+        let window = self.keys.entry(key_id.to_string()).or_default();
+        window.prune(now);
+        window
+    }
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    windows: Mutex<Windows>,
+}
+
+impl RateLimiter {
+    /// Admits a request for `key_id` or explains which limit it hit.
+    pub fn check(&self, key_id: &str, limits: RateLimits) -> Result<RateLimitPermit, RateLimitExceeded> {
+        if limits.is_unlimited() {
+            return Ok(RateLimitPermit {
+                status: RateLimitStatus::default(),
+                guard: None,
+            });
+        }
+
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.get(key_id, now);
+
+        let request_reset = KeyWindow::reset_after(window.requests.front().copied(), now);
+        let token_reset = KeyWindow::reset_after(window.tokens.front().map(|&(t, _)| t), now);
+        let used_requests = window.requests.len() as u32;
+        let used_tokens = window.used_tokens();
+
+        let mut status = RateLimitStatus {
+            requests: limits
+                .rpm_limit
+                .map(|limit| (limit, limit.saturating_sub(used_requests), request_reset)),
+            tokens: limits
+                .tpm_limit
+                .map(|limit| (limit, (limit as u64).saturating_sub(used_tokens) as u32, token_reset)),
+        };
+
+        if let Some(limit) = limits.rpm_limit {
+            if used_requests >= limit {
+                return Err(RateLimitExceeded {
+                    message: format!("Rate limit reached for requests per minute: limit {}, used {}.", limit, used_requests),
+                    limit_type: "requests",
+                    retry_after: request_reset,
+                    status,
+                });
+            }
+        }
+        if let Some(limit) = limits.tpm_limit {
+            if used_tokens >= limit as u64 {
+                return Err(RateLimitExceeded {
+                    message: format!("Rate limit reached for tokens per minute: limit {}, used {}.", limit, used_tokens),
+                    limit_type: "tokens",
+                    retry_after: token_reset,
+                    status,
+                });
+            }
+        }
+        if let Some(limit) = limits.max_concurrent {
+            let in_flight = window.in_flight.load(Ordering::SeqCst);
+            if in_flight >= limit {
+                return Err(RateLimitExceeded {
+                    message: format!("Too many concurrent requests: limit {}.", limit),
+                    limit_type: "requests",
+                    retry_after: Duration::from_secs(1),
+                    status,
+                });
+            }
+        }
+
+        window.requests.push_back(now);
+        if let Some((_, remaining, reset)) = status.requests.as_mut() {
+            *remaining = remaining.saturating_sub(1);
+            *reset = KeyWindow::reset_after(window.requests.front().copied(), now);
+        }
+        window.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        Ok(RateLimitPermit {
+            status,
+            guard: Some(ConcurrencyGuard(window.in_flight.clone())),
+        })
+    }
+
+    /// Counts tokens used by a finished request towards the key's TPM limit.
+    pub fn record_tokens(&self, key_id: &str, tokens: u64) {
+        if tokens == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        windows.get(key_id, now).tokens.push_back((now, tokens));
+    }
+}
+
+/// Formats a reset duration like OpenAI does, e.g. `"20ms"`, `"6s"` or `"1m0s"`.
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis < 1000 {
+        return format!("{}ms", millis);
+    }
+    let seconds = duration.as_secs_f64().ceil() as u64;
+    if seconds < 60 {
+        format!("{}s", seconds)
+    } else {
+        format!("{}m{}s", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tpm(limit: u32) -> RateLimits {
+        RateLimits {
+            tpm_limit: Some(limit),
+            ..RateLimits::default()
+        }
+    }
+
+    #[test]
+    fn tokens_count_before_the_first_limited_request() {
+        let limiter = RateLimiter::default();
+        limiter.record_tokens("key-1", 120);
+
+        let exceeded = limiter.check("key-1", tpm(100)).err().expect("tokens were not counted");
+        assert_eq!(exceeded.limit_type, "tokens");
+        assert!(limiter.check("key-2", tpm(100)).is_ok());
+    }
+
+    #[test]
+    fn idle_windows_are_dropped() {
+        let start = Instant::now();
+        let mut windows = Windows::default();
+        windows.get("idle", start).tokens.push_back((start, 10));
+        windows.get("busy", start);
+        let busy = windows.get("busy", start).in_flight.clone();
+
+        let later = start + WINDOW + Duration::from_secs(1);
+        windows.get("new", later);
+        let mut keys: Vec<&str> = windows.keys.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["busy", "new"]);
+
+        drop(busy);
+        windows.get("new", later + WINDOW);
+        assert_eq!(windows.keys.keys().collect::<Vec<_>>(), ["new"]);
+    }
+}
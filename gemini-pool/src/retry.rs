@@ -0,0 +1,72 @@
+//! Retry policy for upstream Gemini requests.
+
+use rand::Rng;
+use std::env;
+use std::time::Duration;
+
+/// How failed upstream requests are retried. Each retry uses a different key.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts per request, including the first one.
+    pub max_attempts: u32,
+    /// Upstream status codes worth retrying. Connection errors are always retried.
+    pub retry_statuses: Vec<u16>,
+    /// Backoff before the first retry; doubled for every further retry.
+    pub base_backoff: Duration,
+    /// Upper bound of the backoff before jitter.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            retry_statuses: vec![429, 500, 503],
+            base_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Reads the policy from `UPSTREAM_MAX_ATTEMPTS`, `UPSTREAM_RETRY_STATUSES`,
+    /// `UPSTREAM_BACKOFF_MS` and `UPSTREAM_MAX_BACKOFF_MS`, keeping the
+    /// defaults for anything unset.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let read_u64 = |name: &str| env::var(name).ok().and_then(|v| v.trim().parse::<u64>().ok());
+
+        Self {
+            max_attempts: read_u64("UPSTREAM_MAX_ATTEMPTS")
+                .map(|n| n.max(1) as u32)
+                .unwrap_or(default.max_attempts),
+            retry_statuses: env::var("UPSTREAM_RETRY_STATUSES")
+                .ok()
+                .map(|v| v.split(',').filter_map(|s| s.trim().parse().ok()).collect())
+                .unwrap_or(default.retry_statuses),
+            base_backoff: read_u64("UPSTREAM_BACKOFF_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.base_backoff),
+            max_backoff: read_u64("UPSTREAM_MAX_BACKOFF_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.max_backoff),
+        }
+    }
+
+    pub fn should_retry_status(&self, status: u16) -> bool {
+        self.retry_statuses.contains(&status)
+    }
+
+    /// Exponential backoff with full jitter before retry number `retry` (1-based).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+        let capped = exponential.min(self.max_backoff);
+        let millis = capped.as_millis() as u64;
+        if millis == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+}
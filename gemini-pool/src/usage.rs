@@ -0,0 +1,229 @@
+//! Usage analytics over `usage_logs`: time ranges, grouping into hours, days
+//! or months, and the breakdowns served by the `/admin/api/usage` endpoints.
+//!
+//! Periods are in UTC, like the timestamps in `usage_logs`. A series covers
+//! the whole requested range, so periods without requests are reported as
+//! zeros instead of being left out.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+use serde::Serialize;
+
+/// Most periods a single series may have, e.g. about 41 days by the hour.
+pub const MAX_PERIODS: usize = 1000;
+
+/// Timestamp format of `usage_logs` and of the periods in a series.
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageGrouping {
+    Hour,
+    Day,
+    Month,
+}
+
+impl UsageGrouping {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "hour" => Some(Self::Hour),
+            "day" => Some(Self::Day),
+            "month" => Some(Self::Month),
+            _ => None,
+        }
+    }
+
+    /// `strftime` format that truncates a `usage_logs` timestamp to the start
+    /// of its period.
+    pub fn sql_format(&self) -> &'static str {
+        match self {
+            Self::Hour => "%Y-%m-%d %H:00:00",
+            Self::Day => "%Y-%m-%d 00:00:00",
+            Self::Month => "%Y-%m-01 00:00:00",
+        }
+    }
+
+    /// Range covered when the request names no start: a day by the hour, 30
+    /// days by the day and a year by the month, ending with the current period.
+    pub fn default_range(&self) -> Duration {
+        match self {
+            Self::Hour => Duration::hours(24),
+            Self::Day => Duration::days(30),
+            Self::Month => Duration::days(365),
+        }
+    }
+
+    /// Start of the period containing `time`.
+    pub fn truncate(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let date = time.date_naive();
+        let start = match self {
+            Self::Hour => date.and_hms_opt(time.hour(), 0, 0),
+            Self::Day => date.and_hms_opt(0, 0, 0),
+            Self::Month => date.with_day(1).and_then(|date| date.and_hms_opt(0, 0, 0)),
+        };
+        start.map_or(time, |start| Utc.from_utc_datetime(&start))
+    }
+
+    /// Start of the period after the one starting at `start`.
+    pub fn next(&self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::Hour => start + Duration::hours(1),
+            Self::Day => start + Duration::days(1),
+            Self::Month => {
+                let (year, month) = match start.month() {
+                    12 => (start.year() + 1, 1),
+                    month => (start.year(), month + 1),
+                };
+                NaiveDate::from_ymd_opt(year, month, 1)
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+                    .map_or(start + Duration::days(31), |next| Utc.from_utc_datetime(&next))
+            }
+        }
+    }
+
+    /// Starts of the periods overlapping `from..to`, or `None` if there would
+    /// be more than [`MAX_PERIODS`].
+    pub fn periods(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Option<Vec<DateTime<Utc>>> {
+        let mut periods = Vec::new();
+        let mut start = self.truncate(from);
+        while start < to {
+            if periods.len() == MAX_PERIODS {
+                return None;
+            }
+            periods.push(start);
+            start = self.next(start);
+        }
+        Some(periods)
+    }
+}
+
+/// What a breakdown groups usage by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageDimension {
+    ApiKey,
+    Model,
+    Endpoint,
+}
+
+impl UsageDimension {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "api_key" => Some(Self::ApiKey),
+            "model" => Some(Self::Model),
+            "endpoint" => Some(Self::Endpoint),
+            _ => None,
+        }
+    }
+
+    pub fn column(&self) -> &'static str {
+        match self {
+            Self::ApiKey => "api_key_id",
+            Self::Model => "model",
+            Self::Endpoint => "endpoint",
+        }
+    }
+}
+
+/// What a breakdown is ranked by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageOrder {
+    Requests,
+    Tokens,
+    Cost,
+}
+
+impl UsageOrder {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "requests" => Some(Self::Requests),
+            "tokens" => Some(Self::Tokens),
+            "cost" => Some(Self::Cost),
+            _ => None,
+        }
+    }
+
+    /// Sort expression over the aggregate columns of a breakdown query.
+    pub fn sql(&self) -> &'static str {
+        match self {
+            Self::Requests => "requests",
+            Self::Tokens => "input_tokens + output_tokens",
+            Self::Cost => "cost_usd",
+        }
+    }
+}
+
+/// Requests, tokens and cost summed over some `usage_logs` rows.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct UsageAmounts {
+    pub requests: i64,
+    pub failed_requests: i64,
+    pub input_tokens: i64,
+    /// Cached prompt tokens, included in `input_tokens`.
+    pub cached_tokens: i64,
+    pub output_tokens: i64,
+    pub cost_usd: f64,
+}
+
+impl UsageAmounts {
+    pub fn add(&mut self, other: &UsageAmounts) {
+        self.requests += other.requests;
+        self.failed_requests += other.failed_requests;
+        self.input_tokens += other.input_tokens;
+        self.cached_tokens += other.cached_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cost_usd += other.cost_usd;
+    }
+
+    /// What is left of `self` after taking out `other`.
+    pub fn minus(&self, other: &UsageAmounts) -> UsageAmounts {
+        UsageAmounts {
+            requests: self.requests - other.requests,
+            failed_requests: self.failed_requests - other.failed_requests,
+            input_tokens: self.input_tokens - other.input_tokens,
+            cached_tokens: self.cached_tokens - other.cached_tokens,
+            output_tokens: self.output_tokens - other.output_tokens,
+            cost_usd: (self.cost_usd - other.cost_usd).max(0.0),
+        }
+    }
+}
+
+/// Joins fields into one CSV line, quoting those that need it (RFC 4180).
+/// Fields a spreadsheet would run as a formula, such as a client-chosen key
+/// name starting with `=`, get a leading `'` so they are shown as text.
+pub fn csv_line(fields: &[&str]) -> String {
+    let mut line = fields
+        .iter()
+        .map(|field| {
+            let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+                format!("'{}", field)
+            } else {
+                field.to_string()
+            };
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
+
+/// Parses a range bound: RFC 3339, `YYYY-MM-DD HH:MM:SS` in UTC, or a plain
+/// `YYYY-MM-DD` meaning midnight UTC.
+pub fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT) {
+        return Some(Utc.from_utc_datetime(&time));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| Utc.from_utc_datetime(&time))
+}
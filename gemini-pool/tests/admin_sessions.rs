@@ -0,0 +1,150 @@
+mod common;
+
+use common::{TestProxy, ADMIN_PASSWORD};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+async fn login(proxy: &TestProxy) -> Value {
+    proxy.login("admin", ADMIN_PASSWORD).await.json().await.unwrap()
+}
+
+async fn refresh(proxy: &TestProxy, refresh_token: &str) -> reqwest::Response {
+    proxy
+        .http
+        .post(proxy.url("/admin/api/auth/refresh"))
+        .json(&json!({ "refresh_token": refresh_token }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn dashboard_status(proxy: &TestProxy, token: &str) -> StatusCode {
+    proxy
+        .admin_request_as(token, Method::GET, "/admin/api/dashboard", None)
+        .await
+        .status()
+}
+
+#[tokio::test]
+async fn refresh_tokens_are_rotated() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let session = login(&proxy).await;
+    let old_refresh = session["refresh_token"].as_str().unwrap();
+
+    let refreshed = refresh(&proxy, old_refresh).await;
+    assert_eq!(refreshed.status(), StatusCode::OK);
+    let refreshed: Value = refreshed.json().await.unwrap();
+    assert_eq!(dashboard_status(&proxy, refreshed["token"].as_str().unwrap()).await, StatusCode::OK);
+
+    assert_eq!(refresh(&proxy, old_refresh).await.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        refresh(&proxy, refreshed["refresh_token"].as_str().unwrap()).await.status(),
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn a_refresh_token_is_rotated_only_once() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let session = login(&proxy).await;
+    let refresh_token = session["refresh_token"].as_str().unwrap();
+
+    let responses = futures_util::future::join_all((0..16).map(|_| refresh(&proxy, refresh_token))).await;
+    let statuses: Vec<StatusCode> = responses.iter().map(|response| response.status()).collect();
+    assert_eq!(statuses.iter().filter(|status| **status == StatusCode::OK).count(), 1, "{:?}", statuses);
+    assert!(statuses.iter().all(|status| *status == StatusCode::OK || *status == StatusCode::UNAUTHORIZED));
+}
+
+#[tokio::test]
+async fn logout_revokes_the_session() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let session = login(&proxy).await;
+    let other = login(&proxy).await;
+    let token = session["token"].as_str().unwrap();
+
+    let logout = proxy
+        .http
+        .post(proxy.url("/admin/api/auth/logout"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(logout.status(), StatusCode::OK);
+
+    assert_eq!(dashboard_status(&proxy, token).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        refresh(&proxy, session["refresh_token"].as_str().unwrap()).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    // Other sessions of the same user are not affected
+    assert_eq!(dashboard_status(&proxy, other["token"].as_str().unwrap()).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn revoking_all_sessions_signs_out_everywhere() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let sessions = [login(&proxy).await, login(&proxy).await];
+    let users = proxy.admin(Method::GET, "/admin/api/users", None).await;
+    let admin_id = users["users"][0]["id"].as_str().unwrap();
+    assert_eq!(users["users"][0]["active_sessions"], 3);
+
+    proxy
+        .admin(Method::POST, &format!("/admin/api/users/{}/revoke-sessions", admin_id), None)
+        .await;
+
+    for session in &sessions {
+        assert_eq!(dashboard_status(&proxy, session["token"].as_str().unwrap()).await, StatusCode::UNAUTHORIZED);
+    }
+    assert_eq!(
+        proxy.admin_request(Method::GET, "/admin/api/dashboard", None).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn cookie_sessions_use_http_only_cookies() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let response = proxy
+        .http
+        .post(proxy.url("/admin/api/auth/login"))
+        .json(&json!({ "username": "admin", "password": ADMIN_PASSWORD, "use_cookies": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let cookies: Vec<String> = response
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .map(|value| value.to_str().unwrap().to_string())
+        .collect();
+    assert_eq!(cookies.len(), 2);
+    assert!(cookies.iter().all(|cookie| cookie.contains("HttpOnly") && cookie.contains("SameSite=Strict")));
+    let body: Value = response.json().await.unwrap();
+    assert!(body.get("token").is_none());
+
+    let cookie_header = cookies
+        .iter()
+        .map(|cookie| cookie.split(';').next().unwrap())
+        .collect::<Vec<_>>()
+        .join("; ");
+    let verify = proxy
+        .http
+        .get(proxy.url("/admin/api/auth/verify"))
+        .header(reqwest::header::COOKIE, &cookie_header)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(verify.status(), StatusCode::OK);
+
+    let refreshed = proxy
+        .http
+        .post(proxy.url("/admin/api/auth/refresh"))
+        .header(reqwest::header::COOKIE, &cookie_header)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(refreshed.status(), StatusCode::OK);
+    assert_eq!(refreshed.headers().get_all(reqwest::header::SET_COOKIE).iter().count(), 2);
+}
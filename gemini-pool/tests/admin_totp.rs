@@ -0,0 +1,132 @@
+mod common;
+
+use common::{TestProxy, ADMIN_PASSWORD};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The RFC 6238 code for `secret` at `seconds_from_now`.
+fn totp_code(secret: &str, seconds_from_now: i64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key).unwrap();
+    mac.update(&(((now + seconds_from_now) / 30) as u64).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[19] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    format!("{:06}", value % 1_000_000)
+}
+
+/// Enables two-factor login for the bootstrapped admin and returns the
+/// secret and recovery codes.
+async fn enable_totp(proxy: &TestProxy) -> (String, Vec<String>) {
+    let setup = proxy.admin(Method::POST, "/admin/api/auth/totp/setup", None).await;
+    let secret = setup["secret"].as_str().unwrap().to_string();
+    assert!(setup["otpauth_uri"].as_str().unwrap().starts_with("otpauth://totp/Gemini%20Pool:admin?secret="));
+    assert!(setup["qr_svg"].as_str().unwrap().contains("<svg"));
+
+    let enabled = proxy
+        .admin(
+            Method::POST,
+            "/admin/api/auth/totp/enable",
+            Some(json!({ "code": totp_code(&secret, 0) })),
+        )
+        .await;
+    let codes = enabled["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_string())
+        .collect();
+    (secret, codes)
+}
+
+/// Logs in with the password and returns the intermediate token.
+async fn first_step(proxy: &TestProxy) -> String {
+    let login: Value = proxy.login("admin", ADMIN_PASSWORD).await.json().await.unwrap();
+    assert_eq!(login["totp_required"], true);
+    assert!(login.get("token").is_none());
+    login["totp_token"].as_str().unwrap().to_string()
+}
+
+async fn second_step(proxy: &TestProxy, totp_token: &str, code: &str) -> reqwest::Response {
+    proxy
+        .http
+        .post(proxy.url("/admin/api/auth/login/totp"))
+        .json(&json!({ "totp_token": totp_token, "code": code }))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn login_requires_a_fresh_code_once_enabled() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (secret, _) = enable_totp(&proxy).await;
+    let totp_token = first_step(&proxy).await;
+
+    // The intermediate token is not an access token
+    let dashboard = proxy
+        .admin_request_as(&totp_token, Method::GET, "/admin/api/dashboard", None)
+        .await;
+    assert_eq!(dashboard.status(), StatusCode::UNAUTHORIZED);
+
+    // The code used for enabling cannot be replayed; the next one is accepted
+    assert_eq!(second_step(&proxy, &totp_token, &totp_code(&secret, 0)).await.status(), StatusCode::BAD_REQUEST);
+    let login = second_step(&proxy, &totp_token, &totp_code(&secret, 30)).await;
+    assert_eq!(login.status(), StatusCode::OK);
+    let login: Value = login.json().await.unwrap();
+    let status = proxy
+        .admin_request_as(login["token"].as_str().unwrap(), Method::GET, "/admin/api/dashboard", None)
+        .await
+        .status();
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn recovery_codes_work_once() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (_, recovery_codes) = enable_totp(&proxy).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    let totp_token = first_step(&proxy).await;
+    let code = recovery_codes[0].to_uppercase();
+    assert_eq!(second_step(&proxy, &totp_token, &code).await.status(), StatusCode::OK);
+    assert_eq!(second_step(&proxy, &totp_token, &code).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn repeated_invalid_codes_lock_the_second_step() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (secret, _) = enable_totp(&proxy).await;
+    let totp_token = first_step(&proxy).await;
+
+    for _ in 0..5 {
+        assert_eq!(second_step(&proxy, &totp_token, "000000").await.status(), StatusCode::BAD_REQUEST);
+    }
+    let locked = second_step(&proxy, &totp_token, &totp_code(&secret, 30)).await;
+    assert_eq!(locked.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(locked.headers().contains_key(reqwest::header::RETRY_AFTER));
+}
+
+#[tokio::test]
+async fn parallel_attempts_cannot_exceed_the_limits() {
+    // Leave the limiting to the second step rather than the login guard
+    let proxy = TestProxy::start_with_env(&["upstream-key-1"], &[("LOGIN_MAX_FAILURES", "1000")]).await;
+    let (secret, _) = enable_totp(&proxy).await;
+
+    // A valid code is accepted by one of several concurrent requests only
+    let totp_token = first_step(&proxy).await;
+    let code = totp_code(&secret, 30);
+    let replays = futures_util::future::join_all((0..5).map(|_| second_step(&proxy, &totp_token, &code))).await;
+    assert_eq!(replays.iter().filter(|response| response.status() == StatusCode::OK).count(), 1);
+
+    // Parallel guesses are checked at most as often as sequential ones
+    let guesses = futures_util::future::join_all((0..20).map(|_| second_step(&proxy, &totp_token, "000000"))).await;
+    let checked = guesses.iter().filter(|response| response.status() == StatusCode::BAD_REQUEST).count();
+    assert!(checked <= 5, "{} guesses were checked", checked);
+    assert!(guesses.iter().any(|response| response.status() == StatusCode::TOO_MANY_REQUESTS));
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::TestProxy;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+/// Invites `username` with `role` and returns their id and a session token.
+async fn invite_and_login(proxy: &TestProxy, username: &str, role: &str) -> (String, String) {
+    let invited = proxy
+        .admin(
+            Method::POST,
+            "/admin/api/users",
+            Some(json!({ "username": username, "role": role })),
+        )
+        .await;
+    let password = invited["temporary_password"].as_str().unwrap();
+    let login: Value = proxy.login(username, password).await.json().await.unwrap();
+    (
+        invited["id"].as_str().unwrap().to_string(),
+        login["token"].as_str().expect("invited user could not log in").to_string(),
+    )
+}
+
+async fn status_as(proxy: &TestProxy, token: &str, method: Method, path: &str, body: Option<Value>) -> StatusCode {
+    proxy.admin_request_as(token, method, path, body).await.status()
+}
+
+#[tokio::test]
+async fn roles_are_enforced_per_route() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (_, viewer) = invite_and_login(&proxy, "viewer", "viewer").await;
+    let (_, operator) = invite_and_login(&proxy, "operator", "operator").await;
+    let new_key = || Some(json!({ "key_name": "test" }));
+
+    assert_eq!(status_as(&proxy, &viewer, Method::GET, "/admin/api/dashboard", None).await, StatusCode::OK);
+    assert_eq!(status_as(&proxy, &viewer, Method::GET, "/admin/api/api-keys", None).await, StatusCode::OK);
+    assert_eq!(status_as(&proxy, &viewer, Method::GET, "/admin/api/usage/timeseries", None).await, StatusCode::OK);
+    assert_eq!(status_as(&proxy, &viewer, Method::GET, "/admin/api/usage/export", None).await, StatusCode::OK);
+    assert_eq!(
+        status_as(&proxy, &viewer, Method::POST, "/admin/api/api-keys", new_key()).await,
+        StatusCode::FORBIDDEN
+    );
+    let price = || Some(json!({ "input_per_million": 1.0, "output_per_million": 2.0 }));
+    assert_eq!(
+        status_as(&proxy, &viewer, Method::PUT, "/admin/api/model-prices/gemini-2.5-flash", price()).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        status_as(&proxy, &operator, Method::PUT, "/admin/api/model-prices/gemini-2.5-flash", price()).await,
+        StatusCode::OK
+    );
+
+    assert_eq!(
+        status_as(&proxy, &operator, Method::POST, "/admin/api/api-keys", new_key()).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        status_as(&proxy, &operator, Method::POST, "/admin/api/upstream-keys", Some(json!({ "api_key": "upstream-key-2" }))).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        status_as(&proxy, &operator, Method::GET, "/admin/api/users", None).await,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn disabled_users_lose_access_immediately() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (id, token) = invite_and_login(&proxy, "alice", "operator").await;
+
+    proxy
+        .admin(
+            Method::PUT,
+            &format!("/admin/api/users/{}", id),
+            Some(json!({ "role": "operator", "is_active": false })),
+        )
+        .await;
+
+    assert_eq!(
+        status_as(&proxy, &token, Method::GET, "/admin/api/dashboard", None).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let reset = proxy
+        .admin(Method::POST, &format!("/admin/api/users/{}/reset-password", id), None)
+        .await;
+    let login = proxy.login("alice", reset["temporary_password"].as_str().unwrap()).await;
+    assert_eq!(login.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn last_owner_cannot_be_demoted() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let users = proxy.admin(Method::GET, "/admin/api/users", None).await;
+    let owner = &users["users"][0];
+    assert_eq!(owner["username"], "admin");
+    assert_eq!(owner["role"], "owner");
+
+    let response = proxy
+        .admin_request(
+            Method::PUT,
+            &format!("/admin/api/users/{}", owner["id"].as_str().unwrap()),
+            Some(json!({ "role": "viewer", "is_active": true })),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
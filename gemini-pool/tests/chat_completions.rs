@@ -0,0 +1,291 @@
+mod common;
+
+use common::{chat_request, TestProxy};
+use gemini_pool::mock_gemini::{MockResponse, PROMPT_TOKENS};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn chat_completion_returns_the_upstream_reply() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.push(MockResponse::text("Hi there friend"));
+
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Say hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["object"], "chat.completion");
+    assert_eq!(body["model"], "gemini-2.5-flash");
+    assert_eq!(body["choices"][0]["message"]["content"], "Hi there friend");
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    assert_eq!(body["usage"]["prompt_tokens"], PROMPT_TOKENS);
+    assert_eq!(body["usage"]["completion_tokens"], 3);
+
+    let requests = proxy.mock.requests();
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+    assert_eq!(request.path, "/v1beta/models/gemini-2.5-flash:generateContent");
+    assert_eq!(request.api_key.as_deref(), Some("upstream-key-1"));
+    assert_eq!(request.query, None, "the key must not be sent in the query string");
+    let contents = &request.body.as_ref().unwrap()["contents"];
+    assert_eq!(contents[0]["role"], "user");
+    assert_eq!(contents[0]["parts"][0]["text"], "Say hi");
+}
+
+#[tokio::test]
+async fn streaming_chat_completion_forwards_every_chunk() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.push(MockResponse::stream(&["Hel", "lo ", "world"]));
+
+    let mut request = chat_request("gemini-2.5-flash", "Say hello");
+    request["stream"] = json!(true);
+    request["stream_options"] = json!({ "include_usage": true });
+    let response = proxy.chat(&client_key, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.unwrap();
+
+    let events: Vec<&str> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect();
+    assert_eq!(events.last(), Some(&"[DONE]"));
+    let chunks: Vec<Value> = events[..events.len() - 1]
+        .iter()
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect();
+    let text: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(text, "Hello world");
+    let usage = chunks.iter().find(|chunk| !chunk["usage"].is_null()).expect("usage chunk");
+    assert_eq!(usage["usage"]["completion_tokens"], 2);
+
+    let requests = proxy.mock.requests();
+    assert_eq!(requests[0].path, "/v1beta/models/gemini-2.5-flash:streamGenerateContent");
+    assert_eq!(requests[0].query.as_deref(), Some("alt=sse"));
+}
+
+#[tokio::test]
+async fn unknown_client_key_is_rejected_before_calling_gemini() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+
+    let response = proxy.chat("gp_not_a_real_key", chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(proxy.mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn model_allowlist_and_aliases_apply_to_models_and_chat() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    proxy
+        .admin(
+            Method::PUT,
+            "/admin/api/model-aliases/gpt-4o",
+            Some(json!({ "model": "gemini-2.5-pro" })),
+        )
+        .await;
+    let client_key = proxy
+        .create_client_key(json!({ "allowed_models": ["gemini-2.5-*"] }))
+        .await;
+
+    let models: Value = proxy
+        .http
+        .get(proxy.url("/v1/models"))
+        .bearer_auth(&client_key)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let mut ids: Vec<&str> = models["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|model| model["id"].as_str().unwrap())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, ["gemini-2.5-flash", "gemini-2.5-pro", "gpt-4o"]);
+
+    let response = proxy.chat(&client_key, chat_request("gpt-4o", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["model"], "gpt-4o");
+    let last = proxy.mock.requests().pop().unwrap();
+    assert_eq!(last.path, "/v1beta/models/gemini-2.5-pro:generateContent");
+
+    let response = proxy.chat(&client_key, chat_request("gemini-2.0-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn requests_over_the_rpm_limit_get_a_429() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let client_key = proxy.create_client_key(json!({ "rpm_limit": 1 })).await;
+
+    let first = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(first.headers()["x-ratelimit-remaining-requests"], "0");
+
+    let second = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(second.headers().contains_key("retry-after"));
+    let body: Value = second.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "rate_limit_exceeded");
+    assert_eq!(proxy.mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn files_api_references_resolve_against_the_configured_endpoint() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    let base_url = proxy.mock.base_url();
+    let request = |file: &str| {
+        json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{
+                "role": "user",
+                "content": [
+                    { "type": "image_url", "image_url": { "url": "files/photo-1" } },
+                    { "type": "file", "file": { "file_id": file, "filename": "report.pdf" } },
+                ],
+            }],
+        })
+    };
+
+    let response = proxy.chat(&client_key, request(&format!("{}/files/report-1", base_url))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let requests = proxy.mock.requests();
+    let parts = &requests[0].body.as_ref().unwrap()["contents"][0]["parts"];
+    assert_eq!(parts[0]["fileData"]["fileUri"], format!("{}/files/photo-1", base_url));
+    assert_eq!(parts[1]["fileData"]["fileUri"], format!("{}/files/report-1", base_url));
+    assert_eq!(parts[1]["fileData"]["mimeType"], "application/pdf");
+
+    // Files of another endpoint are not forwarded
+    let response = proxy
+        .chat(&client_key, request("https://generativelanguage.googleapis.com/v1beta/files/report-1"))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn thought_signatures_of_function_calls_are_sent_back() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.push(MockResponse::Raw {
+        status: 200,
+        body: json!({
+            "candidates": [{
+                "index": 0,
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "functionCall": { "name": "get_weather", "args": { "city": "Paris" } }, "thoughtSignature": "c2lnbmF0dXJl" },
+                        { "functionCall": { "name": "get_time", "args": {} } },
+                    ],
+                },
+                "finishReason": "STOP",
+            }],
+            "usageMetadata": { "promptTokenCount": PROMPT_TOKENS, "candidatesTokenCount": 4, "totalTokenCount": PROMPT_TOKENS + 4 },
+        }),
+    });
+
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Weather?")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    let message = body["choices"][0]["message"].clone();
+    assert_eq!(body["choices"][0]["finish_reason"], "tool_calls");
+    let weather_id = message["tool_calls"][0]["id"].as_str().unwrap().to_string();
+    let time_id = message["tool_calls"][1]["id"].as_str().unwrap().to_string();
+
+    let request = json!({
+        "model": "gemini-2.5-flash",
+        "messages": [
+            { "role": "user", "content": "Weather?" },
+            message,
+            { "role": "tool", "tool_call_id": weather_id, "content": "{\"temp\": 21}" },
+            { "role": "tool", "tool_call_id": time_id, "content": "noon" },
+        ],
+    });
+    let response = proxy.chat(&client_key, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let requests = proxy.mock.requests();
+    let contents = &requests[1].body.as_ref().unwrap()["contents"];
+    assert_eq!(contents[1]["role"], "model");
+    assert_eq!(contents[1]["parts"][0]["functionCall"]["name"], "get_weather");
+    assert_eq!(contents[1]["parts"][0]["thoughtSignature"], "c2lnbmF0dXJl");
+    assert_eq!(contents[1]["parts"][1]["functionCall"]["name"], "get_time");
+    assert!(contents[1]["parts"][1].get("thoughtSignature").is_none());
+    assert_eq!(contents[2]["parts"][0]["functionResponse"]["name"], "get_weather");
+}
+
+#[tokio::test]
+async fn strict_output_that_fails_validation_is_still_billed() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.push(MockResponse::text("not json at all"));
+
+    let mut request = chat_request("gemini-2.5-flash", "Give me a name");
+    request["response_format"] = json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "person",
+            "strict": true,
+            "schema": { "type": "object", "properties": { "name": { "type": "string" } }, "required": ["name"] },
+        },
+    });
+    let response = proxy.chat(&client_key, request).await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+    let export = proxy
+        .admin_request(Method::GET, "/admin/api/usage/export?format=ndjson", None)
+        .await
+        .text()
+        .await
+        .unwrap();
+    let rows: Vec<Value> = export.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["success"], false);
+    assert_eq!(rows[0]["input_tokens"], PROMPT_TOKENS);
+    assert_eq!(rows[0]["output_tokens"], 4);
+}
+
+#[tokio::test]
+async fn a_final_stream_line_without_newline_is_not_lost() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    let first = json!({ "candidates": [{ "index": 0, "content": { "role": "model", "parts": [{ "text": "Hello" }] } }] });
+    let last = json!({
+        "candidates": [{ "index": 0, "content": { "role": "model", "parts": [{ "text": " world" }] }, "finishReason": "STOP" }],
+        "usageMetadata": { "promptTokenCount": PROMPT_TOKENS, "candidatesTokenCount": 2, "totalTokenCount": PROMPT_TOKENS + 2 },
+    });
+    proxy.mock.push(MockResponse::RawStream {
+        body: format!("data: {}\r\n\r\ndata: {}", first, last),
+    });
+
+    let mut request = chat_request("gemini-2.5-flash", "Say hello");
+    request["stream"] = json!(true);
+    request["stream_options"] = json!({ "include_usage": true });
+    let body = proxy.chat(&client_key, request).await.text().await.unwrap();
+    let chunks: Vec<Value> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|event| *event != "[DONE]")
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect();
+    let text: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(text, "Hello world");
+    let finish_reasons: Vec<&Value> = chunks
+        .iter()
+        .map(|chunk| &chunk["choices"][0]["finish_reason"])
+        .filter(|reason| !reason.is_null())
+        .collect();
+    assert_eq!(finish_reasons, [&json!("stop")]);
+    let usage = chunks.iter().find(|chunk| !chunk["usage"].is_null()).expect("usage chunk");
+    assert_eq!(usage["usage"]["completion_tokens"], 2);
+}
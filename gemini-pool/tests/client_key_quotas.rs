@@ -0,0 +1,90 @@
+mod common;
+
+use common::{chat_request, TestProxy};
+use gemini_pool::mock_gemini::{MockResponse, PROMPT_TOKENS};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn key_list_shows_usage_of_each_quota_period() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (daily_id, daily) = proxy
+        .create_client_key_with_id(json!({ "key_name": "daily", "quota_period": "day", "quota_requests": 2 }))
+        .await;
+    let (monthly_id, monthly) = proxy
+        .create_client_key_with_id(json!({ "key_name": "monthly", "quota_period": "month", "quota_tokens": 1000 }))
+        .await;
+    let (unlimited_id, unlimited) = proxy.create_client_key_with_id(json!({ "key_name": "unlimited" })).await;
+    for key in [&daily, &daily, &monthly, &unlimited] {
+        let response = proxy.chat(key, chat_request("gemini-2.5-flash", "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    // The daily key is out of requests now
+    let response = proxy.chat(&daily, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let list = proxy.admin(Method::GET, "/admin/api/api-keys", None).await;
+    let listed = |id: &str| -> Value {
+        list["api_keys"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|key| key["id"] == id)
+            .cloned()
+            .expect("key not listed")
+    };
+    assert_eq!(listed(&daily_id)["quota_usage"]["requests"], 2);
+    assert_eq!(listed(&monthly_id)["quota_usage"]["requests"], 1);
+    assert!(listed(&monthly_id)["quota_usage"]["tokens"].as_i64().unwrap() > PROMPT_TOKENS);
+    assert!(listed(&unlimited_id)["quota_usage"].is_null());
+
+    // The list agrees with the single key view
+    for id in [&daily_id, &monthly_id, &unlimited_id] {
+        let key = proxy.admin(Method::GET, &format!("/admin/api/api-keys/{}", id), None).await;
+        assert_eq!(key["quota_usage"], listed(id)["quota_usage"], "{}", id);
+    }
+
+    // A key with a quota but no usage yet starts at zero
+    let (fresh_id, _) = proxy
+        .create_client_key_with_id(json!({ "quota_period": "day", "budget_usd": 1.0 }))
+        .await;
+    let list = proxy.admin(Method::GET, "/admin/api/api-keys", None).await;
+    let fresh = list["api_keys"].as_array().unwrap().iter().find(|key| key["id"] == fresh_id.as_str()).unwrap();
+    assert_eq!(fresh["quota_usage"]["requests"], 0);
+    assert_eq!(fresh["quota_usage"]["cost_usd"], 0.0);
+}
+
+#[tokio::test]
+async fn failed_requests_spend_the_token_budget() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (key_id, key) = proxy
+        .create_client_key_with_id(json!({ "quota_period": "day", "quota_tokens": 15, "quota_requests": 5 }))
+        .await;
+    let strict_request = || {
+        let mut request = chat_request("gemini-2.5-flash", "Give me a name");
+        request["response_format"] = json!({
+            "type": "json_schema",
+            "json_schema": { "name": "person", "strict": true, "schema": { "type": "object" } },
+        });
+        request
+    };
+
+    // Each reply spends tokens upstream but fails validation
+    for _ in 0..2 {
+        proxy.mock.push(MockResponse::text("not json at all"));
+        let response = proxy.chat(&key, strict_request()).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+    let response = proxy.chat(&key, strict_request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "insufficient_quota");
+
+    let key = proxy.admin(Method::GET, &format!("/admin/api/api-keys/{}", key_id), None).await;
+    assert_eq!(key["quota_usage"]["requests"], 0);
+    assert_eq!(key["quota_usage"]["tokens"], 2 * (PROMPT_TOKENS + 4));
+    assert!(key["quota_usage"]["cost_usd"].as_f64().unwrap() > 0.0);
+    let list = proxy.admin(Method::GET, "/admin/api/api-keys", None).await;
+    let listed = list["api_keys"].as_array().unwrap().iter().find(|listed| listed["id"] == key_id.as_str()).unwrap();
+    assert_eq!(listed["quota_usage"], key["quota_usage"]);
+}
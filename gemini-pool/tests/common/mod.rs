@@ -0,0 +1,217 @@
+//! Runs the `gemini-pool` binary against a mock Gemini server.
+
+#![allow(dead_code)]
+
+use gemini_pool::mock_gemini::MockGemini;
+use serde_json::{json, Value};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+pub const ADMIN_PASSWORD: &str = "test-admin-password";
+
+/// Database file of a proxy, relative to its directory.
+pub const DATABASE_FILE: &str = "gemini_pool.db";
+
+/// A proxy process with its own database, talking to its own mock Gemini.
+pub struct TestProxy {
+    pub mock: MockGemini,
+    pub base_url: String,
+    pub http: reqwest::Client,
+    admin_token: String,
+    process: Child,
+    port: u16,
+    upstream_keys: Vec<String>,
+    env: Vec<(String, String)>,
+    dir: TempDir,
+}
+
+impl TestProxy {
+    /// Starts a proxy with the given upstream keys.
+    pub async fn start(upstream_keys: &[&str]) -> Self {
+        Self::start_with_env(upstream_keys, &[]).await
+    }
+
+    /// Starts a proxy with the given upstream keys and extra environment variables.
+    pub async fn start_with_env(upstream_keys: &[&str], env: &[(&str, &str)]) -> Self {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        Self::start_in(dir, upstream_keys, env).await
+    }
+
+    /// Starts a proxy in `dir`, which may already hold a [`DATABASE_FILE`],
+    /// e.g. one from an older version.
+    pub async fn start_in(dir: TempDir, upstream_keys: &[&str], env: &[(&str, &str)]) -> Self {
+        let mock = MockGemini::start().await.expect("failed to start mock Gemini");
+        let port = free_port();
+        let upstream_keys: Vec<String> = upstream_keys.iter().map(|key| key.to_string()).collect();
+        let env: Vec<(String, String)> = env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        let process = spawn(&mock, &dir, port, &upstream_keys, &env);
+
+        let mut proxy = Self {
+            mock,
+            base_url: format!("http://127.0.0.1:{}", port),
+            http: reqwest::Client::new(),
+            admin_token: String::new(),
+            process,
+            port,
+            upstream_keys,
+            env,
+            dir,
+        };
+        proxy.admin_login().await;
+        proxy
+    }
+
+    /// Stops the proxy and starts it again on the same database.
+    pub async fn restart(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        self.process = spawn(&self.mock, &self.dir, self.port, &self.upstream_keys, &self.env);
+        // Pooled connections went away with the old process
+        self.http = reqwest::Client::new();
+        self.admin_login().await;
+    }
+
+    async fn admin_login(&mut self) {
+        let login = self.login("admin", ADMIN_PASSWORD).await;
+        assert!(login.status().is_success(), "admin login failed: {}", login.status());
+        let login: Value = login.json().await.unwrap();
+        self.admin_token = login["token"].as_str().unwrap().to_string();
+    }
+
+    /// Path of the proxy's SQLite database.
+    pub fn database_path(&self) -> std::path::PathBuf {
+        self.dir.path().join(DATABASE_FILE)
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Sends an admin login request.
+    pub async fn login(&self, username: &str, password: &str) -> reqwest::Response {
+        self.http
+            .post(self.url("/admin/api/auth/login"))
+            .json(&json!({ "username": username, "password": password }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Sends an admin API request as the bootstrapped owner.
+    pub async fn admin_request(&self, method: reqwest::Method, path: &str, body: Option<Value>) -> reqwest::Response {
+        self.admin_request_as(&self.admin_token, method, path, body).await
+    }
+
+    /// Sends an admin API request with the given session token.
+    pub async fn admin_request_as(
+        &self,
+        token: &str,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> reqwest::Response {
+        let mut request = self.http.request(method, self.url(path)).bearer_auth(token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        request.send().await.unwrap()
+    }
+
+    /// Sends an admin API request and returns the JSON response, if any.
+    pub async fn admin(&self, method: reqwest::Method, path: &str, body: Option<Value>) -> Value {
+        let response = self.admin_request(method, path, body).await;
+        assert!(response.status().is_success(), "{} failed: {}", path, response.status());
+        response.json().await.unwrap_or(Value::Null)
+    }
+
+    /// Creates a client key with the given settings and returns the secret.
+    pub async fn create_client_key(&self, settings: Value) -> String {
+        self.create_client_key_with_id(settings).await.1
+    }
+
+    /// Creates a client key with the given settings and returns its id and secret.
+    pub async fn create_client_key_with_id(&self, settings: Value) -> (String, String) {
+        let mut body = json!({ "key_name": "test" });
+        body.as_object_mut()
+            .unwrap()
+            .extend(settings.as_object().cloned().unwrap_or_default());
+        let created = self.admin(reqwest::Method::POST, "/admin/api/api-keys", Some(body)).await;
+        (
+            created["id"].as_str().unwrap().to_string(),
+            created["api_key"].as_str().unwrap().to_string(),
+        )
+    }
+
+    /// Sends a chat completion request with `client_key`.
+    pub async fn chat(&self, client_key: &str, body: Value) -> reqwest::Response {
+        self.http
+            .post(self.url("/v1/chat/completions"))
+            .bearer_auth(client_key)
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for TestProxy {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// A simple single-message chat request.
+pub fn chat_request(model: &str, message: &str) -> Value {
+    json!({
+        "model": model,
+        "messages": [{ "role": "user", "content": message }],
+    })
+}
+
+fn spawn(mock: &MockGemini, dir: &TempDir, port: u16, upstream_keys: &[String], env: &[(String, String)]) -> Child {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gemini-pool"));
+    command
+        .current_dir(dir.path())
+        .env_clear()
+        .env("GEMINI_API_KEYS", upstream_keys.join(","))
+        .env("GEMINI_BASE_URL", mock.base_url())
+        .env("MASTER_KEY", "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=")
+        .env("ADMIN_PASSWORD", ADMIN_PASSWORD)
+        .env("JWT_SECRET", "test-jwt-secret")
+        .env("DATABASE_URL", format!("sqlite:{}?mode=rwc", DATABASE_FILE))
+        .env("LISTEN_ADDR", format!("127.0.0.1:{}", port))
+        .env("UPSTREAM_BACKOFF_MS", "1")
+        .env("UPSTREAM_MAX_BACKOFF_MS", "10")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    for (name, value) in env {
+        command.env(name, value);
+    }
+    let mut process = command.spawn().expect("failed to start gemini-pool");
+    wait_for_port(&mut process, port);
+    process
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("no free port")
+}
+
+fn wait_for_port(process: &mut Child, port: u16) {
+    let deadline = Instant::now() + Duration::from_secs(15);
+    while Instant::now() < deadline {
+        if let Some(status) = process.try_wait().unwrap() {
+            panic!("gemini-pool exited during startup: {}", status);
+        }
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("gemini-pool did not start listening on port {}", port);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::{chat_request, TestProxy, DATABASE_FILE};
+use gemini_pool::mock_gemini::PROMPT_TOKENS;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+const LEGACY_KEY: &str = "gp_0123456789abcdef0123456789abcdef";
+const LEGACY_KEY_ID: &str = "5f0c2a8e-3b1d-4c6e-9a7f-2d4b6e8a0c1e";
+
+/// Creates a database with the schema of the first release, which stored
+/// client keys in plain text.
+async fn create_legacy_database(dir: &tempfile::TempDir) {
+    let url = format!("sqlite:{}?mode=rwc", dir.path().join(DATABASE_FILE).display());
+    let db = sqlx::SqlitePool::connect(&url).await.unwrap();
+    for statement in [
+        "CREATE TABLE api_keys (
+            id TEXT PRIMARY KEY,
+            key_name TEXT NOT NULL,
+            api_key TEXT NOT NULL UNIQUE,
+            is_active BOOLEAN NOT NULL DEFAULT TRUE,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            total_requests INTEGER NOT NULL DEFAULT 0,
+            total_input_tokens INTEGER NOT NULL DEFAULT 0,
+            total_output_tokens INTEGER NOT NULL DEFAULT 0
+        )",
+        "CREATE TABLE usage_logs (
+            id TEXT PRIMARY KEY,
+            api_key_id TEXT NOT NULL,
+            timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            endpoint TEXT NOT NULL,
+            model TEXT NOT NULL,
+            input_tokens INTEGER NOT NULL DEFAULT 0,
+            output_tokens INTEGER NOT NULL DEFAULT 0,
+            success BOOLEAN NOT NULL DEFAULT TRUE,
+            FOREIGN KEY (api_key_id) REFERENCES api_keys (id)
+        )",
+    ] {
+        sqlx::query(statement).execute(&db).await.unwrap();
+    }
+    sqlx::query(
+        "INSERT INTO api_keys (id, key_name, api_key, total_requests, total_input_tokens, total_output_tokens)
+         VALUES (?, 'legacy', ?, 1, 12, 34)",
+    )
+    .bind(LEGACY_KEY_ID)
+    .bind(LEGACY_KEY)
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO usage_logs (id, api_key_id, endpoint, model, input_tokens, output_tokens)
+         VALUES ('legacy-usage-1', ?, '/v1/chat/completions', 'gemini-2.5-flash', 12, 34)",
+    )
+    .bind(LEGACY_KEY_ID)
+    .execute(&db)
+    .await
+    .unwrap();
+    db.close().await;
+}
+
+#[tokio::test]
+async fn plaintext_client_keys_are_migrated_in_place() {
+    let dir = tempfile::TempDir::new().unwrap();
+    create_legacy_database(&dir).await;
+    let proxy = TestProxy::start_in(dir, &["upstream-key-1"], &[]).await;
+
+    // The old key still authenticates
+    let response = proxy.chat(LEGACY_KEY, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let key = proxy
+        .admin(Method::GET, &format!("/admin/api/api-keys/{}", LEGACY_KEY_ID), None)
+        .await;
+    assert_eq!(key["key_name"], "legacy");
+    assert_eq!(key["key_prefix"], "gp_01234");
+    assert_eq!(key["total_requests"], 2);
+    assert_eq!(key["total_input_tokens"], 12 + PROMPT_TOKENS);
+
+    // Old and new usage rows still join to the key
+    let export = proxy
+        .admin_request(Method::GET, "/admin/api/usage/export?format=ndjson", None)
+        .await
+        .text()
+        .await
+        .unwrap();
+    let rows: Vec<Value> = export.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(rows.len(), 2);
+    for row in &rows {
+        assert_eq!(row["api_key_id"], LEGACY_KEY_ID);
+        assert_eq!(row["key_name"], "legacy");
+    }
+    assert_eq!(rows[0]["id"], "legacy-usage-1");
+    assert_eq!(rows[0]["input_tokens"], 12);
+
+    // Nothing readable is left of the plain text key
+    let url = format!("sqlite:{}", proxy.database_path().display());
+    let db = sqlx::SqlitePool::connect(&url).await.unwrap();
+    let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('api_keys')")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert!(!columns.contains(&"api_key".to_string()), "{:?}", columns);
+    db.close().await;
+    let file = std::fs::read(proxy.database_path()).unwrap();
+    assert!(!file.windows(LEGACY_KEY.len()).any(|window| window == LEGACY_KEY.as_bytes()));
+
+    // Keys created after the migration work as before
+    let new_key = proxy.create_client_key(json!({ "key_name": "fresh" })).await;
+    let response = proxy.chat(&new_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
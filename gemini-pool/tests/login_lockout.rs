@@ -0,0 +1,115 @@
+mod common;
+
+use common::{chat_request, TestProxy, ADMIN_PASSWORD};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+const LOCKOUT_ENV: &[(&str, &str)] = &[("LOGIN_MAX_FAILURES", "3"), ("LOGIN_LOCKOUT_SECS", "60")];
+
+async fn lockouts(proxy: &TestProxy) -> Vec<Value> {
+    let body = proxy.admin(Method::GET, "/admin/api/lockouts", None).await;
+    body["lockouts"].as_array().unwrap().clone()
+}
+
+fn find<'a>(lockouts: &'a [Value], kind: &str, value: &str) -> Option<&'a Value> {
+    lockouts.iter().find(|entry| entry["kind"] == kind && entry["value"] == value)
+}
+
+#[tokio::test]
+async fn failed_admin_logins_lock_the_username_and_ip() {
+    let proxy = TestProxy::start_with_env(&["upstream-key-1"], LOCKOUT_ENV).await;
+
+    for _ in 0..3 {
+        let response = proxy
+            .http
+            .post(proxy.url("/admin/api/auth/login"))
+            // Not a trusted proxy, so the header is ignored
+            .header("x-forwarded-for", "203.0.113.7")
+            .json(&json!({ "username": "Admin", "password": "wrong-password" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // The right password no longer helps
+    let locked = proxy.login("admin", ADMIN_PASSWORD).await;
+    assert_eq!(locked.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = locked.headers()[reqwest::header::RETRY_AFTER]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 60);
+
+    let entries = lockouts(&proxy).await;
+    assert!(find(&entries, "username", "admin").unwrap()["locked_until"].is_string());
+    assert!(find(&entries, "ip", "127.0.0.1").unwrap()["locked_until"].is_string());
+    assert!(find(&entries, "ip", "203.0.113.7").is_none());
+
+    proxy.admin(Method::DELETE, "/admin/api/lockouts/username/admin", None).await;
+    assert_eq!(proxy.login("admin", ADMIN_PASSWORD).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    proxy.admin(Method::DELETE, "/admin/api/lockouts/ip/127.0.0.1", None).await;
+    assert_eq!(proxy.login("admin", ADMIN_PASSWORD).await.status(), StatusCode::OK);
+
+    let missing = proxy
+        .admin_request(Method::DELETE, "/admin/api/lockouts/ip/127.0.0.1", None)
+        .await;
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn guessing_client_keys_locks_the_ip() {
+    let proxy = TestProxy::start_with_env(&["upstream-key-1"], LOCKOUT_ENV).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+
+    for _ in 0..3 {
+        let response = proxy.chat("sk-not-a-real-key", chat_request("gemini-2.5-flash", "Hi")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let locked = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(locked.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(locked.headers().contains_key(reqwest::header::RETRY_AFTER));
+
+    // Admin logins from the same address are locked out as well
+    assert_eq!(proxy.login("admin", ADMIN_PASSWORD).await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    proxy.admin(Method::DELETE, "/admin/api/lockouts/ip/127.0.0.1", None).await;
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn trusted_proxies_forward_the_client_ip() {
+    let mut env = LOCKOUT_ENV.to_vec();
+    env.push(("TRUSTED_PROXIES", "127.0.0.0/8"));
+    let proxy = TestProxy::start_with_env(&["upstream-key-1"], &env).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+
+    let chat_from = |ip: &'static str, key: String| {
+        proxy
+            .http
+            .post(proxy.url("/v1/chat/completions"))
+            .bearer_auth(key)
+            // A spoofed first entry is ignored; the proxy appends the real one
+            .header("x-forwarded-for", format!("10.9.9.9, {}", ip))
+            .json(&chat_request("gemini-2.5-flash", "Hi"))
+            .send()
+    };
+
+    for _ in 0..3 {
+        let response = chat_from("198.51.100.20", "sk-not-a-real-key".to_string()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let locked = chat_from("198.51.100.20", client_key.clone()).await.unwrap();
+    assert_eq!(locked.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Other clients behind the same proxy are unaffected
+    let other = chat_from("198.51.100.21", client_key.clone()).await.unwrap();
+    assert_eq!(other.status(), StatusCode::OK);
+
+    let entries = lockouts(&proxy).await;
+    assert!(find(&entries, "ip", "198.51.100.20").unwrap()["locked_until"].is_string());
+    assert!(find(&entries, "ip", "127.0.0.1").is_none());
+    assert!(find(&entries, "ip", "10.9.9.9").is_none());
+}
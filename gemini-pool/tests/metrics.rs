@@ -0,0 +1,174 @@
+mod common;
+
+use common::{chat_request, TestProxy};
+use gemini_pool::mock_gemini::{MockResponse, PROMPT_TOKENS};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+async fn scrape(proxy: &TestProxy) -> String {
+    let response = proxy.http.get(proxy.url("/metrics")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    response.text().await.unwrap()
+}
+
+/// The value of the sample whose name and labels start with `prefix`.
+fn sample(metrics: &str, prefix: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .find(|line| line.starts_with(prefix))
+        .and_then(|line| line.rsplit(' ').next())
+        .map(|value| value.parse().unwrap())
+}
+
+#[tokio::test]
+async fn requests_and_tokens_are_counted_per_client_key_and_model() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (client_id, client_key) = proxy.create_client_key_with_id(json!({})).await;
+    proxy.mock.push(MockResponse::text("Hi there friend"));
+    proxy.mock.push(MockResponse::stream(&["Hello ", "again"]));
+
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut stream_request = chat_request("gemini-2.5-flash", "Hi");
+    stream_request["stream"] = json!(true);
+    let stream = proxy.chat(&client_key, stream_request).await;
+    assert_eq!(stream.status(), StatusCode::OK);
+    stream.text().await.unwrap();
+    let rejected = proxy.chat("sk-unknown", chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+
+    let metrics = scrape(&proxy).await;
+    let labels = format!(
+        "route=\"/v1/chat/completions\",model=\"gemini-2.5-flash\",status=\"200\",client_key=\"{}\"",
+        client_id
+    );
+    assert_eq!(sample(&metrics, &format!("gemini_pool_requests_total{{{}}}", labels)), Some(2.0));
+    assert_eq!(
+        sample(&metrics, &format!("gemini_pool_request_duration_seconds_count{{{}}}", labels)),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(&metrics, &format!("gemini_pool_request_duration_seconds_bucket{{{},le=\"+Inf\"}}", labels)),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            "gemini_pool_requests_total{route=\"/v1/chat/completions\",model=\"\",status=\"400\",client_key=\"\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(&metrics, "gemini_pool_requests_in_flight{route=\"/v1/chat/completions\"}"),
+        Some(0.0)
+    );
+
+    let tokens = format!("gemini_pool_tokens_total{{client_key=\"{}\",model=\"gemini-2.5-flash\"", client_id);
+    assert_eq!(
+        sample(&metrics, &format!("{},type=\"prompt\"}}", tokens)),
+        Some(2.0 * PROMPT_TOKENS as f64)
+    );
+    assert_eq!(sample(&metrics, &format!("{},type=\"completion\"}}", tokens)), Some(5.0));
+}
+
+#[tokio::test]
+async fn upstream_attempts_and_pool_health_are_reported() {
+    let proxy = TestProxy::start(&["upstream-key-1", "upstream-key-2"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.respond_for_key("upstream-key-1", MockResponse::rate_limited(60));
+
+    for _ in 0..2 {
+        let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let keys = proxy.admin(Method::GET, "/admin/api/upstream-keys", None).await;
+    let keys = keys["upstream_keys"].as_array().unwrap();
+    let key_id = |state: &str| {
+        keys.iter()
+            .find(|key| key["state"] == state)
+            .map(|key| key["id"].as_str().unwrap().to_string())
+            .unwrap()
+    };
+    let (limited, healthy) = (key_id("cooling_down"), key_id("healthy"));
+
+    let metrics = scrape(&proxy).await;
+    let attempts = |key: &str, status: &str| {
+        sample(
+            &metrics,
+            &format!("gemini_pool_upstream_attempts_total{{upstream_key=\"{}\",status=\"{}\"}}", key, status),
+        )
+    };
+    assert_eq!(attempts(&limited, "429"), Some(1.0));
+    assert_eq!(attempts(&healthy, "200"), Some(2.0));
+    assert_eq!(sample(&metrics, "gemini_pool_upstream_keys{state=\"healthy\"}"), Some(1.0));
+    assert_eq!(sample(&metrics, "gemini_pool_upstream_keys{state=\"cooling_down\"}"), Some(1.0));
+    assert_eq!(sample(&metrics, "gemini_pool_upstream_keys{state=\"disabled\"}"), Some(0.0));
+    assert_eq!(
+        sample(&metrics, &format!("gemini_pool_upstream_key_healthy{{upstream_key=\"{}\"}}", limited)),
+        Some(0.0)
+    );
+}
+
+#[tokio::test]
+async fn unknown_models_share_one_label() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (client_id, client_key) = proxy.create_client_key_with_id(json!({})).await;
+    for model in ["made-up-1", "made-up-2"] {
+        let response = proxy.chat(&client_key, chat_request(model, "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Models become known once Gemini lists them
+    proxy.mock.set_models(&["gemini-exp-1206"]);
+    let models = proxy
+        .http
+        .get(proxy.url("/v1/models"))
+        .bearer_auth(&client_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(models.status(), StatusCode::OK);
+    let response = proxy.chat(&client_key, chat_request("gemini-exp-1206", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let metrics = scrape(&proxy).await;
+    assert!(!metrics.contains("made-up"));
+    let requests = |model: &str| {
+        sample(
+            &metrics,
+            &format!(
+                "gemini_pool_requests_total{{route=\"/v1/chat/completions\",model=\"{}\",status=\"200\",client_key=\"{}\"}}",
+                model, client_id
+            ),
+        )
+    };
+    assert_eq!(requests("other"), Some(2.0));
+    assert_eq!(requests("gemini-exp-1206"), Some(1.0));
+    let tokens = format!("gemini_pool_tokens_total{{client_key=\"{}\",model=\"other\",type=\"prompt\"}}", client_id);
+    assert_eq!(sample(&metrics, &tokens), Some(2.0 * PROMPT_TOKENS as f64));
+}
+
+#[tokio::test]
+async fn metrics_token_is_required_when_configured() {
+    let proxy = TestProxy::start_with_env(&["upstream-key-1"], &[("METRICS_TOKEN", "scrape-secret")]).await;
+
+    let missing = proxy.http.get(proxy.url("/metrics")).send().await.unwrap();
+    assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+    let wrong = proxy.http.get(proxy.url("/metrics")).bearer_auth("nope").send().await.unwrap();
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+
+    let scraped = proxy
+        .http
+        .get(proxy.url("/metrics"))
+        .bearer_auth("scrape-secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(scraped.status(), StatusCode::OK);
+    assert!(scraped.headers()[reqwest::header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    assert!(scraped.text().await.unwrap().contains("# TYPE gemini_pool_requests_total counter"));
+}
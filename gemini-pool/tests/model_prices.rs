@@ -0,0 +1,193 @@
+mod common;
+
+use common::{chat_request, TestProxy};
+use gemini_pool::mock_gemini::MockResponse;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+/// A reply whose usage includes cached prompt tokens.
+fn cached_reply(prompt: i64, cached: i64, output: i64) -> MockResponse {
+    MockResponse::Raw {
+        status: 200,
+        body: json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [{ "text": "Hi" }] },
+                "finishReason": "STOP",
+                "index": 0,
+            }],
+            "usageMetadata": {
+                "promptTokenCount": prompt,
+                "cachedContentTokenCount": cached,
+                "candidatesTokenCount": output,
+                "totalTokenCount": prompt + output,
+            },
+        }),
+    }
+}
+
+fn assert_close(actual: &Value, expected: f64) {
+    let actual = actual.as_f64().unwrap();
+    assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+}
+
+async fn usage_totals(proxy: &TestProxy) -> Value {
+    let series = proxy.admin(Method::GET, "/admin/api/usage/timeseries", None).await;
+    series["totals"].clone()
+}
+
+#[tokio::test]
+async fn edited_prices_apply_to_new_requests() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+
+    let prices = proxy.admin(Method::GET, "/admin/api/model-prices", None).await;
+    let flash = prices["prices"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|price| price["model"] == "gemini-2.5-flash")
+        .unwrap();
+    assert_close(&flash["cached_input_per_million"], 0.075);
+
+    proxy
+        .admin(
+            Method::PUT,
+            "/admin/api/model-prices/gemini-2.5-flash",
+            Some(json!({ "input_per_million": 1.0, "cached_input_per_million": 0.5, "output_per_million": 2.0 })),
+        )
+        .await;
+    proxy.mock.push(cached_reply(1_000_000, 400_000, 100_000));
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // 600k uncached input at $1, 400k cached at $0.50 and 100k output at $2
+    let totals = usage_totals(&proxy).await;
+    assert_close(&totals["cost_usd"], 1.0);
+    assert_eq!(totals["cached_tokens"], 400_000);
+    let dashboard = proxy.admin(Method::GET, "/admin/api/dashboard", None).await;
+    assert_close(&dashboard["total_cost_usd"], 1.0);
+    assert_close(&dashboard["month_cost_usd"], 1.0);
+
+    // Without a price new requests cost nothing, and logged costs stay as they were
+    proxy.admin(Method::DELETE, "/admin/api/model-prices/gemini-2.5-flash", None).await;
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let totals = usage_totals(&proxy).await;
+    assert_eq!(totals["requests"], 2);
+    assert_close(&totals["cost_usd"], 1.0);
+
+    let missing = proxy
+        .admin_request(Method::DELETE, "/admin/api/model-prices/gemini-2.5-flash", None)
+        .await;
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn spend_report_breaks_down_by_period_key_and_model() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let team = proxy.create_client_key(json!({ "key_name": "Team \"A\", search" })).await;
+    let other = proxy.create_client_key(json!({ "key_name": "other" })).await;
+    for (key, model) in [(&team, "gemini-2.5-pro"), (&team, "gemini-2.5-pro"), (&team, "gemini-2.5-flash"), (&other, "gemini-2.5-flash")] {
+        let response = proxy.chat(key, chat_request(model, "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let report = proxy.admin(Method::GET, "/admin/api/usage/spend?group_by=month", None).await;
+    let rows = report["rows"].as_array().unwrap();
+    assert_eq!(rows.len(), 3);
+    // The most expensive row of the period comes first
+    assert_eq!(rows[0]["key_name"], "Team \"A\", search");
+    assert_eq!(rows[0]["model"], "gemini-2.5-pro");
+    assert_eq!(rows[0]["requests"], 2);
+    assert!(rows[0]["period"].as_str().unwrap().ends_with("-01 00:00:00"));
+    let sum: f64 = rows.iter().map(|row| row["cost_usd"].as_f64().unwrap()).sum();
+    assert_close(&report["totals"]["cost_usd"], sum);
+    assert!(sum > 0.0);
+
+    let csv = proxy
+        .admin_request(Method::GET, "/admin/api/usage/spend?group_by=month&format=csv", None)
+        .await;
+    assert_eq!(csv.status(), StatusCode::OK);
+    assert_eq!(csv.headers()[reqwest::header::CONTENT_TYPE], "text/csv; charset=utf-8");
+    assert!(csv.headers()[reqwest::header::CONTENT_DISPOSITION]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment; filename=\"gemini-pool-spend-"));
+    let csv = csv.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "period,api_key_id,key_name,model,requests,failed_requests,input_tokens,cached_tokens,output_tokens,cost_usd"
+    );
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].contains(",\"Team \"\"A\"\", search\",gemini-2.5-pro,2,0,"));
+}
+
+#[tokio::test]
+async fn invalid_prices_and_formats_are_rejected() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+
+    for (model, body) in [
+        ("gemini-2.5-flash", json!({ "input_per_million": -1.0, "output_per_million": 2.0 })),
+        ("gemini-2.5-flash", json!({ "input_per_million": 1.0, "cached_input_per_million": -0.5, "output_per_million": 2.0 })),
+        ("gemini%202.5", json!({ "input_per_million": 1.0, "output_per_million": 2.0 })),
+    ] {
+        let response = proxy
+            .admin_request(Method::PUT, &format!("/admin/api/model-prices/{}", model), Some(body))
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", model);
+    }
+
+    let response = proxy
+        .admin_request(Method::GET, "/admin/api/usage/spend?format=xml", None)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Prices can also be set for prefixes that are not in the defaults
+    proxy
+        .admin(
+            Method::PUT,
+            "/admin/api/model-prices/gemini-3",
+            Some(json!({ "input_per_million": 2.0, "output_per_million": 12.0 })),
+        )
+        .await;
+    let prices = proxy.admin(Method::GET, "/admin/api/model-prices", None).await;
+    let added = prices["prices"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|price| price["model"] == "gemini-3")
+        .unwrap();
+    assert!(added["cached_input_per_million"].is_null());
+}
+
+#[tokio::test]
+async fn spend_csv_does_not_carry_spreadsheet_formulas() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let key = proxy
+        .create_client_key(json!({ "key_name": "=HYPERLINK(\"http://example.com\",\"x\")" }))
+        .await;
+    let response = proxy.chat(&key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // Rejected model names are not logged as sent
+    let response = proxy.chat(&key, chat_request("@SUM(1+1)", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let report = proxy.admin(Method::GET, "/admin/api/usage/spend?group_by=month", None).await;
+    let models: Vec<&str> = report["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row["model"].as_str().unwrap())
+        .collect();
+    assert!(models.contains(&"") && models.contains(&"gemini-2.5-flash"), "{:?}", models);
+
+    let csv = proxy
+        .admin_request(Method::GET, "/admin/api/usage/spend?group_by=month&format=csv", None)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(csv.contains(",\"'=HYPERLINK(\"\"http://example.com\"\",\"\"x\"\")\","), "{}", csv);
+    assert!(!csv.contains("@SUM"));
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use common::{chat_request, TestProxy};
+use gemini_pool::mock_gemini::MockResponse;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+fn requests_with_key(proxy: &TestProxy, key: &str) -> usize {
+    proxy
+        .mock
+        .requests()
+        .iter()
+        .filter(|request| request.api_key.as_deref() == Some(key))
+        .count()
+}
+
+/// The admin view of the upstream key whose masked form ends with `masked_suffix`.
+async fn upstream_key(proxy: &TestProxy, masked_suffix: &str) -> Value {
+    let keys = proxy.admin(Method::GET, "/admin/api/upstream-keys", None).await;
+    keys["upstream_keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|key| key["api_key"].as_str().unwrap().ends_with(masked_suffix))
+        .cloned()
+        .expect("upstream key not found")
+}
+
+#[tokio::test]
+async fn rate_limited_key_fails_over_and_cools_down() {
+    let proxy = TestProxy::start(&["upstream-key-1", "upstream-key-2"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.respond_for_key("upstream-key-1", MockResponse::rate_limited(60));
+
+    for _ in 0..3 {
+        let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // The first 429 puts the key into cooldown, so it is not tried again
+    assert_eq!(requests_with_key(&proxy, "upstream-key-1"), 1);
+    assert_eq!(requests_with_key(&proxy, "upstream-key-2"), 3);
+    let key = upstream_key(&proxy, "ey-1").await;
+    assert_eq!(key["state"], "cooling_down");
+    assert_eq!(key["error_count"], 1);
+}
+
+#[tokio::test]
+async fn invalid_key_is_disabled() {
+    let proxy = TestProxy::start(&["upstream-key-1", "upstream-key-2"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.respond_for_key("upstream-key-2", MockResponse::invalid_key());
+
+    // The request that hit the rejected key is retried on the other one
+    for _ in 0..4 {
+        let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    assert_eq!(requests_with_key(&proxy, "upstream-key-2"), 1);
+    assert_eq!(requests_with_key(&proxy, "upstream-key-1"), 4);
+    let key = upstream_key(&proxy, "ey-2").await;
+    assert_eq!(key["state"], "disabled");
+}
+
+#[tokio::test]
+async fn retryable_errors_are_retried_on_the_next_key() {
+    let proxy = TestProxy::start(&["upstream-key-1", "upstream-key-2"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.push(MockResponse::error(503, "The model is overloaded."));
+    proxy.mock.push(MockResponse::text("Recovered"));
+
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["choices"][0]["message"]["content"], "Recovered");
+
+    let requests = proxy.mock.requests();
+    assert_eq!(requests.len(), 2);
+    assert_ne!(requests[0].api_key, requests[1].api_key);
+    let stats = proxy.admin(Method::GET, "/admin/api/dashboard", None).await;
+    assert_eq!(stats["retried_requests"], 1);
+    assert_eq!(stats["failover_saves"], 1);
+}
+
+#[tokio::test]
+async fn non_retryable_errors_are_not_retried() {
+    let proxy = TestProxy::start(&["upstream-key-1", "upstream-key-2"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.push(MockResponse::error(400, "Invalid JSON payload received."));
+
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert!(!response.status().is_success());
+    assert_eq!(proxy.mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn deleted_keys_are_not_imported_again_on_restart() {
+    let mut proxy = TestProxy::start(&["upstream-key-1", "upstream-key-2"]).await;
+    let deleted = upstream_key(&proxy, "ey-2").await;
+    proxy
+        .admin(Method::DELETE, &format!("/admin/api/upstream-keys/{}", deleted["id"].as_str().unwrap()), None)
+        .await;
+
+    proxy.restart().await;
+    let keys = proxy.admin(Method::GET, "/admin/api/upstream-keys", None).await;
+    let keys = keys["upstream_keys"].as_array().unwrap();
+    assert_eq!(keys.len(), 1);
+    assert!(keys[0]["api_key"].as_str().unwrap().ends_with("ey-1"));
+
+    let client_key = proxy.create_client_key(json!({})).await;
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(requests_with_key(&proxy, "upstream-key-2"), 0);
+}
+
+#[tokio::test]
+async fn only_key_specific_forbidden_errors_disable_a_key() {
+    let proxy = TestProxy::start(&["upstream-key-1", "upstream-key-2"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    let forbidden = |reason: Option<&str>| MockResponse::Error {
+        status: 403,
+        message: "Permission denied.".to_string(),
+        reason: reason.map(str::to_string),
+        retry_after: None,
+    };
+
+    // No access to a model or file says nothing about the key
+    proxy.mock.respond_for_key("upstream-key-1", forbidden(None));
+    proxy.mock.respond_for_key("upstream-key-2", forbidden(Some("API_KEY_SERVICE_BLOCKED")));
+    for _ in 0..2 {
+        let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    assert_eq!(upstream_key(&proxy, "ey-1").await["state"], "healthy");
+    assert_eq!(upstream_key(&proxy, "ey-2").await["state"], "disabled");
+}
+
+#[tokio::test]
+async fn odd_keys_and_retry_hints_are_handled() {
+    let proxy = TestProxy::start(&["upstream-key-1", "upstream-key-2"]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    proxy.mock.respond_for_key("upstream-key-1", MockResponse::rate_limited(100_000_000_000_000_000));
+
+    for _ in 0..2 {
+        let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    // Cooldowns are capped at a day
+    let key = upstream_key(&proxy, "ey-1").await;
+    assert_eq!(key["state"], "cooling_down");
+    let until = chrono::DateTime::parse_from_rfc3339(key["until"].as_str().unwrap()).unwrap();
+    assert!(until <= chrono::Utc::now() + chrono::Duration::days(1));
+
+    // Keys are masked by character, not by byte
+    proxy
+        .admin(Method::POST, "/admin/api/upstream-keys", Some(json!({ "api_key": "ключ-для-проверки" })))
+        .await;
+    let key = upstream_key(&proxy, "ерки").await;
+    assert_eq!(key["api_key"], "ключ...ерки");
+}
+
+#[tokio::test]
+async fn masked_keys_are_dropped_from_old_attempt_logs() {
+    // An attempt log written by a version that stored the masked key
+    let dir = tempfile::TempDir::new().unwrap();
+    let url = format!("sqlite:{}?mode=rwc", dir.path().join(common::DATABASE_FILE).display());
+    let db = sqlx::SqlitePool::connect(&url).await.unwrap();
+    sqlx::query(
+        "CREATE TABLE upstream_attempts (
+            id TEXT PRIMARY KEY,
+            request_id TEXT NOT NULL,
+            attempt INTEGER NOT NULL,
+            upstream_key TEXT NOT NULL,
+            timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            endpoint TEXT NOT NULL,
+            model TEXT NOT NULL,
+            status_code INTEGER,
+            error TEXT,
+            latency_ms INTEGER NOT NULL DEFAULT 0,
+            success BOOLEAN NOT NULL DEFAULT FALSE
+        )",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO upstream_attempts (id, request_id, attempt, upstream_key, endpoint, model, success)
+         VALUES ('attempt-1', 'request-1', 1, 'upst...ey-1', '/v1/chat/completions', 'gemini-2.5-flash', TRUE)",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    db.close().await;
+
+    let proxy = TestProxy::start_in(dir, &["upstream-key-1"], &[]).await;
+    let client_key = proxy.create_client_key(json!({})).await;
+    let response = proxy.chat(&client_key, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let url = format!("sqlite:{}", proxy.database_path().display());
+    let db = sqlx::SqlitePool::connect(&url).await.unwrap();
+    let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('upstream_attempts')")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert!(columns.contains(&"upstream_key_id".to_string()));
+    assert!(!columns.contains(&"upstream_key".to_string()));
+    let attempts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM upstream_attempts")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(attempts, 2);
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{chat_request, TestProxy};
+use gemini_pool::mock_gemini::{MockResponse, PROMPT_TOKENS};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+/// Sends `ok` successful chats and `failed` failed ones with `key`.
+async fn send_chats(proxy: &TestProxy, key: &str, ok: usize, failed: usize) {
+    for _ in 0..ok {
+        proxy.mock.push(MockResponse::text("Hi there friend"));
+        let response = proxy.chat(key, chat_request("gemini-2.5-flash", "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    for _ in 0..failed {
+        proxy.mock.push(MockResponse::error(400, "bad request"));
+        let response = proxy.chat(key, chat_request("gemini-2.5-flash", "Hi")).await;
+        assert!(!response.status().is_success());
+    }
+}
+
+#[tokio::test]
+async fn timeseries_covers_every_period_of_the_range() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (alpha_id, alpha) = proxy.create_client_key_with_id(json!({ "key_name": "alpha" })).await;
+    let (_, beta) = proxy.create_client_key_with_id(json!({ "key_name": "beta" })).await;
+    send_chats(&proxy, &alpha, 2, 0).await;
+    send_chats(&proxy, &beta, 1, 1).await;
+
+    let yesterday = (Utc::now() - Duration::days(1)).format("%Y-%m-%d");
+    let series = proxy
+        .admin(Method::GET, &format!("/admin/api/usage/timeseries?group_by=hour&from={}", yesterday), None)
+        .await;
+    let points = series["points"].as_array().unwrap();
+    assert!(points.len() > 24 && points.len() <= 48, "{} points", points.len());
+    assert_eq!(points[0]["period"], format!("{} 00:00:00", yesterday));
+    assert_eq!(points.iter().map(|p| p["requests"].as_i64().unwrap()).sum::<i64>(), 4);
+    assert_eq!(points.iter().filter(|p| p["requests"] != 0).count(), 1);
+    assert_eq!(series["totals"]["requests"], 4);
+    assert_eq!(series["totals"]["failed_requests"], 1);
+    assert_eq!(series["totals"]["input_tokens"], 3 * PROMPT_TOKENS);
+
+    let daily = proxy
+        .admin(
+            Method::GET,
+            &format!("/admin/api/usage/timeseries?group_by=day&from={}&api_key_id={}", yesterday, alpha_id),
+            None,
+        )
+        .await;
+    let points = daily["points"].as_array().unwrap();
+    assert_eq!(points.len(), 2);
+    assert_eq!(points[0]["requests"], 0);
+    assert_eq!(points[1]["requests"], 2);
+    assert_eq!(points[1]["period"], format!("{} 00:00:00", Utc::now().format("%Y-%m-%d")));
+}
+
+#[tokio::test]
+async fn top_breakdowns_rank_keys_and_models() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (alpha_id, alpha) = proxy.create_client_key_with_id(json!({ "key_name": "alpha" })).await;
+    let (beta_id, beta) = proxy.create_client_key_with_id(json!({ "key_name": "beta" })).await;
+    send_chats(&proxy, &alpha, 1, 0).await;
+    send_chats(&proxy, &beta, 2, 1).await;
+
+    let top = proxy.admin(Method::GET, "/admin/api/usage/top?by=api_key&limit=1", None).await;
+    let items = top["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["key"], beta_id.as_str());
+    assert_eq!(items[0]["name"], "beta");
+    assert_eq!(items[0]["requests"], 3);
+    assert_eq!(items[0]["failed_requests"], 1);
+    assert_eq!(top["others"]["requests"], 1);
+    assert_eq!(top["totals"]["requests"], 4);
+
+    let failures = proxy
+        .admin(Method::GET, "/admin/api/usage/top?by=api_key&success=false", None)
+        .await;
+    let items: Vec<&Value> = failures["items"].as_array().unwrap().iter().collect();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["key"], beta_id.as_str());
+    assert!(!items.iter().any(|item| item["key"] == alpha_id.as_str()));
+
+    let models = proxy
+        .admin(Method::GET, "/admin/api/usage/top?by=model&order_by=tokens", None)
+        .await;
+    assert_eq!(models["items"][0]["key"], "gemini-2.5-flash");
+    assert_eq!(models["items"][0]["input_tokens"], 3 * PROMPT_TOKENS);
+    assert!(models["items"][0]["name"].is_null());
+}
+
+#[tokio::test]
+async fn invalid_ranges_and_groupings_are_rejected() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+
+    for path in [
+        "/admin/api/usage/timeseries?group_by=week",
+        "/admin/api/usage/timeseries?from=yesterday",
+        "/admin/api/usage/timeseries?from=2024-02-01&to=2024-01-01",
+        // Two years by the hour is too many periods
+        "/admin/api/usage/timeseries?group_by=hour&from=2023-01-01&to=2025-01-01",
+        "/admin/api/usage/top?by=region",
+        "/admin/api/usage/top?order_by=latency",
+    ] {
+        let response = proxy.admin_request(Method::GET, path, None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", path);
+    }
+
+    let monthly = proxy
+        .admin(
+            Method::GET,
+            "/admin/api/usage/timeseries?group_by=month&from=2024-11-15&to=2025-02-01",
+            None,
+        )
+        .await;
+    let periods: Vec<&str> = monthly["points"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["period"].as_str().unwrap())
+        .collect();
+    assert_eq!(periods, ["2024-11-01 00:00:00", "2024-12-01 00:00:00", "2025-01-01 00:00:00"]);
+}
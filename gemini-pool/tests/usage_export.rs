@@ -0,0 +1,103 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{chat_request, TestProxy};
+use gemini_pool::mock_gemini::{MockResponse, PROMPT_TOKENS};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+async fn export(proxy: &TestProxy, query: &str) -> reqwest::Response {
+    let response = proxy
+        .admin_request(Method::GET, &format!("/admin/api/usage/export{}", query), None)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK, "{}", query);
+    response
+}
+
+#[tokio::test]
+async fn csv_export_lists_rows_of_the_selected_key() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (team_id, team) = proxy.create_client_key_with_id(json!({ "key_name": "Team \"A\", search" })).await;
+    let (_, other) = proxy.create_client_key_with_id(json!({ "key_name": "other" })).await;
+    for key in [&team, &team, &other] {
+        let response = proxy.chat(key, chat_request("gemini-2.5-flash", "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    proxy.mock.push(MockResponse::error(400, "bad request"));
+    let response = proxy.chat(&team, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert!(!response.status().is_success());
+
+    let response = export(&proxy, &format!("?api_key_id={}", team_id)).await;
+    assert_eq!(response.headers()[reqwest::header::CONTENT_TYPE], "text/csv; charset=utf-8");
+    assert!(response.headers()[reqwest::header::CONTENT_DISPOSITION]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment; filename=\"gemini-pool-usage-1970-01-01-"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "id,timestamp,api_key_id,key_name,endpoint,model,input_tokens,cached_tokens,output_tokens,cost_usd,success"
+    );
+    assert_eq!(lines.len(), 4);
+    let expected = format!(",{},\"Team \"\"A\"\", search\",/v1/chat/completions,gemini-2.5-flash,", team_id);
+    assert!(lines[1..].iter().all(|line| line.contains(&expected)), "{}", csv);
+    let succeeded = format!("gemini-2.5-flash,{},0,", PROMPT_TOKENS);
+    assert_eq!(lines.iter().filter(|line| line.contains(&succeeded) && line.ends_with(",true")).count(), 2);
+    assert_eq!(lines.iter().filter(|line| line.ends_with(",0,0,0,0.000000,false")).count(), 1);
+
+    let response = export(&proxy, "?success=false").await;
+    assert_eq!(response.text().await.unwrap().lines().count(), 2);
+
+    // Spreadsheets must not run key names as formulas
+    let formula = proxy.create_client_key(json!({ "key_name": "+cmd|' /C calc'!A0" })).await;
+    let response = proxy.chat(&formula, chat_request("gemini-2.5-flash", "Hi")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let csv = export(&proxy, "").await.text().await.unwrap();
+    assert!(csv.contains(",'+cmd|' /C calc'!A0,"), "{}", csv);
+}
+
+#[tokio::test]
+async fn ndjson_export_filters_by_model_and_range() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+    let (key_id, key) = proxy.create_client_key_with_id(json!({ "key_name": "alpha" })).await;
+    for model in ["gemini-2.5-pro", "gemini-2.5-flash", "gemini-2.5-pro"] {
+        let response = proxy.chat(&key, chat_request(model, "Hi")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = export(&proxy, "?format=ndjson&model=gemini-2.5-pro").await;
+    assert_eq!(response.headers()[reqwest::header::CONTENT_TYPE], "application/x-ndjson");
+    let body = response.text().await.unwrap();
+    let rows: Vec<Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(rows.len(), 2);
+    for row in &rows {
+        assert_eq!(row["api_key_id"], key_id.as_str());
+        assert_eq!(row["key_name"], "alpha");
+        assert_eq!(row["model"], "gemini-2.5-pro");
+        assert_eq!(row["input_tokens"], PROMPT_TOKENS);
+        assert_eq!(row["success"], true);
+        assert!(row["cost_usd"].as_f64().unwrap() > 0.0);
+    }
+    assert!(rows[0]["timestamp"].as_str().unwrap() <= rows[1]["timestamp"].as_str().unwrap());
+
+    let today = Utc::now().format("%Y-%m-%d");
+    let tomorrow = (Utc::now() + Duration::days(1)).format("%Y-%m-%d");
+    let response = export(&proxy, &format!("?format=ndjson&from={}&to={}", today, tomorrow)).await;
+    assert_eq!(response.text().await.unwrap().lines().count(), 3);
+    let later = (Utc::now() + Duration::days(2)).format("%Y-%m-%d");
+    let response = export(&proxy, &format!("?format=ndjson&from={}&to={}", tomorrow, later)).await;
+    assert_eq!(response.text().await.unwrap(), "");
+}
+
+#[tokio::test]
+async fn invalid_export_requests_are_rejected() {
+    let proxy = TestProxy::start(&["upstream-key-1"]).await;
+
+    for query in ["?format=xml", "?from=yesterday", "?from=2025-02-01&to=2025-01-01"] {
+        let response = proxy
+            .admin_request(Method::GET, &format!("/admin/api/usage/export{}", query), None)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
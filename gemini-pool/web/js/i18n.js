@@ -53,6 +53,8 @@ class I18n {
                 'output_tokens': '输出 Tokens',
                 'total_requests': '总请求数',
                 'total_tokens': '总 Token 数',
+                'retried_requests': '重试请求数',
+                'failover_saves': '故障转移挽回',
                 
                 // API Key 管理
                 'api_key_management': 'API Keys 管理',
@@ -73,12 +75,11 @@ class I18n {
                 'api_key_update_failed': 'API Key 更新失败',
                 'api_key_delete_failed': 'API Key 删除失败',
                 'api_key_not_found': 'API Key 不存在',
-                'new_api_key': '新 API Key',
                 'save_changes': '保存更改',
                 'enable': '启用',
                 'disable': '禁用',
-                'show': '显示',
-                'hide': '隐藏',
+                'new_api_key_once': '新 API Key（仅显示这一次，请立即复制）',
+                'api_key_prefix_hint': '出于安全考虑，只保存了 Key 的前缀，完整 Key 仅在创建时显示一次',
                 
                 // 表格标题
                 'table_name': '名称',
@@ -89,6 +90,181 @@ class I18n {
                 'table_input_tokens': '输入 Tokens',
                 'table_output_tokens': '输出 Tokens',
                 'table_actions': '操作',
+                'table_cooldown_until': '冷却至',
+                'table_label': '标签',
+                'table_success_count': '成功',
+                'table_error_count': '失败',
+                'table_last_error': '最近错误',
+                'table_notes': '备注',
+                'table_rate_limits': '速率限制',
+                
+                // 速率限制
+                'rate_limits': '速率限制',
+                'rpm_limit_placeholder': '每分钟请求数',
+                'tpm_limit_placeholder': '每分钟 Tokens',
+                'max_concurrent_placeholder': '最大并发',
+                'rate_limits_hint': '留空表示不限制',
+                'concurrent': '并发',
+                'unlimited': '不限制',
+                
+                // 配额
+                'table_quota': '配额使用',
+                'quota': '配额',
+                'quota_period_none': '无配额',
+                'quota_period_day': '每天',
+                'quota_period_month': '每月',
+                'quota_tokens_placeholder': 'Tokens 上限',
+                'quota_requests_placeholder': '请求数上限',
+                'budget_usd_placeholder': '预算 (USD)',
+                'quota_hint': '超出配额后该 Key 将被拒绝，直到下一个周期开始（UTC）',
+                'quota_resets_at': '重置于',
+                
+                // 模型访问
+                'table_allowed_models': '允许的模型',
+                'allowed_models': '允许的模型',
+                'allowed_models_hint': '每行一个模型名或别名，可用 * 通配；留空表示允许所有模型',
+                'all_models': '全部',
+                'model_aliases': '模型别名',
+                'table_alias': '别名',
+                'table_target_model': '目标模型',
+                'alias_placeholder': '别名，例如 gpt-4o',
+                'alias_model_placeholder': 'Gemini 模型，例如 gemini-2.5-pro',
+                'save_alias': '保存别名',
+                'model_alias_saved': '模型别名已保存',
+                'model_alias_save_failed': '保存模型别名失败',
+                'model_alias_deleted': '模型别名已删除',
+                'model_alias_delete_failed': '删除模型别名失败',
+                'delete_model_alias_confirm': '确定要删除这个模型别名吗？',
+                'model_prices': '模型价格',
+                'table_model': '模型',
+                'table_input_price': '输入 / 百万 Tokens',
+                'table_cached_price': '缓存输入 / 百万 Tokens',
+                'table_output_price': '输出 / 百万 Tokens',
+                'price_model_placeholder': '模型或前缀，例如 gemini-2.5-pro',
+                'price_input_placeholder': '输入价格',
+                'price_cached_placeholder': '缓存输入价格（可选）',
+                'price_output_placeholder': '输出价格',
+                'same_as_input': '同输入价格',
+                'save_price': '保存价格',
+                'model_price_saved': '模型价格已保存，将用于之后的请求',
+                'model_price_save_failed': '保存模型价格失败',
+                'model_price_deleted': '模型价格已删除',
+                'model_price_delete_failed': '删除模型价格失败',
+                'delete_model_price_confirm': '确定要删除这个模型价格吗？之后该模型的请求将不计费用。',
+                
+                // 上游 Keys
+                'upstream_keys': '上游 Gemini Keys',
+                'key_healthy': '正常',
+                'key_cooling_down': '冷却中',
+                'key_disabled': '已停用',
+                'add_upstream_key': '+ 添加上游 Key',
+                'add_upstream_key_title': '添加上游 Gemini Key',
+                'edit_upstream_key': '编辑上游 Gemini Key',
+                'upstream_key_value': 'Gemini API Key',
+                'upstream_key_label': '标签',
+                'upstream_key_notes': '备注',
+                'missing_upstream_key': '请输入 Gemini API Key',
+                'upstream_key_created': '上游 Key 添加成功',
+                'upstream_key_creation_failed': '添加上游 Key 失败',
+                'upstream_key_updated': '上游 Key 更新成功',
+                'upstream_key_update_failed': '更新上游 Key 失败',
+                'upstream_key_deleted': '上游 Key 删除成功',
+                'upstream_key_delete_failed': '删除上游 Key 失败',
+                'delete_upstream_key_confirm': '确定要删除这个上游 Key 吗？删除后将立即停止使用。',
+                
+                // 管理员账号
+                'admin_users': '管理员账号',
+                'invite_username_placeholder': '用户名',
+                'invite_user': '邀请管理员',
+                'table_username': '用户名',
+                'table_role': '角色',
+                'table_last_login': '最近登录',
+                'role_viewer': '只读',
+                'role_operator': '运维',
+                'role_owner': '所有者',
+                'never': '从未',
+                'reset_password': '重置密码',
+                'change_password': '修改密码',
+                'current_password': '当前密码',
+                'new_password': '新密码',
+                'confirm_password': '确认新密码',
+                'password_mismatch': '两次输入的新密码不一致',
+                'password_changed': '密码已修改',
+                'password_change_failed': '修改密码失败',
+                'user_invited': '已邀请 {username}，临时密码（只显示一次）：',
+                'user_invite_failed': '邀请管理员失败',
+                'user_updated': '管理员账号已更新',
+                'user_update_failed': '更新管理员账号失败',
+                'reset_password_confirm': '确定要重置该管理员的密码吗？旧密码将立即失效。',
+                'password_reset_done': '密码已重置，临时密码（只显示一次）：',
+                'password_reset_failed': '重置密码失败',
+                'table_sessions': '活跃会话',
+                'revoke_sessions': '撤销会话',
+                'revoke_sessions_confirm': '确定要让该管理员在所有设备上退出登录吗？',
+                'sessions_revoked': '会话已撤销',
+                'sessions_revoke_failed': '撤销会话失败',
+                'logout_all': '退出所有会话',
+                'logout_all_confirm': '确定要在所有设备上退出登录吗？',
+                
+                // 两步验证
+                'two_factor': '两步验证',
+                'totp_code': '验证码',
+                'totp_code_placeholder': '输入验证器中的 6 位验证码或恢复码',
+                'missing_totp_code': '请输入验证码',
+                'totp_expired': '验证已超时，请重新登录',
+                'totp_intro': '启用后，登录时除密码外还需要输入验证器应用（如 Google Authenticator）中的验证码。',
+                'totp_start_setup': '开始设置',
+                'totp_scan_hint': '用验证器应用扫描二维码，或手动输入密钥，然后输入显示的验证码。',
+                'totp_secret': '密钥',
+                'totp_enable': '启用两步验证',
+                'totp_enabled': '已启用',
+                'totp_off': '未启用',
+                'totp_disable': '停用两步验证',
+                'totp_regenerate_codes': '重新生成恢复码',
+                'totp_recovery_hint': '请妥善保存以下恢复码。每个恢复码只能使用一次，丢失验证器时可用来登录。它们只显示这一次。',
+                'totp_enabled_done': '两步验证已启用',
+                'totp_disabled_done': '两步验证已停用',
+                'totp_reset': '重置两步验证',
+                'totp_reset_confirm': '确定要停用该管理员的两步验证吗？其验证器和恢复码将失效。',
+                'totp_reset_done': '两步验证已重置',
+                'missing_password': '请输入当前密码',
+                
+                // 登录锁定
+                'lockouts': '登录锁定',
+                'refresh': '刷新',
+                'table_lockout_subject': '对象',
+                'table_failures': '失败次数',
+                'table_lockout_count': '锁定次数',
+                'table_locked_until': '锁定至',
+                'lockout_kind_ip': 'IP',
+                'lockout_kind_username': '用户名',
+                'no_lockouts': '没有失败的登录尝试',
+                'unlock': '解除锁定',
+                'lockout_cleared': '已解除锁定',
+                'too_many_attempts': '失败次数过多，请 {minutes} 分钟后再试',
+                'usage_analytics': '用量分析',
+                'usage_range_24h': '最近 24 小时',
+                'usage_range_7d': '最近 7 天',
+                'usage_range_30d': '最近 30 天',
+                'usage_range_12m': '最近 12 个月',
+                'usage_all_keys': '全部 API Keys',
+                'usage_requests_chart': '请求数（UTC）',
+                'usage_tokens_chart': 'Token 数（UTC）',
+                'usage_top_keys': 'Token 用量最多的 Keys',
+                'usage_top_models': 'Token 用量最多的模型',
+                'usage_succeeded': '成功',
+                'usage_failed': '失败',
+                'usage_input_tokens': '输入 Tokens',
+                'usage_output_tokens': '输出 Tokens',
+                'usage_others': '其他',
+                'usage_no_data': '该时间段内没有请求',
+                'table_tokens': 'Tokens',
+                'table_cost': '费用',
+                'month_cost': '本月费用（估算）',
+                'total_cost': '总费用（估算）',
+                'export_spend_csv': '导出费用 CSV',
+                'export_usage_csv': '导出明细 CSV',
+                'export_usage_ndjson': '导出明细 NDJSON',
                 
                 // 错误信息
                 'missing_api_key_name': '请输入 API Key 名称',
@@ -153,6 +329,8 @@ class I18n {
                 'output_tokens': 'Output Tokens',
                 'total_requests': 'Total Requests',
                 'total_tokens': 'Total Tokens',
+                'retried_requests': 'Retried Requests',
+                'failover_saves': 'Failover Saves',
                 
                 // API Key management
                 'api_key_management': 'API Keys Management',
@@ -173,12 +351,11 @@ class I18n {
                 'api_key_update_failed': 'Failed to update API Key',
                 'api_key_delete_failed': 'Failed to delete API Key',
                 'api_key_not_found': 'API Key not found',
-                'new_api_key': 'New API Key',
                 'save_changes': 'Save Changes',
                 'enable': 'Enable',
                 'disable': 'Disable',
-                'show': 'Show',
-                'hide': 'Hide',
+                'new_api_key_once': 'New API Key (shown only this once, copy it now)',
+                'api_key_prefix_hint': 'Only a prefix of the key is stored; the full key is shown once when it is created',
                 
                 // Table headers
                 'table_name': 'Name',
@@ -189,6 +366,181 @@ class I18n {
                 'table_input_tokens': 'Input Tokens',
                 'table_output_tokens': 'Output Tokens',
                 'table_actions': 'Actions',
+                'table_cooldown_until': 'until',
+                'table_label': 'Label',
+                'table_success_count': 'Successes',
+                'table_error_count': 'Errors',
+                'table_last_error': 'Last Error',
+                'table_notes': 'Notes',
+                'table_rate_limits': 'Rate Limits',
+                
+                // Rate limits
+                'rate_limits': 'Rate Limits',
+                'rpm_limit_placeholder': 'Requests / min',
+                'tpm_limit_placeholder': 'Tokens / min',
+                'max_concurrent_placeholder': 'Max concurrent',
+                'rate_limits_hint': 'Leave empty for no limit',
+                'concurrent': 'Concurrent',
+                'unlimited': 'Unlimited',
+                
+                // Quotas
+                'table_quota': 'Quota Usage',
+                'quota': 'Quota',
+                'quota_period_none': 'No quota',
+                'quota_period_day': 'Daily',
+                'quota_period_month': 'Monthly',
+                'quota_tokens_placeholder': 'Max tokens',
+                'quota_requests_placeholder': 'Max requests',
+                'budget_usd_placeholder': 'Budget (USD)',
+                'quota_hint': 'Once over quota the key is rejected until the next period starts (UTC)',
+                'quota_resets_at': 'resets',
+                
+                // Model access
+                'table_allowed_models': 'Allowed Models',
+                'allowed_models': 'Allowed Models',
+                'allowed_models_hint': 'One model name or alias per line, * as wildcard; leave empty to allow all models',
+                'all_models': 'All',
+                'model_aliases': 'Model Aliases',
+                'table_alias': 'Alias',
+                'table_target_model': 'Target Model',
+                'alias_placeholder': 'Alias, e.g. gpt-4o',
+                'alias_model_placeholder': 'Gemini model, e.g. gemini-2.5-pro',
+                'save_alias': 'Save Alias',
+                'model_alias_saved': 'Model alias saved',
+                'model_alias_save_failed': 'Failed to save model alias',
+                'model_alias_deleted': 'Model alias deleted',
+                'model_alias_delete_failed': 'Failed to delete model alias',
+                'delete_model_alias_confirm': 'Are you sure you want to delete this model alias?',
+                'model_prices': 'Model Prices',
+                'table_model': 'Model',
+                'table_input_price': 'Input / 1M Tokens',
+                'table_cached_price': 'Cached Input / 1M Tokens',
+                'table_output_price': 'Output / 1M Tokens',
+                'price_model_placeholder': 'Model or prefix, e.g. gemini-2.5-pro',
+                'price_input_placeholder': 'Input price',
+                'price_cached_placeholder': 'Cached input price (optional)',
+                'price_output_placeholder': 'Output price',
+                'same_as_input': 'Same as input',
+                'save_price': 'Save Price',
+                'model_price_saved': 'Model price saved; it applies to new requests',
+                'model_price_save_failed': 'Failed to save model price',
+                'model_price_deleted': 'Model price deleted',
+                'model_price_delete_failed': 'Failed to delete model price',
+                'delete_model_price_confirm': 'Delete this model price? Requests to the model will no longer be costed.',
+                
+                // Upstream keys
+                'upstream_keys': 'Upstream Gemini Keys',
+                'key_healthy': 'Healthy',
+                'key_cooling_down': 'Cooling Down',
+                'key_disabled': 'Disabled',
+                'add_upstream_key': '+ Add Upstream Key',
+                'add_upstream_key_title': 'Add Upstream Gemini Key',
+                'edit_upstream_key': 'Edit Upstream Gemini Key',
+                'upstream_key_value': 'Gemini API Key',
+                'upstream_key_label': 'Label',
+                'upstream_key_notes': 'Notes',
+                'missing_upstream_key': 'Please enter a Gemini API Key',
+                'upstream_key_created': 'Upstream key added successfully',
+                'upstream_key_creation_failed': 'Failed to add upstream key',
+                'upstream_key_updated': 'Upstream key updated successfully',
+                'upstream_key_update_failed': 'Failed to update upstream key',
+                'upstream_key_deleted': 'Upstream key deleted successfully',
+                'upstream_key_delete_failed': 'Failed to delete upstream key',
+                'delete_upstream_key_confirm': 'Are you sure you want to delete this upstream key? It stops being used immediately.',
+                
+                // Admin users
+                'admin_users': 'Admin Users',
+                'invite_username_placeholder': 'Username',
+                'invite_user': 'Invite Admin',
+                'table_username': 'Username',
+                'table_role': 'Role',
+                'table_last_login': 'Last Login',
+                'role_viewer': 'Viewer',
+                'role_operator': 'Operator',
+                'role_owner': 'Owner',
+                'never': 'Never',
+                'reset_password': 'Reset Password',
+                'change_password': 'Change Password',
+                'current_password': 'Current Password',
+                'new_password': 'New Password',
+                'confirm_password': 'Confirm New Password',
+                'password_mismatch': 'The new passwords do not match',
+                'password_changed': 'Password changed',
+                'password_change_failed': 'Failed to change password',
+                'user_invited': 'Invited {username}. Temporary password (shown only once):',
+                'user_invite_failed': 'Failed to invite admin',
+                'user_updated': 'Admin user updated',
+                'user_update_failed': 'Failed to update admin user',
+                'reset_password_confirm': 'Reset this admin\'s password? The old password stops working immediately.',
+                'password_reset_done': 'Password reset. Temporary password (shown only once):',
+                'password_reset_failed': 'Failed to reset password',
+                'table_sessions': 'Active Sessions',
+                'revoke_sessions': 'Revoke Sessions',
+                'revoke_sessions_confirm': 'Sign this admin out on all devices?',
+                'sessions_revoked': 'Sessions revoked',
+                'sessions_revoke_failed': 'Failed to revoke sessions',
+                'logout_all': 'Sign Out Everywhere',
+                'logout_all_confirm': 'Sign out on all devices?',
+                
+                // Two-factor login
+                'two_factor': 'Two-Factor',
+                'totp_code': 'Verification Code',
+                'totp_code_placeholder': 'Enter the 6-digit code from your authenticator or a recovery code',
+                'missing_totp_code': 'Please enter the verification code',
+                'totp_expired': 'Verification timed out, please log in again',
+                'totp_intro': 'Once enabled, logging in also requires a code from an authenticator app such as Google Authenticator.',
+                'totp_start_setup': 'Start Setup',
+                'totp_scan_hint': 'Scan the QR code with your authenticator app, or enter the secret by hand, then enter the code it shows.',
+                'totp_secret': 'Secret',
+                'totp_enable': 'Enable Two-Factor Login',
+                'totp_enabled': 'Enabled',
+                'totp_off': 'Off',
+                'totp_disable': 'Disable Two-Factor Login',
+                'totp_regenerate_codes': 'New Recovery Codes',
+                'totp_recovery_hint': 'Store these recovery codes somewhere safe. Each one works once and lets you log in without your authenticator. They are shown only this once.',
+                'totp_enabled_done': 'Two-factor login enabled',
+                'totp_disabled_done': 'Two-factor login disabled',
+                'totp_reset': 'Reset Two-Factor',
+                'totp_reset_confirm': 'Turn off two-factor login for this admin? Their authenticator and recovery codes stop working.',
+                'totp_reset_done': 'Two-factor login reset',
+                'missing_password': 'Please enter your current password',
+                
+                // Login lockouts
+                'lockouts': 'Login Lockouts',
+                'refresh': 'Refresh',
+                'table_lockout_subject': 'Subject',
+                'table_failures': 'Failures',
+                'table_lockout_count': 'Lockouts',
+                'table_locked_until': 'Locked Until',
+                'lockout_kind_ip': 'IP',
+                'lockout_kind_username': 'Username',
+                'no_lockouts': 'No failed login attempts',
+                'unlock': 'Unlock',
+                'lockout_cleared': 'Lockout lifted',
+                'too_many_attempts': 'Too many failed attempts, please try again in {minutes} min',
+                'usage_analytics': 'Usage Analytics',
+                'usage_range_24h': 'Last 24 hours',
+                'usage_range_7d': 'Last 7 days',
+                'usage_range_30d': 'Last 30 days',
+                'usage_range_12m': 'Last 12 months',
+                'usage_all_keys': 'All API Keys',
+                'usage_requests_chart': 'Requests (UTC)',
+                'usage_tokens_chart': 'Tokens (UTC)',
+                'usage_top_keys': 'Top Keys by Tokens',
+                'usage_top_models': 'Top Models by Tokens',
+                'usage_succeeded': 'Succeeded',
+                'usage_failed': 'Failed',
+                'usage_input_tokens': 'Input Tokens',
+                'usage_output_tokens': 'Output Tokens',
+                'usage_others': 'Others',
+                'usage_no_data': 'No requests in this period',
+                'table_tokens': 'Tokens',
+                'table_cost': 'Cost',
+                'month_cost': 'Spend This Month (est.)',
+                'total_cost': 'Total Spend (est.)',
+                'export_spend_csv': 'Export Spend CSV',
+                'export_usage_csv': 'Export Logs CSV',
+                'export_usage_ndjson': 'Export Logs NDJSON',
                 
                 // Error messages
                 'missing_api_key_name': 'Please enter API Key name',
@@ -4,6 +4,8 @@ class Login {
         this.form = document.getElementById('loginForm');
         this.usernameInput = document.getElementById('username');
         this.passwordInput = document.getElementById('password');
+        this.totpInput = document.getElementById('totpCode');
+        this.totpToken = null;
         this.loginBtn = document.getElementById('loginBtn');
         this.loginBtnText = document.getElementById('loginBtnText');
         this.loginBtnLoading = document.getElementById('loginBtnLoading');
@@ -53,6 +55,10 @@ class Login {
     async handleLogin(e) {
         e.preventDefault();
         
+        if (this.totpToken) {
+            return this.handleTotp();
+        }
+        
         const username = this.usernameInput.value.trim();
         const password = this.passwordInput.value.trim();
         
@@ -69,23 +75,23 @@ class Login {
                 headers: {
                     'Content-Type': 'application/json',
                 },
+                // 令牌以 HttpOnly Cookie 下发，页面脚本无法读取
                 body: JSON.stringify({
                     username,
-                    password
+                    password,
+                    use_cookies: true
                 })
             });
             
             const data = await response.json();
             
-            if (response.ok) {
-                // 登录成功，保存 token
-                localStorage.setItem('adminToken', data.token);
-                this.showMessage(window.i18n.t('login_success'), 'success');
-                
-                // 延迟跳转，显示成功消息
-                setTimeout(() => {
-                    window.location.href = 'management.html';
-                }, 1000);
+            if (response.ok && data.totp_required) {
+                // 已启用两步验证，需要再输入验证码
+                this.showTotpStep(data.totp_token);
+            } else if (response.ok) {
+                this.handleLoginSuccess();
+            } else if (response.status === 429) {
+                this.showLockedMessage(response);
             } else {
                 this.showMessage(data.error || window.i18n.t('login_failed'), 'error');
             }
@@ -97,6 +103,76 @@ class Login {
         this.setLoading(false);
     }
     
+    showTotpStep(totpToken) {
+        this.totpToken = totpToken;
+        document.getElementById('usernameGroup').style.display = 'none';
+        document.getElementById('passwordGroup').style.display = 'none';
+        document.getElementById('totpGroup').style.display = 'block';
+        this.totpInput.required = true;
+        this.totpInput.focus();
+    }
+    
+    async handleTotp() {
+        const code = this.totpInput.value.trim();
+        if (!code) {
+            this.showMessage(window.i18n.t('missing_totp_code'), 'error');
+            return;
+        }
+        
+        this.setLoading(true);
+        
+        try {
+            const response = await fetch('/admin/api/auth/login/totp', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify({
+                    totp_token: this.totpToken,
+                    code
+                })
+            });
+            
+            if (response.ok) {
+                this.handleLoginSuccess();
+            } else if (response.status === 401) {
+                // 中间令牌已过期，重新输入密码
+                this.showMessage(window.i18n.t('totp_expired'), 'error');
+                setTimeout(() => window.location.reload(), 2000);
+            } else if (response.status === 429) {
+                this.showLockedMessage(response);
+                this.totpInput.value = '';
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('login_failed'), 'error');
+                this.totpInput.value = '';
+            }
+        } catch (error) {
+            console.error('Two-factor login error:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+        
+        this.setLoading(false);
+    }
+    
+    // 失败次数过多被暂时锁定，按 Retry-After 提示等待时间
+    showLockedMessage(response) {
+        const seconds = parseInt(response.headers.get('Retry-After'), 10) || 60;
+        const minutes = Math.ceil(seconds / 60);
+        this.showMessage(window.i18n.t('too_many_attempts').replace('{minutes}', minutes), 'error');
+    }
+    
+    handleLoginSuccess() {
+        // 登录成功，清除旧版本保存在本地的 token
+        localStorage.removeItem('adminToken');
+        this.showMessage(window.i18n.t('login_success'), 'success');
+        
+        // 延迟跳转，显示成功消息
+        setTimeout(() => {
+            window.location.href = 'management.html';
+        }, 1000);
+    }
+    
     setLoading(loading) {
         if (loading) {
             this.loginBtn.disabled = true;
@@ -129,28 +205,14 @@ class Login {
     }
     
     checkAuthStatus() {
-        const token = localStorage.getItem('adminToken');
-        if (token) {
-            // 验证 token 是否有效
-            fetch('/admin/api/auth/verify', {
-                headers: {
-                    'Authorization': `Bearer ${token}`
-                }
-            })
+        // 会话 Cookie 仍然有效时直接跳转到管理页面
+        fetch('/admin/api/auth/verify')
             .then(response => {
                 if (response.ok) {
-                    // Token 有效，直接跳转到管理页面
                     window.location.href = 'management.html';
-                } else {
-                    // Token 无效，清除本地存储
-                    localStorage.removeItem('adminToken');
                 }
             })
-            .catch(() => {
-                // 网络错误，清除本地存储
-                localStorage.removeItem('adminToken');
-            });
-        }
+            .catch(() => {});
     }
 }
 
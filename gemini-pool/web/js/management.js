@@ -3,44 +3,68 @@ class ApiKeyManagement {
     constructor() {
         this.messageContainer = document.getElementById('message-container');
         this.apiKeysTable = document.getElementById('apiKeysTable');
+        this.upstreamKeysTable = document.getElementById('upstreamKeysTable');
         this.createModal = document.getElementById('createModal');
         this.editModal = document.getElementById('editModal');
         this.createForm = document.getElementById('createApiKeyForm');
         this.editForm = document.getElementById('editApiKeyForm');
-        this.toggleKeyBtn = document.getElementById('toggleKeyVisibility');
-        
-        this.token = localStorage.getItem('adminToken');
-        this.currentApiKey = null; // 存储当前编辑的完整API key
+        this.createUpstreamModal = document.getElementById('createUpstreamModal');
+        this.editUpstreamModal = document.getElementById('editUpstreamModal');
+        this.createUpstreamForm = document.getElementById('createUpstreamKeyForm');
+        this.editUpstreamForm = document.getElementById('editUpstreamKeyForm');
+        this.modelAliasesTable = document.getElementById('modelAliasesTable');
+        this.modelAliasForm = document.getElementById('modelAliasForm');
+        this.modelPricesTable = document.getElementById('modelPricesTable');
+        this.modelPriceForm = document.getElementById('modelPriceForm');
+        this.adminUsersSection = document.getElementById('adminUsersSection');
+        this.adminUsersTable = document.getElementById('adminUsersTable');
+        this.inviteUserForm = document.getElementById('inviteUserForm');
+        this.lockoutsSection = document.getElementById('lockoutsSection');
+        this.lockoutsTable = document.getElementById('lockoutsTable');
+        this.usageRange = document.getElementById('usageRange');
+        this.usageKeyFilter = document.getElementById('usageKeyFilter');
+        this.changePasswordModal = document.getElementById('changePasswordModal');
+        this.changePasswordForm = document.getElementById('changePasswordForm');
+        this.totpModal = document.getElementById('totpModal');
+        this.totpSetupForm = document.getElementById('totpSetupForm');
+        this.totpEnabledForm = document.getElementById('totpEnabledForm');
+        this.currentUser = null;
         
         this.init();
     }
     
     init() {
-        // 检查认证状态
-        if (!this.token) {
-            window.location.href = 'login.html';
-            return;
-        }
-        
         // 初始化多语言支持
         window.i18n.init();
         
         // 绑定表单事件
         this.createForm.addEventListener('submit', (e) => this.handleCreateApiKey(e));
         this.editForm.addEventListener('submit', (e) => this.handleEditApiKey(e));
+        this.createUpstreamForm.addEventListener('submit', (e) => this.handleCreateUpstreamKey(e));
+        this.editUpstreamForm.addEventListener('submit', (e) => this.handleEditUpstreamKey(e));
+        this.modelAliasForm.addEventListener('submit', (e) => this.handleSaveModelAlias(e));
+        this.modelPriceForm.addEventListener('submit', (e) => this.handleSaveModelPrice(e));
+        this.inviteUserForm.addEventListener('submit', (e) => this.handleInviteUser(e));
+        this.changePasswordForm.addEventListener('submit', (e) => this.handleChangePassword(e));
+        this.totpSetupForm.addEventListener('submit', (e) => this.handleEnableTotp(e));
+        this.totpEnabledForm.addEventListener('submit', (e) => e.preventDefault());
         
         // 绑定API key显示/隐藏按钮事件
-        if (this.toggleKeyBtn) {
-            this.toggleKeyBtn.addEventListener('click', () => this.toggleApiKeyVisibility());
-        }
         
         // 加载数据
+        this.loadCurrentUser();
         this.loadDashboardData();
+        this.loadUsage();
+        this.loadUpstreamKeys();
+        this.loadModelAliases();
+        this.loadModelPrices();
         this.loadApiKeys();
         
         // 定期刷新数据
         setInterval(() => {
             this.loadDashboardData();
+            this.loadUsage();
+            this.loadUpstreamKeys();
             this.loadApiKeys();
         }, 30000); // 每30秒刷新一次
         
@@ -48,45 +72,751 @@ class ApiKeyManagement {
         window.addEventListener('languageChanged', () => {
             this.updateButtonTexts();
             this.loadApiKeys(); // 重新渲染表格以更新状态文本
+            this.loadUpstreamKeys();
+            this.loadDashboardData();
+            this.loadUsage();
+            this.renderCurrentUser();
+            this.loadAdminUsers();
+            this.loadLockouts();
+        });
+    }
+    
+    // 请求管理 API；访问令牌过期时先用刷新令牌换取新令牌，再重试一次
+    async apiFetch(url, options = {}) {
+        const response = await fetch(url, options);
+        if (response.status !== 401 || !(await this.refreshSession())) {
+            return response;
+        }
+        return fetch(url, options);
+    }
+    
+    // 多个请求同时过期时只刷新一次
+    refreshSession() {
+        if (!this.refreshing) {
+            this.refreshing = fetch('/admin/api/auth/refresh', { method: 'POST' })
+                .then(response => response.ok)
+                .catch(() => false)
+                .finally(() => {
+                    this.refreshing = null;
+                });
+        }
+        return this.refreshing;
+    }
+    
+    async loadCurrentUser() {
+        try {
+            const response = await this.apiFetch('/admin/api/auth/verify');
+            
+            if (response.ok) {
+                this.currentUser = await response.json();
+                this.renderCurrentUser();
+                // 只有所有者可以管理管理员账号
+                if (this.currentUser.role === 'owner') {
+                    this.adminUsersSection.style.display = 'block';
+                    this.lockoutsSection.style.display = 'block';
+                    this.loadAdminUsers();
+                    this.loadLockouts();
+                }
+            } else if (response.status === 401) {
+                this.handleAuthError();
+            }
+        } catch (error) {
+            console.error('Error loading current user:', error);
+        }
+    }
+    
+    renderCurrentUser() {
+        if (!this.currentUser) {
+            return;
+        }
+        document.getElementById('adminUser').textContent = this.currentUser.username;
+        document.getElementById('adminRole').textContent = window.i18n.t(`role_${this.currentUser.role}`);
+    }
+    
+    async loadAdminUsers() {
+        if (!this.currentUser || this.currentUser.role !== 'owner') {
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch('/admin/api/users');
+            
+            if (response.ok) {
+                const data = await response.json();
+                this.renderAdminUsersTable(data.users);
+            } else if (response.status === 401) {
+                this.handleAuthError();
+            }
+        } catch (error) {
+            console.error('Error loading admin users:', error);
+        }
+    }
+    
+    renderAdminUsersTable(users) {
+        this.adminUsersTable.innerHTML = '';
+        
+        users.forEach(user => {
+            const roleOptions = ['viewer', 'operator', 'owner'].map(role => `
+                <option value="${role}" ${role === user.role ? 'selected' : ''}>${window.i18n.t(`role_${role}`)}</option>
+            `).join('');
+            const row = document.createElement('tr');
+            row.innerHTML = `
+                <td>${this.escapeHtml(user.username)}</td>
+                <td>
+                    <select class="form-input" style="padding: 6px 10px;"
+                            onchange="management.updateAdminUser('${user.id}', this.value, ${user.is_active})">
+                        ${roleOptions}
+                    </select>
+                </td>
+                <td>
+                    <span class="status-badge ${user.is_active ? 'status-active' : 'status-inactive'}">
+                        ${user.is_active ? window.i18n.t('active') : window.i18n.t('inactive')}
+                    </span>
+                </td>
+                <td>${window.i18n.formatDate(user.created_at)}</td>
+                <td>${user.last_login_at ? window.i18n.formatDate(user.last_login_at) : window.i18n.t('never')}</td>
+                <td>${user.active_sessions}</td>
+                <td>
+                    <span class="status-badge ${user.totp_enabled ? 'status-active' : 'status-inactive'}">
+                        ${user.totp_enabled ? window.i18n.t('totp_enabled') : window.i18n.t('totp_off')}
+                    </span>
+                </td>
+                <td>
+                    <button class="btn" style="padding: 8px 15px; font-size: 0.8rem; margin-right: 5px;"
+                            onclick="management.updateAdminUser('${user.id}', '${user.role}', ${!user.is_active})">
+                        ${user.is_active ? window.i18n.t('disable') : window.i18n.t('enable')}
+                    </button>
+                    <button class="btn" style="padding: 8px 15px; font-size: 0.8rem; margin-right: 5px;"
+                            onclick="management.revokeAdminSessions('${user.id}')">
+                        ${window.i18n.t('revoke_sessions')}
+                    </button>
+                    ${user.totp_enabled ? `
+                    <button class="btn" style="padding: 8px 15px; font-size: 0.8rem; margin-right: 5px;"
+                            onclick="management.resetAdminTotp('${user.id}')">
+                        ${window.i18n.t('totp_reset')}
+                    </button>` : ''}
+                    <button class="btn btn-danger" style="padding: 8px 15px; font-size: 0.8rem;"
+                            onclick="management.resetAdminPassword('${user.id}')">
+                        ${window.i18n.t('reset_password')}
+                    </button>
+                </td>
+            `;
+            this.adminUsersTable.appendChild(row);
+        });
+    }
+    
+    async loadLockouts() {
+        if (!this.currentUser || this.currentUser.role !== 'owner') {
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch('/admin/api/lockouts');
+            
+            if (response.ok) {
+                const data = await response.json();
+                this.renderLockoutsTable(data.lockouts);
+            } else if (response.status === 401) {
+                this.handleAuthError();
+            }
+        } catch (error) {
+            console.error('Error loading lockouts:', error);
+        }
+    }
+    
+    renderLockoutsTable(lockouts) {
+        this.lockoutsTable.innerHTML = '';
+        
+        if (lockouts.length === 0) {
+            this.lockoutsTable.innerHTML = `
+                <tr><td colspan="5" style="text-align: center; color: var(--text-secondary);">${window.i18n.t('no_lockouts')}</td></tr>
+            `;
+            return;
+        }
+        
+        lockouts.forEach(entry => {
+            const row = document.createElement('tr');
+            row.innerHTML = `
+                <td>${window.i18n.t(`lockout_kind_${entry.kind}`)}: <code>${this.escapeHtml(entry.value)}</code></td>
+                <td>${entry.failures}</td>
+                <td>${entry.lockouts}</td>
+                <td>
+                    ${entry.locked_until
+                        ? `<span class="status-badge status-inactive">${window.i18n.formatDate(entry.locked_until)}</span>`
+                        : '-'}
+                </td>
+                <td>
+                    <button class="btn" style="padding: 8px 15px; font-size: 0.8rem;"
+                            onclick="management.clearLockout('${entry.kind}', '${encodeURIComponent(entry.value)}')">
+                        ${window.i18n.t('unlock')}
+                    </button>
+                </td>
+            `;
+            this.lockoutsTable.appendChild(row);
+        });
+    }
+    
+    async clearLockout(kind, value) {
+        try {
+            const response = await this.apiFetch(`/admin/api/lockouts/${kind}/${value}`, {
+                method: 'DELETE'
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('lockout_cleared'), 'success');
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('operation_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error clearing lockout:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+        this.loadLockouts();
+    }
+    
+    async handleInviteUser(e) {
+        e.preventDefault();
+        
+        const formData = new FormData(this.inviteUserForm);
+        const username = formData.get('username').trim();
+        const role = formData.get('role');
+        
+        try {
+            const response = await this.apiFetch('/admin/api/users', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify({ username, role })
+            });
+            
+            const data = await response.json();
+            if (response.ok) {
+                // 临时密码只显示一次，消息不自动消失
+                const message = window.i18n.t('user_invited').replace('{username}', this.escapeHtml(data.username));
+                this.showMessage(`${message} <code>${this.escapeHtml(data.temporary_password)}</code>`, 'success', 0);
+                this.inviteUserForm.reset();
+                this.loadAdminUsers();
+            } else {
+                this.showMessage(data.error || window.i18n.t('user_invite_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error inviting admin user:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async updateAdminUser(userId, role, isActive) {
+        try {
+            const response = await this.apiFetch(`/admin/api/users/${userId}`, {
+                method: 'PUT',
+                headers: {
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify({ role, is_active: isActive })
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('user_updated'), 'success');
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('user_update_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error updating admin user:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+        // 失败时也重新加载，恢复下拉框的原值
+        this.loadAdminUsers();
+    }
+    
+    async resetAdminPassword(userId) {
+        if (!confirm(window.i18n.t('reset_password_confirm'))) {
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch(`/admin/api/users/${userId}/reset-password`, {
+                method: 'POST'
+            });
+            
+            const data = await response.json();
+            if (response.ok) {
+                this.showMessage(`${window.i18n.t('password_reset_done')} <code>${this.escapeHtml(data.temporary_password)}</code>`, 'success', 0);
+            } else {
+                this.showMessage(data.error || window.i18n.t('password_reset_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error resetting admin password:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async revokeAdminSessions(userId) {
+        if (!confirm(window.i18n.t('revoke_sessions_confirm'))) {
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch(`/admin/api/users/${userId}/revoke-sessions`, {
+                method: 'POST'
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('sessions_revoked'), 'success');
+                // 撤销的可能包括自己的会话
+                if (userId === this.currentUser.id) {
+                    this.handleAuthError();
+                    return;
+                }
+                this.loadAdminUsers();
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('sessions_revoke_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error revoking admin sessions:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async resetAdminTotp(userId) {
+        if (!confirm(window.i18n.t('totp_reset_confirm'))) {
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch(`/admin/api/users/${userId}/totp`, {
+                method: 'DELETE'
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('totp_reset_done'), 'success');
+                this.loadAdminUsers();
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('operation_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error resetting two-factor login:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    showTotpModal() {
+        const enabled = this.currentUser && this.currentUser.totp_enabled;
+        document.getElementById('totpDisabledView').style.display = enabled ? 'none' : 'block';
+        this.totpSetupForm.style.display = 'none';
+        this.totpEnabledForm.style.display = enabled ? 'block' : 'none';
+        document.getElementById('totpRecoveryCodes').style.display = 'none';
+        this.totpSetupForm.reset();
+        this.totpEnabledForm.reset();
+        this.totpModal.style.display = 'block';
+        this.totpModal.classList.add('show');
+    }
+    
+    hideTotpModal() {
+        this.totpModal.style.display = 'none';
+        this.totpModal.classList.remove('show');
+        // 清除页面上的密钥和恢复码
+        document.getElementById('totpQr').innerHTML = '';
+        document.getElementById('totpSecret').value = '';
+        document.getElementById('totpRecoveryCodeList').textContent = '';
+    }
+    
+    async startTotpSetup() {
+        try {
+            const response = await this.apiFetch('/admin/api/auth/totp/setup', { method: 'POST' });
+            const data = await response.json();
+            
+            if (response.ok) {
+                // 二维码 SVG 由服务端生成
+                document.getElementById('totpQr').innerHTML = data.qr_svg;
+                document.getElementById('totpSecret').value = data.secret;
+                document.getElementById('totpDisabledView').style.display = 'none';
+                this.totpSetupForm.style.display = 'block';
+                document.getElementById('totpSetupCode').focus();
+            } else {
+                this.showMessage(data.error || window.i18n.t('operation_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error starting two-factor setup:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async handleEnableTotp(e) {
+        e.preventDefault();
+        
+        const code = document.getElementById('totpSetupCode').value.trim();
+        
+        try {
+            const response = await this.apiFetch('/admin/api/auth/totp/enable', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify({ code })
+            });
+            const data = await response.json();
+            
+            if (response.ok) {
+                this.currentUser.totp_enabled = true;
+                this.totpSetupForm.style.display = 'none';
+                document.getElementById('totpQr').innerHTML = '';
+                this.showRecoveryCodes(data.recovery_codes);
+                this.showMessage(window.i18n.t('totp_enabled_done'), 'success');
+                this.loadAdminUsers();
+            } else {
+                this.showMessage(data.error || window.i18n.t('operation_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error enabling two-factor login:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async regenerateRecoveryCodes() {
+        const password = document.getElementById('totpPassword').value;
+        if (!password) {
+            this.showMessage(window.i18n.t('missing_password'), 'error');
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch('/admin/api/auth/totp/recovery-codes', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify({ password })
+            });
+            const data = await response.json();
+            
+            if (response.ok) {
+                this.totpEnabledForm.reset();
+                this.showRecoveryCodes(data.recovery_codes);
+            } else {
+                this.showMessage(data.error || window.i18n.t('operation_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error regenerating recovery codes:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async disableTotp() {
+        const password = document.getElementById('totpPassword').value;
+        if (!password) {
+            this.showMessage(window.i18n.t('missing_password'), 'error');
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch('/admin/api/auth/totp/disable', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify({ password })
+            });
+            
+            if (response.ok) {
+                this.currentUser.totp_enabled = false;
+                this.hideTotpModal();
+                this.showMessage(window.i18n.t('totp_disabled_done'), 'success');
+                this.loadAdminUsers();
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('operation_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error disabling two-factor login:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    showRecoveryCodes(codes) {
+        document.getElementById('totpRecoveryCodeList').textContent = codes.join('\n');
+        document.getElementById('totpRecoveryCodes').style.display = 'block';
+    }
+    
+    async handleChangePassword(e) {
+        e.preventDefault();
+        
+        const formData = new FormData(this.changePasswordForm);
+        const currentPassword = formData.get('currentPassword');
+        const newPassword = formData.get('newPassword');
+        
+        if (newPassword !== formData.get('confirmPassword')) {
+            this.showMessage(window.i18n.t('password_mismatch'), 'error');
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch('/admin/api/auth/change-password', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify({ current_password: currentPassword, new_password: newPassword })
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('password_changed'), 'success');
+                this.hideChangePasswordModal();
+            } else if (response.status === 401) {
+                this.handleAuthError();
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('password_change_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error changing password:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    showChangePasswordModal() {
+        this.changePasswordForm.reset();
+        this.changePasswordModal.style.display = 'block';
+        this.changePasswordModal.classList.add('show');
+    }
+    
+    hideChangePasswordModal() {
+        this.changePasswordModal.style.display = 'none';
+        this.changePasswordModal.classList.remove('show');
+        this.changePasswordForm.reset();
+    }
+    
+    async loadDashboardData() {
+        try {
+            const response = await this.apiFetch('/admin/api/dashboard');
+            
+            if (response.ok) {
+                const data = await response.json();
+                this.updateDashboard(data);
+            } else if (response.status === 401) {
+                this.handleAuthError();
+            }
+        } catch (error) {
+            console.error('Error loading dashboard data:', error);
+        }
+    }
+    
+    async loadApiKeys() {
+        try {
+            const response = await this.apiFetch('/admin/api/api-keys');
+            
+            if (response.ok) {
+                const data = await response.json();
+                this.renderApiKeysTable(data.api_keys);
+                this.updateUsageKeyFilter(data.api_keys);
+            } else if (response.status === 401) {
+                this.handleAuthError();
+            }
+        } catch (error) {
+            console.error('Error loading API keys:', error);
+            this.showMessage('加载 API Keys 失败', 'error');
+        }
+    }
+    
+    async loadModelAliases() {
+        try {
+            const response = await this.apiFetch('/admin/api/model-aliases');
+            
+            if (response.ok) {
+                const data = await response.json();
+                this.renderModelAliasesTable(data.aliases);
+            } else if (response.status === 401) {
+                this.handleAuthError();
+            }
+        } catch (error) {
+            console.error('Error loading model aliases:', error);
+        }
+    }
+    
+    renderModelAliasesTable(aliases) {
+        this.modelAliasesTable.innerHTML = '';
+        
+        aliases.forEach(alias => {
+            const row = document.createElement('tr');
+            row.innerHTML = `
+                <td>${this.escapeHtml(alias.alias)}</td>
+                <td>${this.escapeHtml(alias.model)}</td>
+                <td>
+                    <button class="btn btn-danger" style="padding: 8px 15px; font-size: 0.8rem;" 
+                            onclick="management.deleteModelAlias('${encodeURIComponent(alias.alias)}')">
+                        ${window.i18n.t('delete')}
+                    </button>
+                </td>
+            `;
+            this.modelAliasesTable.appendChild(row);
+        });
+    }
+    
+    async handleSaveModelAlias(e) {
+        e.preventDefault();
+        
+        const formData = new FormData(this.modelAliasForm);
+        const alias = formData.get('alias').trim();
+        const model = formData.get('model').trim();
+        
+        try {
+            const response = await this.apiFetch(`/admin/api/model-aliases/${encodeURIComponent(alias)}`, {
+                method: 'PUT',
+                headers: {
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify({ model })
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('model_alias_saved'), 'success');
+                this.modelAliasForm.reset();
+                this.loadModelAliases();
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('model_alias_save_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error saving model alias:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async deleteModelAlias(alias) {
+        if (!confirm(window.i18n.t('delete_model_alias_confirm'))) {
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch(`/admin/api/model-aliases/${alias}`, {
+                method: 'DELETE'
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('model_alias_deleted'), 'success');
+                this.loadModelAliases();
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('model_alias_delete_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error deleting model alias:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async loadModelPrices() {
+        try {
+            const response = await this.apiFetch('/admin/api/model-prices');
+            
+            if (response.ok) {
+                const data = await response.json();
+                this.renderModelPricesTable(data.prices);
+            } else if (response.status === 401) {
+                this.handleAuthError();
+            }
+        } catch (error) {
+            console.error('Error loading model prices:', error);
+        }
+    }
+    
+    renderModelPricesTable(prices) {
+        this.modelPricesTable.innerHTML = '';
+        
+        prices.forEach(price => {
+            const cached = price.cached_input_per_million;
+            const row = document.createElement('tr');
+            row.innerHTML = `
+                <td>${this.escapeHtml(price.model)}</td>
+                <td>$${price.input_per_million}</td>
+                <td>${cached === null ? `<span style="color: var(--text-secondary);">${window.i18n.t('same_as_input')}</span>` : `$${cached}`}</td>
+                <td>$${price.output_per_million}</td>
+                <td>
+                    <button class="btn btn-danger" style="padding: 8px 15px; font-size: 0.8rem;" 
+                            onclick="management.deleteModelPrice('${encodeURIComponent(price.model)}')">
+                        ${window.i18n.t('delete')}
+                    </button>
+                </td>
+            `;
+            this.modelPricesTable.appendChild(row);
         });
     }
     
-    async loadDashboardData() {
+    async handleSaveModelPrice(e) {
+        e.preventDefault();
+        
+        const formData = new FormData(this.modelPriceForm);
+        const model = formData.get('model').trim();
+        const cached = formData.get('cached');
+        const body = {
+            input_per_million: parseFloat(formData.get('input')),
+            // 留空表示缓存 Token 按输入价格计费
+            cached_input_per_million: cached === '' ? null : parseFloat(cached),
+            output_per_million: parseFloat(formData.get('output'))
+        };
+        
         try {
-            const response = await fetch('/admin/api/dashboard', {
+            const response = await this.apiFetch(`/admin/api/model-prices/${encodeURIComponent(model)}`, {
+                method: 'PUT',
                 headers: {
-                    'Authorization': `Bearer ${this.token}`
-                }
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify(body)
             });
             
             if (response.ok) {
+                this.showMessage(window.i18n.t('model_price_saved'), 'success');
+                this.modelPriceForm.reset();
+                this.loadModelPrices();
+            } else {
                 const data = await response.json();
-                this.updateDashboard(data);
-            } else if (response.status === 401) {
-                this.handleAuthError();
+                this.showMessage(data.error || window.i18n.t('model_price_save_failed'), 'error');
             }
         } catch (error) {
-            console.error('Error loading dashboard data:', error);
+            console.error('Error saving model price:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
         }
     }
     
-    async loadApiKeys() {
+    async deleteModelPrice(model) {
+        if (!confirm(window.i18n.t('delete_model_price_confirm'))) {
+            return;
+        }
+        
         try {
-            const response = await fetch('/admin/api/api-keys', {
-                headers: {
-                    'Authorization': `Bearer ${this.token}`
-                }
+            const response = await this.apiFetch(`/admin/api/model-prices/${model}`, {
+                method: 'DELETE'
             });
             
             if (response.ok) {
+                this.showMessage(window.i18n.t('model_price_deleted'), 'success');
+                this.loadModelPrices();
+            } else {
                 const data = await response.json();
-                this.renderApiKeysTable(data.api_keys);
+                this.showMessage(data.error || window.i18n.t('model_price_delete_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error deleting model price:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async loadUpstreamKeys() {
+        try {
+            const response = await this.apiFetch('/admin/api/upstream-keys');
+            
+            if (response.ok) {
+                const data = await response.json();
+                this.renderUpstreamKeysTable(data.upstream_keys);
             } else if (response.status === 401) {
                 this.handleAuthError();
             }
         } catch (error) {
-            console.error('Error loading API keys:', error);
-            this.showMessage('加载 API Keys 失败', 'error');
+            console.error('Error loading upstream keys:', error);
         }
     }
     
@@ -95,6 +825,253 @@ class ApiKeyManagement {
         document.getElementById('totalRequests').textContent = window.i18n.formatNumber(data.total_requests || 0);
         document.getElementById('totalTokens').textContent = window.i18n.formatNumber(data.total_tokens || 0);
         document.getElementById('activeKeys').textContent = data.active_keys || 0;
+        document.getElementById('retriedRequests').textContent = window.i18n.formatNumber(data.retried_requests || 0);
+        document.getElementById('failoverSaves').textContent = window.i18n.formatNumber(data.failover_saves || 0);
+        document.getElementById('monthCost').textContent = this.formatCost(data.month_cost_usd || 0);
+        document.getElementById('totalCost').textContent = this.formatCost(data.total_cost_usd || 0);
+    }
+    
+    // 费用为估算值（美元），小额显示更多小数位
+    formatCost(usd) {
+        if (usd > 0 && usd < 0.01) {
+            return `$${usd.toFixed(4)}`;
+        }
+        return `$${usd.toFixed(2)}`;
+    }
+    
+    // 用量分析的查询参数：时间范围决定分组方式，可按 API Key 过滤
+    usageParams() {
+        const ranges = {
+            '24h': { group_by: 'hour' },
+            '7d': { group_by: 'day', days: 7 },
+            '30d': { group_by: 'day' },
+            '12m': { group_by: 'month' }
+        };
+        const range = ranges[this.usageRange.value] || ranges['24h'];
+        const params = new URLSearchParams({ group_by: range.group_by });
+        if (range.days) {
+            // 按 UTC 日期对齐，包含今天
+            const from = new Date(Date.now() - (range.days - 1) * 24 * 60 * 60 * 1000);
+            params.set('from', from.toISOString().substring(0, 10));
+        }
+        if (this.usageKeyFilter.value) {
+            params.set('api_key_id', this.usageKeyFilter.value);
+        }
+        return params;
+    }
+    
+    async loadUsage() {
+        try {
+            const params = this.usageParams();
+            const response = await this.apiFetch(`/admin/api/usage/timeseries?${params}`);
+            
+            if (!response.ok) {
+                if (response.status === 401) {
+                    this.handleAuthError();
+                }
+                return;
+            }
+            const series = await response.json();
+            this.renderUsageCharts(series);
+            
+            // 排行和明细导出使用与图表完全相同的时间范围
+            this.usageSeriesRange = { from: series.from, to: series.to };
+            params.set('from', series.from);
+            params.set('to', series.to);
+            params.set('order_by', 'tokens');
+            params.set('limit', '5');
+            const [keys, models] = await Promise.all(['api_key', 'model'].map(async by => {
+                params.set('by', by);
+                const topResponse = await this.apiFetch(`/admin/api/usage/top?${params}`);
+                return topResponse.ok ? topResponse.json() : null;
+            }));
+            if (keys) {
+                this.renderUsageTop(document.getElementById('usageTopKeysTable'), keys);
+            }
+            if (models) {
+                this.renderUsageTop(document.getElementById('usageTopModelsTable'), models);
+            }
+        } catch (error) {
+            console.error('Error loading usage analytics:', error);
+        }
+    }
+    
+    // 按当前时间范围和过滤条件下载费用明细（按周期、API Key 和模型汇总）
+    exportSpend() {
+        const params = this.usageParams();
+        params.set('format', 'csv');
+        window.location.href = `/admin/api/usage/spend?${params}`;
+    }
+    
+    // 导出所选范围和 API Key 的逐条用量记录（服务端流式输出）
+    exportUsage(format) {
+        const params = this.usageParams();
+        params.delete('group_by');
+        if (this.usageSeriesRange) {
+            params.set('from', this.usageSeriesRange.from);
+            params.set('to', this.usageSeriesRange.to);
+        }
+        params.set('format', format);
+        window.location.href = `/admin/api/usage/export?${params}`;
+    }
+    
+    // API Key 列表变化时更新过滤下拉框，保留当前选择
+    updateUsageKeyFilter(apiKeys) {
+        const selected = this.usageKeyFilter.value;
+        this.usageKeyFilter.innerHTML = `<option value="" data-i18n="usage_all_keys">${window.i18n.t('usage_all_keys')}</option>`;
+        apiKeys.forEach(key => {
+            const option = document.createElement('option');
+            option.value = key.id;
+            option.textContent = key.key_name;
+            this.usageKeyFilter.appendChild(option);
+        });
+        this.usageKeyFilter.value = apiKeys.some(key => key.id === selected) ? selected : '';
+    }
+    
+    renderUsageCharts(series) {
+        const points = series.points.map(point => ({
+            ...point,
+            succeeded: point.requests - point.failed_requests
+        }));
+        this.renderUsageChart(document.getElementById('usageRequestsChart'), points, series.group_by, [
+            { field: 'succeeded', color: 'var(--primary-color)', label: window.i18n.t('usage_succeeded') },
+            { field: 'failed_requests', color: 'var(--error-color)', label: window.i18n.t('usage_failed') }
+        ]);
+        this.renderUsageChart(document.getElementById('usageTokensChart'), points, series.group_by, [
+            { field: 'input_tokens', color: 'var(--secondary-color)', label: window.i18n.t('usage_input_tokens') },
+            { field: 'output_tokens', color: 'var(--accent-color)', label: window.i18n.t('usage_output_tokens') }
+        ]);
+    }
+    
+    // 绘制堆叠柱状图，series 中的字段自下而上叠加
+    renderUsageChart(container, points, groupBy, series) {
+        const width = 600;
+        const height = 200;
+        const left = 45;
+        const bottom = 20;
+        const top = 10;
+        const total = point => series.reduce((sum, s) => sum + point[s.field], 0);
+        const max = Math.max(1, ...points.map(total));
+        const scale = (height - top - bottom) / max;
+        const slot = (width - left) / Math.max(points.length, 1);
+        const barWidth = Math.max(1, slot * 0.8);
+        // 横轴最多显示 8 个标签，避免重叠
+        const labelEvery = Math.ceil(points.length / 8);
+        
+        let bars = '';
+        points.forEach((point, index) => {
+            const x = left + index * slot + (slot - barWidth) / 2;
+            const label = this.formatUsagePeriod(point.period, groupBy);
+            const tooltip = [label, ...series.map(s => `${s.label}: ${point[s.field]}`)].join('\n');
+            let y = height - bottom;
+            series.forEach(s => {
+                const barHeight = point[s.field] * scale;
+                y -= barHeight;
+                bars += `<rect x="${x}" y="${y}" width="${barWidth}" height="${barHeight}" style="fill: ${s.color};"><title>${this.escapeHtml(tooltip)}</title></rect>`;
+            });
+            if (index % labelEvery === 0) {
+                bars += `<text x="${x + barWidth / 2}" y="${height - 5}" text-anchor="middle">${label}</text>`;
+            }
+        });
+        
+        const legend = series.map(s => `<span style="--legend-color: ${s.color};">${s.label}</span>`).join('');
+        container.innerHTML = `
+            <svg viewBox="0 0 ${width} ${height}">
+                <line x1="${left}" y1="${height - bottom}" x2="${width}" y2="${height - bottom}" style="stroke: var(--text-secondary);"></line>
+                <text x="${left - 5}" y="${top + 4}" text-anchor="end">${window.i18n.formatNumber(max)}</text>
+                <text x="${left - 5}" y="${height - bottom}" text-anchor="end">0</text>
+                ${bars}
+            </svg>
+            <div class="usage-legend">${legend}</div>
+        `;
+    }
+    
+    // 周期为 UTC 时间 "YYYY-MM-DD HH:MM:SS"，按分组截取显示
+    formatUsagePeriod(period, groupBy) {
+        if (groupBy === 'hour') {
+            return period.substring(11, 16);
+        }
+        if (groupBy === 'day') {
+            return period.substring(5, 10);
+        }
+        return period.substring(0, 7);
+    }
+    
+    renderUsageTop(table, data) {
+        table.innerHTML = '';
+        
+        if (data.items.length === 0) {
+            table.innerHTML = `
+                <tr><td colspan="4" style="text-align: center; color: var(--text-secondary);">${window.i18n.t('usage_no_data')}</td></tr>
+            `;
+            return;
+        }
+        
+        const rows = data.items.map(item => ({ label: item.name || item.key || '-', usage: item }));
+        if (data.others.requests > 0) {
+            rows.push({ label: window.i18n.t('usage_others'), usage: data.others });
+        }
+        rows.forEach(({ label, usage }) => {
+            const row = document.createElement('tr');
+            row.innerHTML = `
+                <td>${this.escapeHtml(label)}</td>
+                <td>${window.i18n.formatNumber(usage.requests)}</td>
+                <td>${window.i18n.formatNumber(usage.input_tokens + usage.output_tokens)}</td>
+                <td>${this.formatCost(usage.cost_usd)}</td>
+            `;
+            table.appendChild(row);
+        });
+    }
+    
+    renderUpstreamKeysTable(upstreamKeys) {
+        this.upstreamKeysTable.innerHTML = '';
+        
+        const badges = {
+            healthy: ['status-active', 'key_healthy'],
+            cooling_down: ['status-cooling', 'key_cooling_down'],
+            disabled: ['status-inactive', 'key_disabled']
+        };
+        
+        upstreamKeys.forEach(key => {
+            const [badgeClass, label] = key.is_active
+                ? (badges[key.state] || badges.healthy)
+                : ['status-inactive', 'inactive'];
+            // 冷却或停用时，在状态下方显示原因
+            let detail = key.reason ? this.escapeHtml(key.reason) : '';
+            if (key.until) {
+                detail += ` (${window.i18n.t('table_cooldown_until')} ${window.i18n.formatDate(key.until)})`;
+            }
+            const lastError = key.last_error
+                ? `<span title="${this.escapeHtml(key.last_error)}">${this.escapeHtml(key.last_error.substring(0, 60))}</span><br>
+                   <small style="color: var(--text-secondary);">${window.i18n.formatDate(key.last_error_at)}</small>`
+                : '-';
+            const row = document.createElement('tr');
+            row.innerHTML = `
+                <td>${this.escapeHtml(key.label) || '-'}</td>
+                <td><span class="api-key-display">${this.escapeHtml(key.api_key)}</span></td>
+                <td>
+                    <span class="status-badge ${badgeClass}">
+                        ${window.i18n.t(label)}
+                    </span>
+                    ${detail ? `<br><small style="color: var(--text-secondary);">${detail}</small>` : ''}
+                </td>
+                <td>${window.i18n.formatNumber(key.success_count)}</td>
+                <td>${window.i18n.formatNumber(key.error_count)}</td>
+                <td>${lastError}</td>
+                <td>${this.escapeHtml(key.notes) || '-'}</td>
+                <td>
+                    <button class="btn" style="margin-right: 5px; padding: 8px 15px; font-size: 0.8rem;" 
+                            onclick="management.showEditUpstreamModal('${key.id}')">
+                        ${window.i18n.t('edit')}
+                    </button>
+                    <button class="btn btn-danger" style="padding: 8px 15px; font-size: 0.8rem;" 
+                            onclick="management.deleteUpstreamKey('${key.id}')">
+                        ${window.i18n.t('delete')}
+                    </button>
+                </td>
+            `;
+            this.upstreamKeysTable.appendChild(row);
+        });
     }
     
     renderApiKeysTable(apiKeys) {
@@ -106,7 +1083,7 @@ class ApiKeyManagement {
                 <td>${this.escapeHtml(key.key_name)}</td>
                 <td>
                     <span class="api-key-display">
-                        ${this.maskApiKey(key.api_key)}
+                        ${this.formatKeyPrefix(key.key_prefix)}
                     </span>
                 </td>
                 <td>
@@ -118,6 +1095,9 @@ class ApiKeyManagement {
                 <td>${window.i18n.formatNumber(key.total_requests)}</td>
                 <td>${window.i18n.formatNumber(key.total_input_tokens)}</td>
                 <td>${window.i18n.formatNumber(key.total_output_tokens)}</td>
+                <td>${this.formatRateLimits(key)}</td>
+                <td>${this.formatQuotaUsage(key)}</td>
+                <td>${key.allowed_models.length ? key.allowed_models.map(m => this.escapeHtml(m)).join('<br>') : window.i18n.t('all_models')}</td>
                 <td>
                     <button class="btn" style="margin-right: 5px; padding: 8px 15px; font-size: 0.8rem;" 
                             onclick="management.showEditModal('${key.id}')">
@@ -134,6 +1114,142 @@ class ApiKeyManagement {
         });
     }
     
+    async handleCreateUpstreamKey(e) {
+        e.preventDefault();
+        
+        const formData = new FormData(this.createUpstreamForm);
+        const apiKey = formData.get('apiKey').trim();
+        
+        if (!apiKey) {
+            this.showMessage(window.i18n.t('missing_upstream_key'), 'error');
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch('/admin/api/upstream-keys', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify({
+                    api_key: apiKey,
+                    label: formData.get('label').trim(),
+                    notes: formData.get('notes').trim()
+                })
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('upstream_key_created'), 'success');
+                this.hideCreateUpstreamModal();
+                this.loadUpstreamKeys();
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('upstream_key_creation_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error creating upstream key:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async handleEditUpstreamKey(e) {
+        e.preventDefault();
+        
+        const formData = new FormData(this.editUpstreamForm);
+        const keyId = formData.get('keyId');
+        
+        try {
+            const response = await this.apiFetch(`/admin/api/upstream-keys/${keyId}`, {
+                method: 'PUT',
+                headers: {
+                    'Content-Type': 'application/json'
+                },
+                body: JSON.stringify({
+                    label: formData.get('label').trim(),
+                    notes: formData.get('notes').trim(),
+                    is_active: formData.get('isActive') === 'true'
+                })
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('upstream_key_updated'), 'success');
+                this.hideEditUpstreamModal();
+                this.loadUpstreamKeys();
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('upstream_key_update_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error updating upstream key:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async deleteUpstreamKey(keyId) {
+        if (!confirm(window.i18n.t('delete_upstream_key_confirm'))) {
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch(`/admin/api/upstream-keys/${keyId}`, {
+                method: 'DELETE'
+            });
+            
+            if (response.ok) {
+                this.showMessage(window.i18n.t('upstream_key_deleted'), 'success');
+                this.loadUpstreamKeys();
+            } else {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('upstream_key_delete_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error deleting upstream key:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    async showEditUpstreamModal(keyId) {
+        try {
+            const response = await this.apiFetch(`/admin/api/upstream-keys/${keyId}`);
+            
+            if (response.ok) {
+                const keyData = await response.json();
+                
+                document.getElementById('editUpstreamKeyId').value = keyData.id;
+                document.getElementById('editUpstreamKeyValue').value = keyData.api_key;
+                document.getElementById('editUpstreamKeyLabel').value = keyData.label;
+                document.getElementById('editUpstreamKeyNotes').value = keyData.notes;
+                document.getElementById(keyData.is_active ? 'editUpstreamActiveTrue' : 'editUpstreamActiveFalse').checked = true;
+                
+                this.editUpstreamModal.style.display = 'block';
+                this.editUpstreamModal.classList.add('show');
+            } else {
+                this.showMessage(window.i18n.t('load_failed'), 'error');
+            }
+        } catch (error) {
+            console.error('Error loading upstream key details:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+        }
+    }
+    
+    showCreateUpstreamModal() {
+        this.createUpstreamForm.reset();
+        this.createUpstreamModal.style.display = 'block';
+        this.createUpstreamModal.classList.add('show');
+    }
+    
+    hideCreateUpstreamModal() {
+        this.createUpstreamModal.style.display = 'none';
+        this.createUpstreamModal.classList.remove('show');
+        this.createUpstreamForm.reset();
+    }
+    
+    hideEditUpstreamModal() {
+        this.editUpstreamModal.style.display = 'none';
+        this.editUpstreamModal.classList.remove('show');
+        this.editUpstreamForm.reset();
+    }
+    
     async handleCreateApiKey(e) {
         e.preventDefault();
         
@@ -149,10 +1265,9 @@ class ApiKeyManagement {
         this.setCreateLoading(true);
         
         try {
-            const response = await fetch('/admin/api/api-keys', {
+            const response = await this.apiFetch('/admin/api/api-keys', {
                 method: 'POST',
                 headers: {
-                    'Authorization': `Bearer ${this.token}`,
                     'Content-Type': 'application/json'
                 },
                 body: JSON.stringify({
@@ -169,9 +1284,9 @@ class ApiKeyManagement {
                 this.loadApiKeys();
                 this.loadDashboardData();
                 
-                // 如果是自动生成的 key，显示完整的 key
+                // 完整的 key 只在创建时返回一次
                 if (data.api_key) {
-                    this.showMessage(`${window.i18n.t('new_api_key')}: ${data.api_key}`, 'warning');
+                    this.showMessage(`${window.i18n.t('new_api_key_once')}: ${this.escapeHtml(data.api_key)}`, 'warning', 0);
                 }
             } else {
                 this.showMessage(data.error || window.i18n.t('api_key_creation_failed'), 'error');
@@ -191,19 +1306,37 @@ class ApiKeyManagement {
         const keyId = formData.get('keyId');
         const keyName = formData.get('keyName').trim();
         const isActive = formData.get('isActive') === 'true';
+        // 空值表示不限制
+        const limit = (name) => {
+            const value = formData.get(name).trim();
+            return value ? parseInt(value, 10) : null;
+        };
+        const quotaPeriod = formData.get('quotaPeriod');
+        const allowedModels = formData.get('allowedModels')
+            .split(/[\n,]/)
+            .map(m => m.trim())
+            .filter(m => m);
+        const budget = formData.get('budgetUsd').trim();
         
         this.setEditLoading(true);
         
         try {
-            const response = await fetch(`/admin/api/api-keys/${keyId}`, {
+            const response = await this.apiFetch(`/admin/api/api-keys/${keyId}`, {
                 method: 'PUT',
                 headers: {
-                    'Authorization': `Bearer ${this.token}`,
                     'Content-Type': 'application/json'
                 },
                 body: JSON.stringify({
                     key_name: keyName,
-                    is_active: isActive
+                    is_active: isActive,
+                    rpm_limit: limit('rpmLimit'),
+                    tpm_limit: limit('tpmLimit'),
+                    max_concurrent: limit('maxConcurrent'),
+                    quota_period: quotaPeriod || null,
+                    quota_tokens: quotaPeriod ? limit('quotaTokens') : null,
+                    quota_requests: quotaPeriod ? limit('quotaRequests') : null,
+                    budget_usd: quotaPeriod && budget ? parseFloat(budget) : null,
+                    allowed_models: allowedModels
                 })
             });
             
@@ -230,11 +1363,8 @@ class ApiKeyManagement {
         }
         
         try {
-            const response = await fetch(`/admin/api/api-keys/${keyId}`, {
-                method: 'DELETE',
-                headers: {
-                    'Authorization': `Bearer ${this.token}`
-                }
+            const response = await this.apiFetch(`/admin/api/api-keys/${keyId}`, {
+                method: 'DELETE'
             });
             
             if (response.ok) {
@@ -253,11 +1383,7 @@ class ApiKeyManagement {
     
     async showEditModal(keyId) {
         try {
-            const response = await fetch(`/admin/api/api-keys/${keyId}`, {
-                headers: {
-                    'Authorization': `Bearer ${this.token}`
-                }
-            });
+            const response = await this.apiFetch(`/admin/api/api-keys/${keyId}`);
             
             if (response.ok) {
                 const keyData = await response.json();
@@ -265,9 +1391,16 @@ class ApiKeyManagement {
                 document.getElementById('editKeyId').value = keyData.id;
                 document.getElementById('editKeyName').value = keyData.key_name;
                 
-                // 存储完整的API key并初始显示为掩码
-                this.currentApiKey = keyData.api_key;
-                this.updateApiKeyDisplay(false); // 初始状态为隐藏
+                document.getElementById('editKeyValue').value = `${keyData.key_prefix}…`;
+                
+                document.getElementById('editRpmLimit').value = keyData.rpm_limit ?? '';
+                document.getElementById('editTpmLimit').value = keyData.tpm_limit ?? '';
+                document.getElementById('editMaxConcurrent').value = keyData.max_concurrent ?? '';
+                document.getElementById('editQuotaPeriod').value = keyData.quota_period ?? '';
+                document.getElementById('editQuotaTokens').value = keyData.quota_tokens ?? '';
+                document.getElementById('editQuotaRequests').value = keyData.quota_requests ?? '';
+                document.getElementById('editBudgetUsd').value = keyData.budget_usd ?? '';
+                document.getElementById('editAllowedModels').value = (keyData.allowed_models || []).join('\n');
                 
                 if (keyData.is_active) {
                     document.getElementById('editActiveTrue').checked = true;
@@ -305,10 +1438,7 @@ class ApiKeyManagement {
         // 清除单选按钮的选中状态
         document.getElementById('editActiveTrue').checked = false;
         document.getElementById('editActiveFalse').checked = false;
-        // 清除API key相关状态
-        this.currentApiKey = null;
         document.getElementById('editKeyValue').value = '';
-        this.updateToggleButtonText(false);
     }
     
     setCreateLoading(loading) {
@@ -337,20 +1467,52 @@ class ApiKeyManagement {
         }
     }
     
-    showMessage(message, type) {
+    showMessage(message, type, duration = 5000) {
         this.messageContainer.innerHTML = `
             <div class="message message-${type}">
                 ${message}
             </div>
         `;
         
-        setTimeout(() => {
-            this.messageContainer.innerHTML = '';
-        }, 5000);
+        // duration 为 0 时消息一直保留，直到被下一条消息替换
+        clearTimeout(this.messageTimer);
+        if (duration > 0) {
+            this.messageTimer = setTimeout(() => {
+                this.messageContainer.innerHTML = '';
+            }, duration);
+        }
     }
     
     handleAuthError() {
-        localStorage.removeItem('adminToken');
+        window.location.href = 'login.html';
+    }
+    
+    async logout() {
+        try {
+            await fetch('/admin/api/auth/logout', { method: 'POST' });
+        } catch (error) {
+            console.error('Error logging out:', error);
+        }
+        window.location.href = 'login.html';
+    }
+    
+    async logoutAll() {
+        if (!confirm(window.i18n.t('logout_all_confirm'))) {
+            return;
+        }
+        
+        try {
+            const response = await this.apiFetch('/admin/api/auth/logout-all', { method: 'POST' });
+            if (!response.ok && response.status !== 401) {
+                const data = await response.json();
+                this.showMessage(data.error || window.i18n.t('operation_failed'), 'error');
+                return;
+            }
+        } catch (error) {
+            console.error('Error signing out all sessions:', error);
+            this.showMessage(window.i18n.t('network_error'), 'error');
+            return;
+        }
         window.location.href = 'login.html';
     }
     
@@ -378,43 +1540,49 @@ class ApiKeyManagement {
     }
     
     
-    maskApiKey(apiKey) {
-        if (apiKey.length <= 8) return apiKey;
-        return apiKey.substring(0, 4) + '****' + apiKey.substring(apiKey.length - 4);
-    }
-    
-    escapeHtml(text) {
-        const div = document.createElement('div');
-        div.textContent = text;
-        return div.innerHTML;
+    formatRateLimits(key) {
+        const limits = [
+            [key.rpm_limit, 'RPM'],
+            [key.tpm_limit, 'TPM'],
+            [key.max_concurrent, window.i18n.t('concurrent')]
+        ].filter(([value]) => value != null);
+        
+        if (limits.length === 0) {
+            return window.i18n.t('unlimited');
+        }
+        return limits.map(([value, label]) => `${label}: ${window.i18n.formatNumber(value)}`).join('<br>');
     }
     
-    toggleApiKeyVisibility() {
-        const keyInput = document.getElementById('editKeyValue');
-        const isCurrentlyMasked = keyInput.value.includes('****');
-        this.updateApiKeyDisplay(!isCurrentlyMasked);
+    formatQuotaUsage(key) {
+        const usage = key.quota_usage;
+        if (!usage) {
+            return window.i18n.t('unlimited');
+        }
+        
+        const lines = [];
+        const addLine = (label, used, limit, format) => {
+            if (limit == null) return;
+            const percent = Math.min(100, Math.round(used / limit * 100));
+            const cls = percent >= 100 ? 'status-inactive' : (percent >= 80 ? 'status-cooling' : 'status-active');
+            lines.push(`${label}: ${format(used)} / ${format(limit)} <span class="status-badge ${cls}">${percent}%</span>`);
+        };
+        addLine('Tokens', usage.tokens, key.quota_tokens, (v) => window.i18n.formatNumber(v));
+        addLine(window.i18n.t('requests'), usage.requests, key.quota_requests, (v) => window.i18n.formatNumber(v));
+        addLine('USD', usage.cost_usd, key.budget_usd, (v) => '$' + v.toFixed(2));
+        
+        const period = window.i18n.t(`quota_period_${key.quota_period}`);
+        lines.push(`<small style="color: var(--text-secondary);">${period} · ${window.i18n.t('quota_resets_at')} ${window.i18n.formatDate(usage.resets_at)}</small>`);
+        return lines.join('<br>');
     }
     
-    updateApiKeyDisplay(showFull) {
-        const keyInput = document.getElementById('editKeyValue');
-        if (showFull && this.currentApiKey) {
-            keyInput.value = this.currentApiKey;
-        } else if (this.currentApiKey) {
-            keyInput.value = this.maskApiKey(this.currentApiKey);
-        }
-        this.updateToggleButtonText(showFull);
+    formatKeyPrefix(prefix) {
+        return this.escapeHtml(prefix) + '…';
     }
     
-    updateToggleButtonText(isShowing) {
-        const toggleBtn = document.getElementById('toggleKeyVisibility');
-        const btnText = toggleBtn.querySelector('span');
-        if (isShowing) {
-            btnText.textContent = window.i18n.t('hide') || '隐藏';
-            btnText.setAttribute('data-i18n', 'hide');
-        } else {
-            btnText.textContent = window.i18n.t('show') || '显示';
-            btnText.setAttribute('data-i18n', 'show');
-        }
+    escapeHtml(text) {
+        const div = document.createElement('div');
+        div.textContent = text;
+        return div.innerHTML;
     }
 }
 
@@ -431,9 +1599,40 @@ function hideEditModal() {
     management.hideEditModal();
 }
 
+function showCreateUpstreamModal() {
+    management.showCreateUpstreamModal();
+}
+
+function hideCreateUpstreamModal() {
+    management.hideCreateUpstreamModal();
+}
+
+function hideEditUpstreamModal() {
+    management.hideEditUpstreamModal();
+}
+
+function showChangePasswordModal() {
+    management.showChangePasswordModal();
+}
+
+function hideChangePasswordModal() {
+    management.hideChangePasswordModal();
+}
+
+function showTotpModal() {
+    management.showTotpModal();
+}
+
+function hideTotpModal() {
+    management.hideTotpModal();
+}
+
 function logout() {
-    localStorage.removeItem('adminToken');
-    window.location.href = 'login.html';
+    management.logout();
+}
+
+function logoutAll() {
+    management.logoutAll();
 }
 
 // 页面加载完成后初始化管理功能